@@ -1,34 +1,185 @@
-use std::{fs, thread};
-use std::borrow::BorrowMut;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::ops::Add;
-use std::process::id;
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use eframe::emath::{Align2, Vec2};
-use eframe::epaint::{Color32, Rgba};
-use egui::{ColorImage, Direction, DroppedFile, epaint, FontId, Id, Key, LayerId, Layout, MultiTouchInfo, Order, PointerState, Pos2, pos2, Rect, Sense, Shape, Style, TextStyle, Visuals, Widget};
-use egui::Key::{N, S};
-use rand::{random, Rng, RngCore};
+use eframe::epaint::Color32;
+use egui::{DroppedFile, Event, FontId, Key, Order, PointerState, Pos2, Rect, Widget};
+use egui_extras::RetainedImage;
+use image::ImageFormat;
 use uuid::Uuid;
 
-use crate::demo::{Block, BlockPosition, BlockType};
-use crate::persistor::{Persistor, SavedBlock};
-use crate::state::BoardState;
-use crate::view::ViewState;
+use crate::demo::{arrow_shapes, distance_to_path, path_midpoint, smooth, stroke_shape, text_color_on, Block, BlockType, ButtonAction, ButtonData, ChecklistChange, ChecklistData, ChecklistItem, Connector, ConnectorStyle, FrameData, MarkdownCache, MarkdownStyle, ShapeKind, ShapeStyle, StrokeData, FRAME_TITLE_HEIGHT};
+use crate::board_file::BoardFile;
+use crate::export::{self, ExportError, ExportScope, Snapshot};
+use crate::fragment::Fragment;
+use crate::handles::ResizeHandle;
+use crate::history::{Command, History};
+use crate::loader::Loader;
+use crate::persistor::{PersistError, Persistor, SavedBlock, SavedBoard, DEFAULT_BOARD};
+use crate::state::{block_rect, BoardState};
+use crate::view::{ViewState, MAX_ZOOM, MIN_ZOOM};
+
+/// The file prompts in the File menu.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FileDialog {
+    Open,
+    SaveAs,
+    ExportSvg,
+    ExportPng,
+    ExportJson,
+    ImportJson,
+}
+
+/// What dragging on the canvas does.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Tool {
+    /// Select, move and edit blocks.
+    Select,
+    Pen,
+    Eraser,
+}
+
+/// The message in the error banner, if there is one.
+#[derive(Default)]
+struct ErrorBanner {
+    message: Option<String>,
+}
+
+impl ErrorBanner {
+    /// Shows a failed operation in the banner instead of taking down the UI
+    /// thread. Kept apart from `App` so it can be reached while a block is borrowed.
+    fn report<E: Display>(&mut self, context: &str, result: Result<(), E>) {
+        if let Err(e) = result {
+            log::error!("{}: {}", context, e);
+            self.message = Some(format!("{}: {}", context, e));
+        }
+    }
+}
+
+/// A dropped file, decoded off the UI thread.
+struct DecodedImage {
+    /// The board it was dropped on.
+    board: String,
+    /// Where its top left corner goes, in board coordinates.
+    point: Pos2,
+    /// The file's bytes and the decoded image, or why it couldn't be imported.
+    result: Result<(Vec<u8>, RetainedImage), String>,
+}
+
+/// An image block's picture, as far as it has been loaded.
+enum BlockImage {
+    /// Being read from the database and decoded on a worker thread.
+    Loading,
+    Loaded(RetainedImage),
+    /// Could not be loaded; the error has been reported.
+    Missing,
+}
+
+/// An image read from the database and decoded off the UI thread.
+struct StoredImage {
+    hash: String,
+    result: Result<RetainedImage, String>,
+}
+
+/// A PNG export rendered off the UI thread.
+struct FinishedExport {
+    path: PathBuf,
+    result: Result<(), ExportError>,
+}
 
 pub struct App {
+    /// The board being shown.
+    board: SavedBoard,
+
+    /// Every board in the database, for the File menu.
+    boards: Vec<SavedBoard>,
+
+    /// The new name being typed for the current board, while renaming it.
+    renaming_board: Option<String>,
+
+    /// Whether deleting the current board is waiting for confirmation.
+    deleting_board: bool,
+
+    /// The open file prompt and the path typed into it.
+    file_dialog: Option<(FileDialog, String)>,
+
+    /// What the export prompt exports.
+    export_scope: ExportScope,
+
+    /// Pixels per board unit in PNG exports.
+    export_scale: f32,
+
     board_state: BoardState,
 
     view_state: ViewState,
 
     dragging_widget: String,
 
-    selected_widget: String,
+    resizing_widget: String,
+
+    /// Where each block moved by the current drag was when it started.
+    drag_start: HashMap<String, Pos2>,
+
+    /// The board position a rubber-band selection was started from.
+    marquee_start: Option<Pos2>,
+
+    /// The board position under the pointer during a rubber-band selection.
+    marquee_end: Pos2,
+
+    /// The handle being dragged, the resized block's bounds when the drag
+    /// started and the board position it started from.
+    resize_start: Option<(ResizeHandle, Rect, Pos2)>,
+
+    /// The selected block's bounds when dragging one of the size sliders in
+    /// the side panel started.
+    slider_start: Option<Rect>,
+
+    /// `selected_widgets` as of the end of the previous frame.
+    last_selected: Vec<String>,
+
+    history: History,
+
+    /// Selected block ids, in the order they were selected.
+    selected_widgets: Vec<String>,
+
+    /// The selected connector, never set together with `selected_widgets`.
+    selected_connector: String,
+
+    /// `selected_connector` as of the end of the previous frame.
+    last_selected_connector: String,
+
+    /// Screen area of the editor drawn next to the selected shape or connector, as last drawn.
+    inline_editor: Option<Rect>,
+
+    /// Screen areas of the controls drawn on blocks, like the checkboxes of
+    /// checklists and the collapse toggles of frames, as last drawn. Presses
+    /// there go to the control instead of selecting or moving the block.
+    block_controls: Vec<Rect>,
+
+    tool: Tool,
+
+    pen_color: Color32,
+
+    /// Pen width in board units.
+    pen_width: f32,
+
+    /// Whether the eraser removes whole strokes instead of splitting them.
+    erase_whole_strokes: bool,
+
+    /// Board positions of the pen stroke being drawn.
+    current_stroke: Vec<Pos2>,
+
+    /// Edits made by the current eraser drag, recorded as one step on release.
+    erased: Vec<Command>,
+
+    /// Screen area of the canvas, as last drawn.
+    canvas_rect: Rect,
 
     hovered_widget: String,
 
@@ -36,15 +187,32 @@ pub struct App {
 
     total_blocks: i32,
 
-    pixels_per_point: f32,
-
-    last_pixels_per_point: f32,
+    /// The zoom the cached sizes in `board_state.sizes` were measured at.
+    measured_zoom: f32,
 
     persist: Persistor,
 
-    view_state_sender: Option<Sender<ViewState>>,
+    loader: Option<Loader>,
+
+    error_banner: ErrorBanner,
+
+    /// Images keyed by content hash, for the image blocks currently loaded.
+    images: HashMap<String, BlockImage>,
+
+    /// Labels parsed this frame, shared by the measure and draw passes.
+    markdown: MarkdownCache,
 
-    board_state_receiver: Option<Receiver<BoardState>>,
+    /// Dropped files waiting to be decoded hand their images back through here.
+    decoded_sender: Sender<DecodedImage>,
+    decoded_images: Receiver<DecodedImage>,
+
+    /// Images of loaded image blocks come back through here once decoded.
+    stored_sender: Sender<StoredImage>,
+    stored_images: Receiver<StoredImage>,
+
+    /// PNG exports report back through here once they are written.
+    export_sender: Sender<FinishedExport>,
+    finished_exports: Receiver<FinishedExport>,
 
     initialized: bool,
 
@@ -57,19 +225,55 @@ pub struct App {
 
 impl Default for App {
     fn default() -> Self {
+        let (decoded_sender, decoded_images) = channel();
+        let (stored_sender, stored_images) = channel();
+        let (export_sender, finished_exports) = channel();
         Self {
+            board: SavedBoard::new(String::from(DEFAULT_BOARD), String::from("Board")),
+            boards: Vec::new(),
+            renaming_board: None,
+            deleting_board: false,
+            file_dialog: None,
+            export_scope: ExportScope::Board,
+            export_scale: 1.00,
             dragging_widget: String::from(""),
-            selected_widget: String::from(""),
+            resizing_widget: String::from(""),
+            drag_start: HashMap::new(),
+            marquee_start: None,
+            marquee_end: Pos2::ZERO,
+            resize_start: None,
+            slider_start: None,
+            last_selected: Vec::new(),
+            history: History::default(),
+            selected_widgets: Vec::new(),
+            selected_connector: String::new(),
+            last_selected_connector: String::new(),
+            inline_editor: None,
+            block_controls: Vec::new(),
+            tool: Tool::Select,
+            pen_color: Color32::BLACK,
+            pen_width: 2.0,
+            erase_whole_strokes: false,
+            current_stroke: Vec::new(),
+            erased: Vec::new(),
+            canvas_rect: Rect::NOTHING,
             hovered_widget: String::from(""),
             board_state: BoardState::default(),
             view_state: ViewState::default(),
             persist: Persistor::default(),
             rendered_blocks: 0,
             total_blocks: 0,
-            pixels_per_point: 0.0,
-            last_pixels_per_point: 0.0,
-            view_state_sender: None,
-            board_state_receiver: None,
+            measured_zoom: 1.0,
+            loader: None,
+            error_banner: ErrorBanner::default(),
+            images: HashMap::new(),
+            markdown: MarkdownCache::default(),
+            decoded_sender,
+            decoded_images,
+            stored_sender,
+            stored_images,
+            export_sender,
+            finished_exports,
             initialized: false,
             last_viewport_change: Instant::now(),
             debug_mode: true,
@@ -80,115 +284,877 @@ impl Default for App {
 
 const BUFFER: f32 = 300.00;
 
+/// Largest width or height given to a freshly dropped image.
+const MAX_IMAGE_SIZE: f32 = 400.00;
+
+const MIN_IMAGE_SIZE: f32 = 16.00;
+
+const MIN_SHAPE_SIZE: f32 = 20.00;
+
+const MIN_LABEL_WIDTH: f32 = 40.00;
+
+const CHECKLIST_WIDTH: f32 = 240.00;
+
+const MIN_CHECKLIST_WIDTH: f32 = 120.00;
+
+const FRAME_SIZE: Vec2 = Vec2::new(480.00, 320.00);
+
+const MIN_FRAME_SIZE: Vec2 = Vec2::new(120.00, 80.00);
+
+/// Width labels wrap at until they are resized.
+const LABEL_WIDTH: f32 = 300.00;
+
+/// Side length of the resize handles drawn around the selected block, in screen points.
+const HANDLE_SIZE: f32 = 10.00;
+
+/// How close to a connector, in screen points, a click has to be to select it.
+const CONNECTOR_HIT_DISTANCE: f32 = 6.00;
+
+/// Pointer movements shorter than this, in screen points, don't add a point to a pen stroke.
+const MIN_STROKE_STEP: f32 = 2.00;
+
+/// Diameter of the eraser, in screen points.
+const ERASER_SIZE: f32 = 16.00;
+
 impl App {
     /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    ///
+    /// `database` is the board file to open, created if it doesn't exist.
+    pub fn new(cc: &eframe::CreationContext<'_>, database: PathBuf) -> Self {
         // This is also where you can customized the look at feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
         // cc.egui_ctx.set_visuals(Visuals::dark());
 
-        let (view_state_sender, view_state_reciever): (Sender<ViewState>, Receiver<ViewState>) = channel();
+        let mut instance = App {
+            persist: Persistor::new(database),
+            ..App::default()
+        };
+        let setup = instance.persist.setup();
+        instance.report("Could not open the board", setup);
+        instance.open_last_board();
+        instance.persist.start_write_behind();
+        instance.loader = Some(Loader::spawn(instance.persist.reader(), cc.egui_ctx.clone()));
+
+        instance
+    }
+
+    /// Lists the boards in the database and opens the one used last.
+    fn open_last_board(&mut self) {
+        match self.persist.boards() {
+            Ok(boards) => self.boards = boards,
+            Err(e) => self.report("Could not open the board", Err(e)),
+        }
+        let board = self.persist.last_board();
+        match board {
+            Ok(board) => self.open_board(board),
+            Err(e) => self.report("Could not open the board", Err(e)),
+        }
+    }
 
-        let (board_state_sender, board_state_receiver): (Sender<BoardState>, Receiver<BoardState>) = channel();
+    /// Switches to another database file, or to a copy of the current one
+    /// with `FileDialog::SaveAs`.
+    fn open_database(&mut self, ctx: &egui::Context, dialog: FileDialog, path: PathBuf) {
+        self.save_view();
+        // Results still on their way from the previous file are dropped with the old loader.
+        if let Some(loader) = self.loader.as_mut() {
+            loader.shutdown();
+        }
+        let result = match dialog {
+            FileDialog::SaveAs => self.persist.save_as(path),
+            _ => self.persist.switch_database(path),
+        };
+        let opened = result.is_ok();
+        self.report("Could not open the file", result);
+        self.loader = Some(Loader::spawn(self.persist.reader(), ctx.clone()));
+
+        match dialog {
+            FileDialog::Open if opened => self.open_last_board(),
+            // The copy holds the same boards, so everything loaded stays valid.
+            _ => self.request_load(),
+        }
+    }
 
-        let mut instance = App::default();
-        instance.persist.setup();
+    /// The blocks and connectors in `scope`, ready to export.
+    fn snapshot(&mut self, scope: ExportScope) -> Result<Snapshot, PersistError> {
+        let visible = Rect::from_min_max(self.view_state.offset.to_pos2(), self.view_state.viewport.to_pos2());
+        let (ids, bounds): (Vec<String>, Option<Rect>) = match scope {
+            ExportScope::Board => return Snapshot::board(&mut self.persist, &self.board.id),
+            ExportScope::Viewport => {
+                let ids = self.board_state.ids.iter()
+                    .filter(|id| self.board_state.positions.get(*id).map_or(false, |p| block_rect(p).intersects(visible)))
+                    .cloned()
+                    .collect();
+                (ids, Some(visible))
+            }
+            ExportScope::Selection => {
+                let selected = self.board_state.ids.iter().filter(|id| self.selected_widgets.contains(id)).cloned().collect::<Vec<_>>();
+                (self.board_state.with_descendants(&selected), None)
+            }
+            ExportScope::Frame => match self.selected_frame() {
+                Some(id) => {
+                    let bounds = self.board_state.positions.get(id).map(block_rect);
+                    (self.board_state.with_descendants(std::slice::from_ref(id)), bounds)
+                }
+                None => (Vec::new(), None),
+            },
+        };
+        let blocks = ids.iter().filter_map(|id| self.board_state.saved_block(id)).collect();
+        let connectors = self.board_state.connectors_of(&ids);
+        Snapshot::new(&mut self.persist, blocks, connectors, bounds)
+    }
 
+    /// Writes `scope` of the current board to `path` as SVG.
+    fn export_svg(&mut self, ctx: &egui::Context, scope: ExportScope, path: PathBuf) {
+        let snapshot = match self.snapshot(scope) {
+            Ok(snapshot) => snapshot,
+            Err(e) => return self.report("Could not export", Err(e)),
+        };
+        let font = egui::TextStyle::Body.resolve(&ctx.style());
+        let svg = export::svg(&snapshot, &ctx.fonts(), &font);
+        let result = fs::write(&path, svg);
+        self.report(&format!("Could not export {}", path.display()), result);
+    }
 
+    /// Renders `scope` of the current board to `path` as PNG, at
+    /// `export_scale`, on a worker thread. `report_exports` shows it if it fails.
+    fn export_png(&mut self, ctx: &egui::Context, scope: ExportScope, path: PathBuf) {
+        let snapshot = match self.snapshot(scope) {
+            Ok(snapshot) => snapshot,
+            Err(e) => return self.report("Could not export", Err(e)),
+        };
+        let font = egui::TextStyle::Body.resolve(&ctx.style());
+        let scale = self.export_scale;
+        let finished = self.export_sender.clone();
+        let ctx = ctx.clone();
         thread::spawn(move || {
-            loop {
-                let persist = Persistor::default();
-                let buffer = 300.00;
-                match view_state_reciever.try_recv() {
-                    Ok(view_state) => {
-                        let saved_blocks = persist.load(
-                            view_state.offset.x - buffer,
-                            view_state.viewport.x + buffer,
-                            view_state.offset.y - buffer,
-                            view_state.viewport.y + buffer,
-                        );
-
-                        let mut ids: Vec<String> = Vec::with_capacity(saved_blocks.len());
-                        let mut positions: HashMap<String, BlockPosition> = HashMap::with_capacity(saved_blocks.len());
-                        let mut blocks: HashMap<String, Block> = HashMap::with_capacity(saved_blocks.len());
-
-                        for i in 0..saved_blocks.len() {
-                            let block = saved_blocks.get(i).unwrap();
-                            ids.insert(i, block.id.clone());
-                            positions.insert(block.id.clone(), BlockPosition {
-                                id: block.id.clone(),
-                                x: block.position.x,
-                                y: block.position.y,
-                                size: Default::default(),
-                            });
-                            blocks.insert(block.id.clone(), Block {
-                                id: block.id.clone(),
-                                block_type: block.block_type,
-                                block_data: block.block_data.clone(),
-                            });
-                        }
+            let result = export::write_png(&snapshot, &font, scale, &path);
+            if finished.send(FinishedExport { path, result }).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
 
+    /// Shows the PNG exports that failed since the last frame.
+    fn report_exports(&mut self) {
+        while let Ok(FinishedExport { path, result }) = self.finished_exports.try_recv() {
+            self.report(&format!("Could not export {}", path.display()), result);
+        }
+    }
 
-                        let board_state = BoardState {
-                            positions: positions,
-                            blocks: blocks,
-                            ids: ids,
-                            sizes: Default::default()
-                        };
+    /// Writes the whole current board to `path` as JSON, see `BoardFile`.
+    fn export_json(&mut self, path: PathBuf) {
+        let file = match BoardFile::export(&mut self.persist, &self.board.id, &self.board.name) {
+            Ok(file) => file,
+            Err(e) => return self.report("Could not export", Err(e)),
+        };
+        let result = fs::write(&path, file.encode());
+        self.report(&format!("Could not export {}", path.display()), result);
+    }
 
-                        board_state_sender.send(board_state);
-                    }
-                    Err(_) => ()
-                }
-            }
+    /// Adds the board in the JSON file at `path` as a new board and opens it.
+    /// Everything on it gets a new id, so a board can be imported into the
+    /// database it came from.
+    fn import_json(&mut self, path: PathBuf) {
+        let read = fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| {
+            let file = BoardFile::parse(&text).map_err(|e| e.to_string())?;
+            let images = file.image_bytes().map_err(|e| e.to_string())?;
+            Ok((file, images))
         });
+        let (mut file, images) = match read {
+            Ok(read) => read,
+            Err(e) => return self.report(&format!("Could not import {}", path.display()), Err(e)),
+        };
 
-        instance.view_state_sender.insert(view_state_sender);
-        instance.board_state_receiver.insert(board_state_receiver);
+        file.remap_ids();
+        let mut hashes = HashMap::new();
+        for (hash, bytes) in images {
+            match self.persist.save_image(&bytes) {
+                Ok(saved) => {
+                    hashes.insert(hash, saved);
+                }
+                Err(e) => self.report("Could not import", Err(e)),
+            }
+        }
+        file.rehash_images(&hashes);
+
+        self.save_view();
+        let mut board = SavedBoard::new(Uuid::new_v4().to_string(), file.name);
+        // Start out looking at the imported blocks, wherever they are.
+        board.offset = Snapshot::fit(&file.blocks).min.to_vec2();
+        let result = self.persist.on_add_board(&board);
+        self.report("Could not save", result);
+        self.boards.push(board.clone());
+        sort_boards(&mut self.boards);
+        self.open_board(board);
+
+        for block in file.blocks {
+            self.board_state.insert(block.clone());
+            let result = self.persist.on_add(block);
+            self.report("Could not save", result);
+        }
+        for connector in file.connectors {
+            let result = self.persist.on_add_connector(&connector);
+            self.report("Could not save", result);
+            self.board_state.connect(connector);
+        }
+    }
 
-        return instance;
+    /// Surfaces a failed operation in the error banner, see `ErrorBanner::report`.
+    fn report<E: Display>(&mut self, context: &str, result: Result<(), E>) {
+        self.error_banner.report(context, result);
     }
 
     pub fn get_interact_point(&self, state: &PointerState) -> Pos2 {
-        let interact_point = state.interact_pos();
-        if interact_point.is_some() {
-            let x = interact_point.unwrap().x + self.view_state.offset.x;
-            let y = interact_point.unwrap().y + self.view_state.offset.y;
-            return Pos2::new(x, y);
+        match state.interact_pos() {
+            Some(interact_point) => self.view_state.screen_to_world(interact_point),
+            None => Pos2::default(),
         }
-        return Pos2::default();
     }
 
     pub fn add_label(&mut self, x: f32, y: f32) {
-        let id = Uuid::new_v4().to_string();
-        self.board_state.ids.push(id.clone());
+        self.add_block(BlockType::Label, String::from("Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum."), x, y, Vec2::ZERO);
+    }
+
+    pub fn add_button(&mut self, x: f32, y: f32) {
+        self.add_block(BlockType::Button, ButtonData::default().encode(), x, y, Vec2::ZERO);
+    }
+
+    pub fn add_checklist(&mut self, x: f32, y: f32) {
+        self.add_block(BlockType::Checklist, ChecklistData::default().encode(), x, y, Vec2::new(CHECKLIST_WIDTH, 0.00));
+    }
+
+    /// Creates an empty frame centered on `center`.
+    pub fn add_frame(&mut self, center: Pos2) {
+        let position = center - FRAME_SIZE / 2.0;
+        self.add_block(BlockType::Frame, FrameData::default().encode(), position.x, position.y, FRAME_SIZE);
+    }
+
+    /// Starts decoding a file dropped onto the canvas on a worker thread.
+    /// Once it is done, `add_dropped_images` makes an image block of it
+    /// with its top left corner at `point`.
+    pub fn add_image(&mut self, ctx: &egui::Context, file: &DroppedFile, point: Pos2) {
+        if file.bytes.is_none() && file.path.is_none() {
+            return;
+        }
+        let file = file.clone();
+        let board = self.board.id.clone();
+        let decoded = self.decoded_sender.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let result = decode_dropped(&file);
+            if decoded.send(DecodedImage { board, point, result }).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Starts reading the image with content hash `hash` from the database
+    /// and decoding it on a worker thread; `add_stored_images` picks it up.
+    fn load_image(&mut self, ctx: &egui::Context, hash: &str) {
+        self.images.insert(hash.to_string(), BlockImage::Loading);
+        let mut persist = self.persist.reader();
+        let hash = hash.to_string();
+        let stored = self.stored_sender.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let result = decode_image(&mut persist, &hash);
+            if stored.send(StoredImage { hash, result }).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    /// Fills in the images of loaded image blocks that have been decoded
+    /// since the last frame. Images no longer on the board are dropped.
+    fn add_stored_images(&mut self) {
+        while let Ok(StoredImage { hash, result }) = self.stored_images.try_recv() {
+            let image = match self.images.get_mut(&hash) {
+                Some(image @ BlockImage::Loading) => image,
+                _ => continue,
+            };
+            *image = match result {
+                Ok(image) => BlockImage::Loaded(image),
+                Err(e) => {
+                    self.error_banner.report("Could not load image", Err(e));
+                    BlockImage::Missing
+                }
+            };
+        }
+    }
+
+    /// Saves the dropped images that have been decoded since the last frame
+    /// and adds a block for each.
+    fn add_dropped_images(&mut self) {
+        while let Ok(DecodedImage { board, point, result }) = self.decoded_images.try_recv() {
+            // Dropped on the board shown before.
+            if board != self.board.id {
+                continue;
+            }
+            let (bytes, image) = match result {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    self.report("Could not import", Err(e));
+                    continue;
+                }
+            };
+
+            let hash = match self.persist.save_image(&bytes) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    self.report("Could not save", Err(e));
+                    continue;
+                }
+            };
 
+            let size = image.size_vec2();
+            let size = size * (MAX_IMAGE_SIZE / size.max_elem()).min(1.0);
 
-        let block = Block {
+            self.images.insert(hash.clone(), BlockImage::Loaded(image));
+            self.add_block(BlockType::Image, hash, point.x, point.y, size);
+        }
+    }
+
+    /// Creates a block, inside the frame it is added to if there is one.
+    fn add_block(&mut self, block_type: BlockType, block_data: String, x: f32, y: f32, size: Vec2) {
+        let position = Pos2::new(x, y);
+        let block = SavedBlock {
+            size,
+            position,
             id: Uuid::new_v4().to_string(),
-            block_type: BlockType::Label,
-            block_data: String::from("Lorem Ipsum is simply dummy text of the printing and typesetting industry. Lorem Ipsum has been the industry's standard dummy text ever since the 1500s, when an unknown printer took a galley of type and scrambled it to make a type specimen book. It has survived not only five centuries, but also the leap into electronic typesetting, remaining essentially unchanged. It was popularised in the 1960s with the release of Letraset sheets containing Lorem Ipsum passages, and more recently with desktop publishing software like Aldus PageMaker including versions of Lorem Ipsum."),
+            block_type,
+            block_data,
+            style: None,
+            parent: self.board_state.frame_at(position + size / 2.0, &HashSet::new()),
         };
 
-        self.board_state.blocks.insert(id.clone(), block.clone());
+        self.perform(Command::Create(block));
+    }
 
-        let position = BlockPosition {
-            id: id.clone(),
-            x,
-            y,
-            size: Vec2::new(0.00, 0.00),
+    /// Creates a shape centered on `center` and selects it.
+    pub fn add_shape(&mut self, kind: ShapeKind, center: Pos2) {
+        let size = kind.default_size();
+        let block = SavedBlock {
+            size,
+            position: center - size / 2.0,
+            id: Uuid::new_v4().to_string(),
+            block_type: BlockType::Shape,
+            block_data: String::new(),
+            style: Some(ShapeStyle::new(kind)),
+            parent: self.board_state.frame_at(center, &HashSet::new()),
         };
 
-        self.board_state.positions.insert(id.clone(), position.clone());
+        self.selected_connector.clear();
+        self.selected_widgets = vec![block.id.clone()];
+        self.perform(Command::Create(block));
+    }
 
-        self.persist.on_add(SavedBlock {
-            size: position.size,
-            position: Pos2::new(x, y),
-            id: id.clone(),
-            block_type: block.block_type,
-            block_data: block.block_data,
-        });
+    /// The selected blocks and everything in the selected frames, in the shape they are saved in.
+    fn selected_blocks(&self) -> Vec<SavedBlock> {
+        let ids = self.board_state.with_descendants(&self.selected_widgets);
+        ids.iter().filter_map(|id| self.board_state.saved_block(id)).collect()
+    }
+
+    /// The selected blocks with the connectors between them, ready to be copied.
+    fn selected_fragment(&self, images: Vec<(String, Vec<u8>)>) -> Fragment {
+        let blocks = self.selected_blocks();
+        let ids: Vec<String> = blocks.iter().map(|b| b.id.clone()).collect();
+        Fragment::new(blocks, self.board_state.connectors_of(&ids), images)
+    }
+
+    /// The selected block if it is a single frame.
+    fn selected_frame(&self) -> Option<&String> {
+        self.editing_widget()
+            .filter(|id| self.board_state.blocks.get(*id).map_or(false, |b| b.block_type == BlockType::Frame))
+    }
+
+    /// The block being edited in place, which is the selection when it is a single block.
+    fn editing_widget(&self) -> Option<&String> {
+        match self.selected_widgets.as_slice() {
+            [id] => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Deletes the selected blocks together with their connectors, or the
+    /// selected connector. Frames go with everything in them.
+    pub fn delete_selected(&mut self) {
+        let mut commands: Vec<Command> = self
+            .board_state
+            .connectors_of(&self.board_state.with_descendants(&self.selected_widgets))
+            .into_iter()
+            .map(Command::Disconnect)
+            .collect();
+        if let Some(connector) = self.board_state.connectors.get(&self.selected_connector) {
+            commands.push(Command::Disconnect(connector.clone()));
+        }
+        commands.extend(self.selected_blocks().into_iter().map(Command::Delete));
+        if !commands.is_empty() {
+            self.perform(Command::Batch(commands));
+        }
+    }
+
+    pub fn duplicate_selected(&mut self) {
+        let blocks = self.selected_blocks();
+        if let Some(origin) = blocks.iter().map(|b| b.position).reduce(|a, b| a.min(b)) {
+            self.paste_fragment(self.selected_fragment(Vec::new()), origin + Vec2::splat(20.00));
+        }
+    }
+
+    /// Draws an arrow from the first to the second of exactly two selected blocks.
+    pub fn connect_selected(&mut self) {
+        if let [from, to] = self.selected_widgets.as_slice() {
+            let connector = Connector {
+                id: Uuid::new_v4().to_string(),
+                from: from.clone(),
+                to: to.clone(),
+                style: ConnectorStyle::Straight,
+                label: String::new(),
+            };
+            self.selected_widgets.clear();
+            self.selected_connector = connector.id.clone();
+            self.perform(Command::Connect(connector));
+        }
+    }
+
+    /// The connector passing closest to the board position `point`, if any is close enough.
+    fn connector_at(&self, point: Pos2) -> Option<String> {
+        let tolerance = CONNECTOR_HIT_DISTANCE / self.view_state.zoom;
+        let hidden = self.board_state.hidden();
+        self.board_state
+            .connectors
+            .values()
+            .filter(|connector| !hidden.contains(connector.from.as_str()) && !hidden.contains(connector.to.as_str()))
+            .filter_map(|connector| {
+                let from = block_rect(self.board_state.positions.get(&connector.from)?);
+                let to = block_rect(self.board_state.positions.get(&connector.to)?);
+                Some((distance_to_path(&connector.path(from, to), point), connector))
+            })
+            .filter(|(distance, _)| *distance <= tolerance)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(_, connector)| connector.id.clone())
+    }
+
+    /// Drives the pen and eraser tools from the pointer.
+    fn use_tool(&mut self, pointer: &PointerState) {
+        let point = self.get_interact_point(pointer);
+        let on_canvas = pointer.interact_pos().map_or(false, |p| self.canvas_rect.contains(p));
+
+        match self.tool {
+            Tool::Select => {}
+            Tool::Pen => {
+                if pointer.primary_down() {
+                    if pointer.any_pressed() && on_canvas {
+                        self.current_stroke = vec![point];
+                    } else if let Some(last) = self.current_stroke.last() {
+                        if last.distance(point) * self.view_state.zoom >= MIN_STROKE_STEP {
+                            self.current_stroke.push(point);
+                        }
+                    }
+                } else if !self.current_stroke.is_empty() {
+                    let points = smooth(&std::mem::take(&mut self.current_stroke));
+                    let (stroke, bounds) = StrokeData::from_board_points(&points, self.pen_color, self.pen_width);
+                    self.add_block(BlockType::Stroke, stroke.encode(), bounds.min.x, bounds.min.y, bounds.size());
+                }
+            }
+            Tool::Eraser => {
+                if pointer.primary_down() && on_canvas {
+                    self.erase_at(point);
+                } else if !pointer.primary_down() && !self.erased.is_empty() {
+                    // A whole eraser drag is a single undo step.
+                    let commands = std::mem::take(&mut self.erased);
+                    self.history.record(Command::Batch(commands));
+                }
+            }
+        }
+    }
+
+    /// Erases the strokes under the eraser at the board position `point`.
+    fn erase_at(&mut self, point: Pos2) {
+        let radius = ERASER_SIZE / 2.0 / self.view_state.zoom;
+        let eraser = Rect::from_center_size(point, Vec2::splat(radius * 2.0));
+
+        let hidden = self.board_state.hidden();
+        let mut commands = Vec::new();
+        for id in &self.board_state.ids {
+            let position = &self.board_state.positions[id];
+            if self.board_state.blocks[id].block_type != BlockType::Stroke || !block_rect(position).intersects(eraser) || hidden.contains(id.as_str()) {
+                continue;
+            }
+            let block = match self.board_state.saved_block(id) {
+                Some(block) => block,
+                None => continue,
+            };
+            let stroke = StrokeData::parse(&block.block_data);
+            let pieces = match stroke.erase(block.position, point, radius) {
+                Some(pieces) => pieces,
+                None => continue,
+            };
+
+            let parent = block.parent.clone();
+            commands.push(Command::Delete(block));
+            if !self.erase_whole_strokes {
+                for piece in pieces {
+                    let (piece, bounds) = StrokeData::from_board_points(&piece, stroke.color, stroke.width);
+                    commands.push(Command::Create(SavedBlock {
+                        size: bounds.size(),
+                        position: bounds.min,
+                        id: Uuid::new_v4().to_string(),
+                        block_type: BlockType::Stroke,
+                        block_data: piece.encode(),
+                        style: None,
+                        // What's left of a stroke stays in the frame it was drawn in.
+                        parent: parent.clone(),
+                    }));
+                }
+            }
+        }
+
+        for command in commands {
+            self.apply(command.clone());
+            self.erased.push(command);
+        }
+    }
+
+    /// Whether the pointer is over a popup, a menu or the inline editor rather than the board itself.
+    fn pointer_on_editor(&self, ctx: &egui::Context, pointer: &PointerState) -> bool {
+        let screen_point = match pointer.interact_pos() {
+            Some(screen_point) => screen_point,
+            None => return false,
+        };
+        let over_popup = ctx.layer_id_at(screen_point).map_or(false, |layer| layer.order != Order::Background);
+        over_popup
+            || self.inline_editor.map_or(false, |editor| editor.contains(screen_point))
+            || self.block_controls.iter().any(|control| control.contains(screen_point))
+    }
+
+    /// Puts the selection on the clipboard as a board fragment.
+    pub fn copy_selected(&mut self, ctx: &egui::Context) {
+        let blocks = self.selected_blocks();
+        if blocks.is_empty() {
+            return;
+        }
+
+        let mut images = Vec::new();
+        for block in blocks.iter().filter(|b| b.block_type == BlockType::Image) {
+            match self.persist.load_image(&block.block_data) {
+                Ok(Some(bytes)) => images.push((block.block_data.clone(), bytes)),
+                Ok(None) => {}
+                Err(e) => self.report("Could not copy", Err(e)),
+            }
+        }
+
+        ctx.output().copied_text = self.selected_fragment(images).encode();
+    }
+
+    /// Adds the blocks and connectors of a fragment with its top left corner
+    /// at `at`, as one undo step. The blocks go into the frame they land in,
+    /// like dropped blocks.
+    fn paste_fragment(&mut self, mut fragment: Fragment, at: Pos2) {
+        let mut hashes = HashMap::new();
+        for (hash, bytes) in &fragment.images {
+            match self.persist.save_image(bytes) {
+                Ok(saved) => {
+                    hashes.insert(hash.clone(), saved);
+                }
+                Err(e) => self.report("Could not paste", Err(e)),
+            }
+        }
+
+        fragment.remap_ids();
+        let mut commands = Vec::with_capacity(fragment.blocks.len() + fragment.connectors.len());
+        for mut block in fragment.blocks {
+            block.position = at + block.position.to_vec2();
+            if let Some(hash) = hashes.get(&block.block_data).filter(|_| block.block_type == BlockType::Image) {
+                block.block_data = hash.clone();
+            }
+            commands.push(Command::Create(block));
+        }
+        commands.extend(fragment.connectors.into_iter().map(Command::Connect));
+
+        if !commands.is_empty() {
+            self.selected_connector.clear();
+            self.selected_widgets = commands
+                .iter()
+                .filter_map(|c| match c {
+                    Command::Create(block) => Some(block.id.clone()),
+                    _ => None,
+                })
+                .collect();
+            self.apply(Command::Batch(commands.clone()));
+            commands.extend(self.drop_into_frames(&self.selected_widgets.clone()));
+            self.history.record(Command::Batch(commands));
+        }
+    }
+
+    /// Puts blocks that were just moved or added into the frame they were
+    /// dropped on, or takes them out of the one they left, see
+    /// `BoardState::drop_into_frames`. The changes are saved and returned as
+    /// commands, to be recorded with the edit that moved the blocks.
+    fn drop_into_frames(&mut self, dropped: &[String]) -> Vec<Command> {
+        let changes = self.board_state.drop_into_frames(dropped);
+        let mut commands = Vec::with_capacity(changes.len());
+        for (id, from, to) in changes {
+            let result = self.persist.on_parent_change(&id, to.as_deref());
+            self.report("Could not save", result);
+            commands.push(Command::Reparent { id, from, to });
+        }
+        commands
+    }
+
+    /// Collapses the frame `id` to its title bar, or expands it again, as one undo step.
+    fn toggle_frame(&mut self, id: &str) {
+        let (block, position) = match (self.board_state.blocks.get(id), self.board_state.positions.get(id)) {
+            (Some(block), Some(position)) => (block, position),
+            _ => return,
+        };
+        let mut data = FrameData::parse(&block.block_data);
+        let from = block_rect(position);
+        let height = match data.collapsed.take() {
+            Some(height) => height,
+            None => {
+                data.collapsed = Some(from.height());
+                FRAME_TITLE_HEIGHT
+            }
+        };
+        let to = Rect::from_min_size(from.min, Vec2::new(from.width(), height));
+        self.perform(Command::Batch(vec![
+            Command::Edit { id: id.to_string(), from: block.block_data.clone(), to: data.encode() },
+            Command::Resize { id: id.to_string(), from, to },
+        ]));
+    }
+
+    /// Applies a new edit and records it in the undo history.
+    fn perform(&mut self, command: Command) {
+        self.apply(command.clone());
+        self.history.record(command);
+    }
+
+    /// Applies a command from the undo history to both the loaded board and the database.
+    fn apply(&mut self, command: Command) {
+        let result = match command {
+            Command::Create(block) => {
+                self.board_state.insert(block.clone());
+                self.persist.on_add(block)
+            }
+            Command::Delete(block) => {
+                self.board_state.remove(&block.id);
+                self.selected_widgets.retain(|id| id != &block.id);
+                self.persist.on_delete(&block.id)
+            }
+            Command::Move { id, to, .. } => {
+                if let Some(position) = self.board_state.positions.get_mut(&id) {
+                    position.x = to.x;
+                    position.y = to.y;
+                }
+                self.persist.on_move(&id, to.x, to.y)
+            }
+            Command::Resize { id, to, .. } => {
+                if let Some(position) = self.board_state.positions.get_mut(&id) {
+                    position.x = to.min.x;
+                    position.y = to.min.y;
+                    position.size = to.size();
+                }
+                self.board_state.sizes.remove(&id);
+                self.persist.on_size_change(&id, to)
+            }
+            Command::Edit { id, to, .. } => {
+                if let Some(block) = self.board_state.blocks.get_mut(&id) {
+                    block.block_data = to.clone();
+                }
+                self.board_state.sizes.remove(&id);
+                self.persist.on_data_change(&id, &to)
+            }
+            Command::Restyle { id, to, .. } => {
+                if let Some(block) = self.board_state.blocks.get_mut(&id) {
+                    block.style = Some(to);
+                }
+                self.persist.on_style_change(&id, &to)
+            }
+            Command::Reparent { id, to, .. } => match self.board_state.reparent(&id, to.clone()) {
+                true => self.persist.on_parent_change(&id, to.as_deref()),
+                false => Ok(()),
+            },
+            Command::Connect(connector) => {
+                let result = self.persist.on_add_connector(&connector);
+                self.board_state.connect(connector);
+                result
+            }
+            Command::Disconnect(connector) => {
+                self.board_state.disconnect(&connector.id);
+                if self.selected_connector == connector.id {
+                    self.selected_connector.clear();
+                }
+                self.persist.on_delete_connector(&connector.id)
+            }
+            Command::EditConnector { to, .. } => {
+                let result = self.persist.on_connector_change(&to);
+                self.board_state.connectors.insert(to.id.clone(), to);
+                result
+            }
+            Command::Batch(commands) => {
+                for command in commands {
+                    self.apply(command);
+                }
+                Ok(())
+            }
+        };
+        self.report("Could not save", result);
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(command) = self.history.undo() {
+            self.apply(command);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(command) = self.history.redo() {
+            self.apply(command);
+        }
+    }
+
+    /// The resize handles around the block being edited, in board coordinates.
+    ///
+    /// Buttons are sized by their caption and strokes by their points, so they
+    /// have none. A label's height follows its text, so it only gets the
+    /// handles that change its width, and so do collapsed frames.
+    fn resize_handles(&self) -> Vec<(ResizeHandle, Rect)> {
+        let id = match self.editing_widget() {
+            Some(id) => id,
+            None => return Vec::new(),
+        };
+        let (block, position) = match (self.board_state.blocks.get(id), self.board_state.positions.get(id)) {
+            (Some(block), Some(position)) => (block, position),
+            _ => return Vec::new(),
+        };
+        if !matches!(block.block_type, BlockType::Label | BlockType::Image | BlockType::Shape | BlockType::Checklist | BlockType::Frame) {
+            return Vec::new();
+        }
+
+        let bounds = block_rect(position);
+        let size = Vec2::splat(HANDLE_SIZE / self.view_state.zoom);
+        let fixed_height = has_fixed_height(block);
+        ResizeHandle::ALL
+            .into_iter()
+            .filter(|handle| !fixed_height || !handle.is_vertical())
+            .map(|handle| (handle, Rect::from_center_size(handle.position(bounds), size)))
+            .collect()
+    }
+
+    /// The board position currently at the center of the screen.
+    fn view_center(&self) -> Pos2 {
+        let center = (self.view_state.offset + self.view_state.viewport) / 2.0;
+        Pos2::new(center.x, center.y)
+    }
+
+    /// Shows `board` with the camera where it was left, dropping everything
+    /// loaded from the board shown before.
+    fn open_board(&mut self, board: SavedBoard) {
+        let result = self.persist.open_board(&board.id);
+        self.report("Could not open the board", result);
+
+        self.board_state = BoardState::default();
+        self.images.clear();
+        self.selected_widgets.clear();
+        self.selected_connector.clear();
+        self.drag_start.clear();
+        self.dragging_widget.clear();
+        self.resizing_widget.clear();
+        self.resize_start = None;
+        self.marquee_start = None;
+        self.current_stroke.clear();
+        self.erased.clear();
+        // Edits to another board can't be undone from this one.
+        self.history = History::default();
+
+        let screen_size = (self.view_state.viewport - self.view_state.offset) * self.view_state.zoom;
+        self.view_state.offset = board.offset;
+        self.view_state.zoom = board.zoom;
+        self.view_state.set_screen_size(screen_size);
+        self.view_state.last_offset = self.view_state.viewport;
+        self.view_state.last_zoom = self.view_state.zoom;
+        self.last_viewport_change = Instant::now();
+        self.board = board;
+        self.request_load();
+    }
+
+    /// Creates an empty board and switches to it, starting with its name.
+    fn new_board(&mut self) {
+        self.save_view();
+        let board = SavedBoard::new(Uuid::new_v4().to_string(), String::from("Untitled board"));
+        let result = self.persist.on_add_board(&board);
+        self.report("Could not save", result);
+        self.boards.push(board.clone());
+        sort_boards(&mut self.boards);
+        self.open_board(board);
+        self.renaming_board = Some(self.board.name.clone());
+    }
+
+    fn rename_board(&mut self, name: String) {
+        let result = self.persist.on_rename_board(&self.board.id, &name);
+        self.report("Could not save", result);
+        if let Some(board) = self.boards.iter_mut().find(|b| b.id == self.board.id) {
+            board.name = name.clone();
+        }
+        sort_boards(&mut self.boards);
+        self.board.name = name;
+    }
+
+    /// Deletes the current board with everything on it and switches to another.
+    fn delete_board(&mut self) {
+        let id = self.board.id.clone();
+        let result = self.persist.on_delete_board(&id);
+        self.report("Could not delete the board", result);
+        self.boards.retain(|b| b.id != id);
+        match self.boards.first().cloned() {
+            Some(board) => self.open_board(board),
+            None => self.new_board(),
+        }
+    }
+
+    /// Centers the view on `point`, loading its blocks right away.
+    fn jump_to(&mut self, point: Pos2) {
+        let screen_size = self.view_state.viewport - self.view_state.offset;
+        self.view_state.offset = point.to_vec2() - screen_size / 2.0;
+        self.view_state.viewport = self.view_state.offset + screen_size;
+        self.view_state.last_offset = self.view_state.viewport;
+        self.view_state.last_zoom = self.view_state.zoom;
+        self.last_viewport_change = Instant::now();
+        self.request_load();
+    }
+
+    fn run_button_action(&mut self, ctx: &egui::Context, action: ButtonAction) {
+        match action {
+            ButtonAction::OpenUrl(url) => ctx.output().open_url(url),
+            ButtonAction::JumpTo(point) => self.jump_to(point),
+            // Toggles only flip their own state, which happens while rendering.
+            ButtonAction::None | ButtonAction::Toggle(_) => {}
+        }
+    }
+
+    /// Remembers the camera for the next time the current board is opened.
+    fn save_view(&mut self) {
+        self.board.offset = self.view_state.offset;
+        self.board.zoom = self.view_state.zoom;
+        if let Some(board) = self.boards.iter_mut().find(|b| b.id == self.board.id) {
+            *board = self.board.clone();
+        }
+        let result = self.persist.on_board_view_change(&self.board.id, self.board.offset, self.board.zoom);
+        self.report("Could not save", result);
+    }
+
+    /// Loads the visible area and a margin of `BUFFER` around it, and
+    /// remembers the camera for the next time the board is opened.
+    fn request_load(&mut self) {
+        self.save_view();
+        if let Some(loader) = &self.loader {
+            let buffer = Vec2::splat(BUFFER);
+            loader.request(&self.board.id, Rect::from_min_max(
+                (self.view_state.offset - buffer).to_pos2(),
+                (self.view_state.viewport + buffer).to_pos2(),
+            ));
+        }
     }
 
     pub fn on_viewport_change(&mut self) {
@@ -199,14 +1165,16 @@ impl App {
         }
 
         let diff = self.view_state.viewport - self.view_state.last_offset;
+        let zoom_change = self.view_state.zoom / self.view_state.last_zoom;
 
-        if diff.x >= -BUFFER && diff.x <= BUFFER && diff.y >= -BUFFER && diff.y <= BUFFER {
+        if diff.x >= -BUFFER && diff.x <= BUFFER && diff.y >= -BUFFER && diff.y <= BUFFER && (0.9..=1.1).contains(&zoom_change) {
             return
         }
 
         self.view_state.last_offset = self.view_state.viewport;
+        self.view_state.last_zoom = self.view_state.zoom;
         self.last_viewport_change = Instant::now();
-        self.view_state_sender.as_ref().unwrap().send(self.view_state);
+        self.request_load();
     }
 }
 
@@ -214,55 +1182,198 @@ impl eframe::App for App {
     /// Called each time the UI needs repainting, which may be many times per second.
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        let screen_size = ctx.input().screen_rect().size();
-        self.view_state.viewport = screen_size + self.view_state.offset;
+        let screen_rect = ctx.input().screen_rect();
+        let screen_size = screen_rect.size();
+        self.view_state.set_screen_size(screen_size);
 
         if !self.initialized {
-            self.view_state_sender.as_ref().unwrap().send(self.view_state);
+            self.request_load();
             self.initialized = true;
         }
 
-        match self.board_state_receiver.borrow_mut().as_ref().unwrap().try_recv() {
-            Ok(value) => {
-                self.board_state = value;
+        for e in self.persist.take_errors() {
+            self.report("Could not save", Err(e));
+        }
+
+        if let Some(loaded) = self.loader.as_ref().and_then(Loader::try_recv) {
+            match loaded.result {
+                // Left over from the board shown before.
+                _ if loaded.board != self.board.id => {}
+                Ok(board_state) => {
+                    // Blocks more than a screen away from the visible area are let go.
+                    let visible = Rect::from_min_max(self.view_state.offset.to_pos2(), self.view_state.viewport.to_pos2());
+                    let keep = visible.expand2(visible.size()).expand(BUFFER);
+                    let mut pinned = self.selected_widgets.clone();
+                    pinned.extend(self.drag_start.keys().cloned());
+                    pinned.extend([self.dragging_widget.clone(), self.resizing_widget.clone()]);
+                    self.board_state.merge(board_state, loaded.requested, keep, &pinned);
+
+                    // Only keep decoded images that are still loaded.
+                    let blocks = &self.board_state.blocks;
+                    self.images.retain(|hash, _| {
+                        blocks.values().any(|b| b.block_type == BlockType::Image && &b.block_data == hash)
+                    });
+                }
+                Err(e) => self.report("Could not load", Err(e)),
             }
-            Err(_) => {}
         }
 
         //ctx.set_debug_on_hover(true);
 
-        if self.pixels_per_point <= 0.00 {
-            self.pixels_per_point = ctx.pixels_per_point();
+        let pointer = ctx.input().pointer.clone();
+
+        // Ctrl+scroll and pinch zoom around the pointer, the arrow keys around the middle of the screen.
+        let mut zoom_delta = ctx.input().zoom_delta();
+
+        if ctx.input().key_down(Key::ArrowDown) {
+            zoom_delta /= 1.02;
         }
 
-        if self.pixels_per_point > 5.00 {
-            self.pixels_per_point = 5.00;
+        if ctx.input().key_down(Key::ArrowUp) {
+            zoom_delta *= 1.02;
         }
 
-        if self.last_pixels_per_point != self.pixels_per_point {
-            ctx.set_pixels_per_point(self.pixels_per_point);
-            self.last_pixels_per_point = self.pixels_per_point;
+        if zoom_delta != 1.00 {
+            let anchor = pointer.hover_pos().unwrap_or_else(|| screen_rect.center());
+            self.view_state.zoom_around(zoom_delta, anchor);
+            self.on_viewport_change();
         }
 
-        if ctx.input().key_down(Key::ArrowDown) {
-            self.pixels_per_point -= 0.05;
+        let scroll_delta = ctx.input().scroll_delta;
+        if scroll_delta != Vec2::ZERO {
+            self.view_state.pan(scroll_delta);
+            self.on_viewport_change();
         }
 
-        if ctx.input().key_down(Key::ArrowUp) {
-            self.pixels_per_point += 0.05;
-        }
+        let shift = ctx.input().modifiers.shift;
+
+        if !pointer.any_down() {
+            // A whole drag is a single undo step, saved in one go, together
+            // with the blocks it dropped into or out of frames.
+            let dropped: Vec<String> = self.board_state.ids.iter().filter(|id| self.drag_start.contains_key(*id)).cloned().collect();
+            let mut moves = Vec::new();
+            for (id, from) in self.drag_start.drain() {
+                if let Some(position) = self.board_state.positions.get(&id) {
+                    let to = Pos2::new(position.x, position.y);
+                    if from != to {
+                        moves.push(Command::Move { id, from, to });
+                    }
+                }
+            }
+            if !moves.is_empty() {
+                let positions: Vec<(String, Pos2)> = moves
+                    .iter()
+                    .filter_map(|m| match m {
+                        Command::Move { id, to, .. } => Some((id.clone(), *to)),
+                        _ => None,
+                    })
+                    .collect();
+                let result = self.persist.on_moves(&positions);
+                self.report("Could not save", result);
+                moves.extend(self.drop_into_frames(&dropped));
+                self.history.record(Command::Batch(moves));
+            } else if !shift && self.selected_widgets.len() > 1 && self.selected_widgets.contains(&self.dragging_widget) {
+                // Clicking one block of a selection without dragging selects just that block.
+                self.selected_widgets = vec![self.dragging_widget.clone()];
+            }
+            self.dragging_widget = String::from("");
+
+            if let Some(start) = self.marquee_start.take() {
+                let marquee = Rect::from_two_pos(start, self.marquee_end);
+                if !shift {
+                    self.selected_widgets.clear();
+                }
+                for id in self.board_state.draw_order() {
+                    let position = &self.board_state.positions[&id];
+                    let rect = Rect::from_min_size(Pos2::new(position.x, position.y), position.size);
+                    if marquee.intersects(rect) && !self.selected_widgets.contains(&id) {
+                        self.selected_widgets.push(id.clone());
+                    }
+                }
+            }
+
+            if !self.resizing_widget.is_empty() {
+                // Saved once for the whole gesture.
+                if let (Some(position), Some((_, from, _))) = (self.board_state.positions.get(&self.resizing_widget), self.resize_start.take()) {
+                    let to = block_rect(position);
+                    if from != to {
+                        let result = self.persist.on_size_change(&self.resizing_widget, to);
+                        self.report("Could not save", result);
+                        self.history.record(Command::Resize { id: self.resizing_widget.clone(), from, to });
+                    }
+                }
+                self.resizing_widget = String::from("");
+            }
+        }
+
+        let (undo_pressed, redo_pressed) = {
+            let input = ctx.input();
+            let command = input.modifiers.command;
+            (
+                command && !input.modifiers.shift && input.key_pressed(Key::Z),
+                command && (input.modifiers.shift && input.key_pressed(Key::Z) || input.key_pressed(Key::Y)),
+            )
+        };
+
+        // A focused text box handles its own undo and clipboard.
+        if !ctx.wants_keyboard_input() {
+            if undo_pressed {
+                self.undo();
+            }
+            if redo_pressed {
+                self.redo();
+            }
+
+            let (delete_pressed, duplicate_pressed, events) = {
+                let input = ctx.input();
+                (
+                    input.key_pressed(Key::Delete) || input.key_pressed(Key::Backspace),
+                    input.modifiers.command && input.key_pressed(Key::D),
+                    input.events.clone(),
+                )
+            };
+
+            if delete_pressed {
+                self.delete_selected();
+            }
+            if duplicate_pressed {
+                self.duplicate_selected();
+            }
 
-        let scroll_delta = ctx.input().scroll_delta;
-        if scroll_delta != Vec2::ZERO {
-            self.view_state.offset -= scroll_delta;
-            self.on_viewport_change();
+            for event in events {
+                match event {
+                    Event::Copy => self.copy_selected(ctx),
+                    Event::Cut => {
+                        self.copy_selected(ctx);
+                        self.delete_selected();
+                    }
+                    Event::Paste(text) => {
+                        if let Some(fragment) = Fragment::parse(&text) {
+                            let at = match pointer.hover_pos() {
+                                Some(_) => self.get_interact_point(&pointer),
+                                None => self.view_center(),
+                            };
+                            self.paste_fragment(fragment, at);
+                        }
+                    }
+                    _ => {}
+                }
+            }
         }
 
-        let pointer = ctx.input().pointer.clone();
-
-        if !pointer.any_down() {
-            self.dragging_widget = String::from("")
+        let dropped_files = ctx.input().raw.dropped_files.clone();
+        if !dropped_files.is_empty() {
+            let drop_point = match pointer.hover_pos() {
+                Some(_) => self.get_interact_point(&pointer),
+                None => self.view_center(),
+            };
+            for (i, file) in dropped_files.iter().enumerate() {
+                self.add_image(ctx, file, drop_point + Vec2::splat(20.00 * i as f32));
+            }
         }
+        self.add_dropped_images();
+        self.add_stored_images();
+        self.report_exports();
 
         if ctx.input().key_down(Key::Space) {
             let interact_point = self.get_interact_point(&pointer);
@@ -272,42 +1383,145 @@ impl eframe::App for App {
         }
 
 
-        if self.dragging_widget == "" {
+        let drawing = self.tool != Tool::Select;
+
+        if !drawing && self.dragging_widget.is_empty() && self.resizing_widget.is_empty() && pointer.primary_down() && pointer.any_pressed() {
+            let interact_point = self.get_interact_point(&pointer);
+            let grabbed = self.resize_handles().into_iter().find(|(_, area)| area.contains(interact_point));
+            if let Some((handle, _)) = grabbed {
+                self.resizing_widget = self.editing_widget().cloned().unwrap_or_default();
+                self.resize_start = self
+                    .board_state
+                    .positions
+                    .get(&self.resizing_widget)
+                    .map(|position| (handle, block_rect(position), interact_point));
+            }
+        }
+
+        if !drawing {
+            let hovered_handle = match self.resize_start {
+                Some((handle, _, _)) => Some(handle),
+                None => {
+                    let interact_point = self.get_interact_point(&pointer);
+                    self.resize_handles().into_iter().find(|(_, area)| area.contains(interact_point)).map(|(handle, _)| handle)
+                }
+            };
+            if let Some(handle) = hovered_handle {
+                ctx.output().cursor_icon = handle.cursor();
+            }
+        }
+
+        if drawing {
+            self.hovered_widget = String::new();
+            self.use_tool(&pointer);
+        } else if self.marquee_start.is_some() {
+            self.marquee_end = self.get_interact_point(&pointer);
+        } else if self.dragging_widget.is_empty() && self.resizing_widget.is_empty() && self.pointer_on_editor(ctx, &pointer) {
+            // Popups and inline editors handle their own clicks.
+            self.hovered_widget = String::new();
+        } else if self.dragging_widget.is_empty() && self.resizing_widget.is_empty() {
             let interact_point = self.get_interact_point(&pointer);
             let x = interact_point.x;
             let y = interact_point.y;
 
-            let mut matched = false;
-            for id in &self.board_state.ids {
-                let block_position = self.board_state.positions.get(id).unwrap();
+            // Blocks drawn later are on top, so the last one under the pointer wins.
+            let hit = self.board_state.draw_order().into_iter().rev().find(|id| {
+                let block_position = &self.board_state.positions[id];
                 let widget_size = block_position.size;
-                if x >= block_position.x && x <= block_position.x + widget_size.x && y >= block_position.y && y <= block_position.y + widget_size.y {
-                    if pointer.primary_down() {
-                        self.dragging_widget = block_position.id.clone();
-                        self.selected_widget = block_position.id.clone();
+                x >= block_position.x && x <= block_position.x + widget_size.x && y >= block_position.y && y <= block_position.y + widget_size.y
+            });
+
+            match hit {
+                Some(id) => {
+                    // Only on press, so panning across a block doesn't pick it up.
+                    if pointer.primary_down() && pointer.any_pressed() {
+                        self.selected_connector.clear();
+                        // Clicking a button runs its action; it is selected for editing with a double click.
+                        let is_button = matches!(self.board_state.blocks.get(&id).map(|b| b.block_type), Some(BlockType::Button));
+                        if shift {
+                            match self.selected_widgets.iter().position(|s| s == &id) {
+                                Some(index) => {
+                                    self.selected_widgets.remove(index);
+                                }
+                                None => self.selected_widgets.push(id.clone()),
+                            }
+                        } else if !is_button && !self.selected_widgets.contains(&id) {
+                            self.selected_widgets = vec![id.clone()];
+                        }
+
+                        // Dragging a selected block moves the whole selection along with it,
+                        // and dragging a frame everything in it.
+                        let moving = match self.selected_widgets.contains(&id) {
+                            true => self.selected_widgets.clone(),
+                            false => vec![id.clone()],
+                        };
+                        self.drag_start = self
+                            .board_state
+                            .with_descendants(&moving)
+                            .into_iter()
+                            .filter_map(|m| {
+                                let position = self.board_state.positions.get(&m)?;
+                                Some((m, Pos2::new(position.x, position.y)))
+                            })
+                            .collect();
+                        self.dragging_widget = id.clone();
                     }
-                    self.hovered_widget = block_position.id.clone();
-                    matched = true;
+                    self.hovered_widget = id;
                 }
-            }
-
-            if !matched {
-                self.hovered_widget = String::new();
-                if pointer.any_down() {
-                    self.selected_widget = String::new();
+                None => {
+                    self.hovered_widget = String::new();
+                    if pointer.primary_down() && pointer.any_pressed() && shift {
+                        // Shift dragging on empty canvas selects with a rubber band instead of panning.
+                        self.selected_connector.clear();
+                        self.marquee_start = Some(interact_point);
+                        self.marquee_end = interact_point;
+                    } else if pointer.primary_down() && pointer.any_pressed() && !shift {
+                        self.selected_widgets.clear();
+                        self.selected_connector = self.connector_at(interact_point).unwrap_or_default();
+                    } else if pointer.any_down() && !shift {
+                        self.selected_widgets.clear();
+                    }
                 }
             }
         }
 
         if pointer.any_down() && pointer.is_moving() {
-            if self.dragging_widget != "" {
-                let position = self.board_state.positions.get_mut(&self.dragging_widget).unwrap();
-                position.x += pointer.delta().x;
-                position.y += pointer.delta().y;
-                self.persist.on_move(&self.dragging_widget, position.x, position.y)
-            } else {
-                self.view_state.offset.x -= pointer.delta().x;
-                self.view_state.offset.y -= pointer.delta().y;
+            if let (false, Some((handle, start, origin))) = (self.resizing_widget.is_empty(), self.resize_start) {
+                // Images always keep their aspect ratio, other blocks while shift is held.
+                // The new bounds are saved once the pointer is released.
+                let block = &self.board_state.blocks[&self.resizing_widget];
+                let fixed_height = has_fixed_height(block);
+                let (min_size, keep_aspect) = match block.block_type {
+                    BlockType::Image => (Vec2::splat(MIN_IMAGE_SIZE), true),
+                    BlockType::Label => (Vec2::new(MIN_LABEL_WIDTH, 0.00), false),
+                    BlockType::Checklist => (Vec2::new(MIN_CHECKLIST_WIDTH, 0.00), false),
+                    BlockType::Frame if fixed_height => (Vec2::new(MIN_FRAME_SIZE.x, 0.00), false),
+                    BlockType::Frame => (MIN_FRAME_SIZE, shift),
+                    _ => (Vec2::splat(MIN_SHAPE_SIZE), shift),
+                };
+                let delta = self.get_interact_point(&pointer) - origin;
+                let bounds = handle.resize(start, delta, min_size, keep_aspect);
+
+                let position = self.board_state.positions.get_mut(&self.resizing_widget).unwrap();
+                position.x = bounds.min.x;
+                position.size.x = bounds.width();
+                if !fixed_height {
+                    position.y = bounds.min.y;
+                    position.size.y = bounds.height();
+                }
+                // Measured again next frame, which reflows a label's text to the new width.
+                self.board_state.sizes.remove(&self.resizing_widget);
+            } else if !self.dragging_widget.is_empty() {
+                // Positions are saved once the pointer is released.
+                let delta = pointer.delta() / self.view_state.zoom;
+                for id in self.drag_start.keys() {
+                    if let Some(position) = self.board_state.positions.get_mut(id) {
+                        position.x += delta.x;
+                        position.y += delta.y;
+                    }
+                }
+            } else if self.marquee_start.is_none() && !drawing && !ctx.is_using_pointer() {
+                self.view_state.pan(pointer.delta());
                 self.on_viewport_change();
             }
         }
@@ -329,12 +1543,31 @@ impl eframe::App for App {
             self.last_click = now;
         }
 
-        if is_double_click && self.hovered_widget == "" {
+        if drawing {
+            // Quick pen taps are dots, not double clicks.
+        } else if is_double_click && self.hovered_widget.is_empty() && !self.pointer_on_editor(ctx, &pointer) {
             let interact_point = self.get_interact_point(&pointer);
             self.add_label(interact_point.x, interact_point.y);
+        } else if is_double_click {
+            self.selected_connector.clear();
+            self.selected_widgets = vec![self.hovered_widget.clone()];
+        }
+
+        let mut button_action = None;
+        let mut toggled_frame = None;
+        let view_center = self.view_center();
+        let editing = self.editing_widget().cloned().unwrap_or_default();
+        let zoom = self.view_state.zoom;
+
+        if self.measured_zoom != zoom {
+            // Text doesn't scale exactly linearly, so measure everything again.
+            self.board_state.sizes.clear();
+            self.measured_zoom = zoom;
         }
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            ui.set_style(zoomed_style(&ctx.style(), zoom));
+            self.canvas_rect = ui.max_rect();
 
             self.rendered_blocks = 0;
             self.total_blocks = 0;
@@ -348,33 +1581,46 @@ impl eframe::App for App {
                     continue
                 }
 
-                let mut block_position = self.board_state.positions.get_mut(id).unwrap();
+                let block_position = self.board_state.positions.get_mut(id).unwrap();
                 let block = self.board_state.blocks.get_mut(&block_position.id).unwrap();
                 match block.block_type {
-                    BlockType::Button => {}
+                    BlockType::Button => {
+                        let data = ButtonData::parse(&block.block_data);
+                        let r = egui::Button::new(data.caption()).ui(ui);
+                        self.board_state.sizes.insert(id.clone(), r.rect.size() / zoom);
+                    }
+                    BlockType::Image | BlockType::Stroke | BlockType::Shape | BlockType::Frame => {
+                        // Drawn at their saved size, there is nothing to measure.
+                        self.board_state.sizes.insert(id.clone(), block_position.size);
+                    }
+                    BlockType::Checklist => {
+                        // Laid out by its widgets, the draw pass corrects the height.
+                        let width = if block_position.size.x == 0.00 { CHECKLIST_WIDTH } else { block_position.size.x };
+                        self.board_state.sizes.insert(id.clone(), Vec2::new(width, block_position.size.y));
+                    }
                     BlockType::Label => {
-                        let r = match &self.selected_widget == id {
+                        let mut size = match &editing == id {
                             true => {
                                 egui::TextEdit::multiline(&mut block.block_data)
-                                    .hint_text("Type something!").ui(ui)
+                                    .hint_text("Type something!").ui(ui).rect.size() / zoom
                             }
                             false => {
-                                egui::Label::new(block.block_data.clone()).ui(ui)
+                                // Measured as rendered, wrapping where the draw pass will.
+                                let width = if block_position.size.x == 0.00 { LABEL_WIDTH } else { block_position.size.x };
+                                let markdown = self.markdown.get(&block.block_data, &MarkdownStyle::from_style(ui.style()));
+                                markdown.layout(&ui.fonts(), width * zoom).size() / zoom
                             }
                         };
 
-                        let mut rect = r.rect;
                         // Clamp to max 300 width by default
                         if block_position.size.x == 0.00 {
-                            if rect.width() > 300.00 {
-                                rect.set_width(300.00);
+                            if size.x > LABEL_WIDTH {
+                                size.x = LABEL_WIDTH;
                             }
                         } else {
-                            rect.set_width(block_position.size.x);
+                            size.x = block_position.size.x;
                         }
 
-                        let size = rect.size();
-
                         self.board_state.sizes.insert(block.id.clone(), size);
                     }
                 }
@@ -382,48 +1628,329 @@ impl eframe::App for App {
 
             ui.set_clip_rect(old_clip_rect);
 
-            for id in &self.board_state.ids {
+            // Connectors go underneath the blocks they join, and aren't drawn
+            // to blocks hidden in collapsed frames.
+            self.inline_editor = None;
+            self.block_controls.clear();
+            let order = self.board_state.draw_order();
+            let drawn: HashSet<&String> = order.iter().collect();
+            for connector in self.board_state.connectors.values_mut() {
+                if !drawn.contains(&connector.from) || !drawn.contains(&connector.to) {
+                    continue;
+                }
+                let (from, to) = match (self.board_state.positions.get(&connector.from), self.board_state.positions.get(&connector.to)) {
+                    (Some(from), Some(to)) => (block_rect(from), block_rect(to)),
+                    _ => continue,
+                };
+                let path: Vec<Pos2> = connector.path(from, to).into_iter().map(|p| self.view_state.world_to_screen(p)).collect();
+                let selected = connector.id == self.selected_connector;
+                let color = if selected { Color32::RED } else { Color32::GRAY };
+                ui.painter().extend(arrow_shapes(&path, color, zoom));
+
+                let middle = path_midpoint(&path);
+                if selected {
+                    let original = connector.clone();
+                    let editor_rect = Rect::from_min_size(middle, Vec2::new(240.00 * zoom, 0.00));
+                    let r = ui.allocate_ui_at_rect(editor_rect, |ui| connector_editor(ui, connector));
+                    self.inline_editor = Some(r.response.rect);
+                    if *connector != original {
+                        self.history.record(Command::EditConnector { from: original, to: connector.clone() });
+                        let result = self.persist.on_connector_change(connector);
+                        self.error_banner.report("Could not save", result);
+                    }
+                } else if !connector.label.is_empty() {
+                    let font_id = egui::TextStyle::Body.resolve(ui.style());
+                    let galley = ui.painter().layout_no_wrap(connector.label.clone(), font_id, ui.visuals().text_color());
+                    let label_rect = Rect::from_center_size(middle, galley.size());
+                    ui.painter().rect_filled(label_rect.expand(2.00 * zoom), 2.0, ui.visuals().window_fill());
+                    ui.painter().galley(label_rect.min, galley);
+                }
+            }
+
+            for id in &order {
                 self.total_blocks += 1;
-                let mut block_position = self.board_state.positions.get_mut(id).unwrap();
+                let block_position = self.board_state.positions.get_mut(id).unwrap();
                 self.rendered_blocks += 1;
-                let position = Pos2::new(block_position.x - self.view_state.offset.x, block_position.y - self.view_state.offset.y);
+                let position = self.view_state.world_to_screen(Pos2::new(block_position.x, block_position.y));
                 let block = self.board_state.blocks.get_mut(&block_position.id).unwrap();
                 let original_data = block.block_data.clone();
 
                 match block.block_type {
                     BlockType::Button => {
-                        // ui.put(widget_rect, egui::Button::new(data));
+                        let size = match self.board_state.sizes.get(id) {
+                            None => continue,
+                            Some(size) => *size,
+                        };
+                        let mut data = ButtonData::parse(&block.block_data);
+
+                        let rect = if &editing == id {
+                            let editor_rect = Rect::from_min_size(position, Vec2::new((size.x * zoom).max(240.00), size.y * zoom));
+                            let r = ui.allocate_ui_at_rect(editor_rect, |ui| button_editor(ui, &mut data, view_center));
+                            ui.painter().rect_stroke(r.response.rect, 4.0, (1.0, Color32::RED));
+                            r.response.rect
+                        } else {
+                            let r = ui.put(Rect::from_min_size(position, size * zoom), egui::Button::new(data.caption()));
+                            if r.clicked() {
+                                if let ButtonAction::Toggle(state) = data.action {
+                                    data.action = ButtonAction::Toggle(!state);
+                                }
+                                button_action = Some(data.action.clone());
+                            }
+                            if self.selected_widgets.contains(id) {
+                                ui.painter().rect_stroke(r.rect, 4.0, (1.0, Color32::RED));
+                            } else if id == &self.hovered_widget {
+                                ui.painter().rect_stroke(r.rect, 4.0, (1.0, Color32::LIGHT_BLUE));
+                            }
+                            r.rect
+                        };
+
+                        let encoded = data.encode();
+                        if encoded != original_data {
+                            block.block_data = encoded;
+                            self.history.record(Command::Edit { id: id.clone(), from: original_data.clone(), to: block.block_data.clone() });
+                            if &editing != id {
+                                // A toggle flipped by a click is a step of its own.
+                                self.history.seal();
+                            }
+                            // The caption may have changed width, so measure it again next frame.
+                            self.board_state.sizes.remove(id);
+                            let result = self.persist.on_data_change(id, &block.block_data);
+                            self.error_banner.report("Could not save", result);
+                        }
+
+                        let size = rect.size() / zoom;
+                        if size_changed(block_position.size, size) {
+                            block_position.size = size;
+                            let result = self.persist.on_size_change(id, block_rect(block_position));
+                            self.error_banner.report("Could not save", result);
+                        }
+                    }
+                    BlockType::Checklist => {
+                        let size = match self.board_state.sizes.get(id) {
+                            None => continue,
+                            Some(size) => *size,
+                        };
+                        let before = ChecklistData::parse(&block.block_data);
+                        let mut data = before.clone();
+                        let checklist_rect = Rect::from_min_size(position, Vec2::new(size.x * zoom, 0.00));
+                        let controls = &mut self.block_controls;
+                        let r = ui.allocate_ui_at_rect(checklist_rect, |ui| checklist_ui(ui, id, &mut data, &editing == id, zoom, controls));
+                        let rect = r.response.rect;
+                        if self.selected_widgets.contains(id) {
+                            ui.painter().rect_stroke(rect, 4.0, (1.0, Color32::RED));
+                        } else if id == &self.hovered_widget {
+                            ui.painter().rect_stroke(rect, 4.0, (1.0, Color32::LIGHT_BLUE));
+                        }
+
+                        let changes = before.changes(&data);
+                        if !changes.is_empty() {
+                            block.block_data = data.encode();
+                            // Checking off, moving, adding or removing an item is a step of its own,
+                            // even in the middle of typing.
+                            let typing = changes.iter().all(|change| match change {
+                                ChecklistChange::Title(_) => true,
+                                ChecklistChange::Item { index, item } => before.items[*index].done == item.done,
+                                _ => false,
+                            });
+                            if !typing {
+                                self.history.seal();
+                            }
+                            self.history.record(Command::Edit { id: id.clone(), from: original_data.clone(), to: block.block_data.clone() });
+                            if !typing || &editing != id {
+                                self.history.seal();
+                            }
+                            let result = self.persist.on_checklist_change(id, changes);
+                            self.error_banner.report("Could not save", result);
+                        }
+
+                        let size = rect.size() / zoom;
+                        if size_changed(block_position.size, size) {
+                            self.board_state.sizes.insert(id.clone(), size);
+                            if id != &self.resizing_widget {
+                                block_position.size = size;
+                                let result = self.persist.on_size_change(id, block_rect(block_position));
+                                self.error_banner.report("Could not save", result);
+                            }
+                        }
+                    }
+                    BlockType::Frame => {
+                        let mut data = FrameData::parse(&block.block_data);
+                        let rect = Rect::from_min_size(position, block_position.size * zoom);
+                        let title_bar = Rect::from_min_size(position, Vec2::new(rect.width(), rect.height().min(FRAME_TITLE_HEIGHT * zoom)));
+                        let (fill, title_fill, stroke, title_color) = {
+                            let visuals = ui.visuals();
+                            (visuals.faint_bg_color, visuals.extreme_bg_color, visuals.widgets.noninteractive.bg_stroke, visuals.strong_text_color())
+                        };
+                        ui.painter().rect(rect, 0.0, fill, stroke);
+                        ui.painter().rect_filled(title_bar, 0.0, title_fill);
+
+                        // The triangle points down while expanded and right while collapsed.
+                        let toggle_rect = Rect::from_min_size(title_bar.min, Vec2::splat(title_bar.height()));
+                        let toggle = ui.interact(toggle_rect, egui::Id::new((id, "frame toggle")), egui::Sense::click());
+                        self.block_controls.push(toggle_rect);
+                        let (c, r) = (toggle_rect.center(), toggle_rect.height() * 0.20);
+                        let triangle = match data.collapsed {
+                            Some(_) => vec![c + Vec2::new(-r * 0.50, -r), c + Vec2::new(-r * 0.50, r), c + Vec2::new(r * 0.80, 0.00)],
+                            None => vec![c + Vec2::new(-r, -r * 0.50), c + Vec2::new(r, -r * 0.50), c + Vec2::new(0.00, r * 0.80)],
+                        };
+                        let color = ui.style().interact(&toggle).fg_stroke.color;
+                        ui.painter().add(egui::Shape::convex_polygon(triangle, color, egui::Stroke::none()));
+                        if toggle.on_hover_text(if data.collapsed.is_some() { "Expand" } else { "Collapse" }).clicked() {
+                            toggled_frame = Some(id.clone());
+                        }
+
+                        let title_rect = Rect::from_min_max(Pos2::new(toggle_rect.max.x, title_bar.min.y), title_bar.max);
+                        if id == &editing {
+                            let title = ui.put(title_rect, egui::TextEdit::singleline(&mut data.title)
+                                .frame(false)
+                                .hint_text("Title"));
+                            self.block_controls.push(title.rect);
+                        } else {
+                            let font_id = egui::TextStyle::Body.resolve(ui.style());
+                            let galley = ui.painter().layout_no_wrap(data.title.clone(), font_id, title_color);
+                            let title_pos = Pos2::new(title_rect.min.x, title_rect.center().y - galley.size().y / 2.0);
+                            ui.painter().with_clip_rect(title_rect).galley(title_pos, galley);
+                        }
+
+                        let encoded = data.encode();
+                        if encoded != original_data {
+                            block.block_data = encoded;
+                            self.history.record(Command::Edit { id: id.clone(), from: original_data.clone(), to: block.block_data.clone() });
+                            let result = self.persist.on_data_change(id, &block.block_data);
+                            self.error_banner.report("Could not save", result);
+                        }
+
+                        if self.selected_widgets.contains(id) {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::RED));
+                        } else if id == &self.hovered_widget {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::LIGHT_BLUE));
+                        }
+                    }
+                    BlockType::Image => {
+                        let rect = Rect::from_min_size(position, block_position.size * zoom);
+
+                        // Loaded the first time the block is on screen.
+                        let hash = block.block_data.clone();
+                        if !self.images.contains_key(&hash) {
+                            self.load_image(ui.ctx(), &hash);
+                        }
+
+                        match &self.images[&hash] {
+                            BlockImage::Loaded(image) => {
+                                ui.put(rect, egui::Image::new(image.texture_id(ui.ctx()), rect.size()));
+                            }
+                            BlockImage::Loading => {
+                                ui.painter().rect_filled(rect, 0.0, Color32::from_gray(230));
+                            }
+                            BlockImage::Missing => {
+                                ui.painter().rect_filled(rect, 0.0, Color32::from_gray(230));
+                                ui.painter().text(rect.center(), Align2::CENTER_CENTER, "Missing image", FontId::default(), Color32::GRAY);
+                            }
+                        }
+
+                        if self.selected_widgets.contains(id) {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::RED));
+                        } else if id == &self.hovered_widget {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::LIGHT_BLUE));
+                        }
+                    }
+                    BlockType::Shape => {
+                        let style = block.style.unwrap_or_else(|| ShapeStyle::new(ShapeKind::Rectangle));
+                        let rect = Rect::from_min_size(position, block_position.size * zoom);
+                        ui.painter().extend(style.shapes(rect, zoom));
+
+                        let text_rect = style.kind.text_rect(rect);
+                        let text_color = text_color_on(style.fill);
+                        if id == &editing {
+                            ui.put(text_rect, egui::TextEdit::multiline(&mut block.block_data)
+                                .frame(false)
+                                .text_color(text_color)
+                                .hint_text("Type something!"));
+
+                            let mut new_style = style;
+                            let editor_rect = Rect::from_min_size(rect.left_bottom() + Vec2::new(0.00, 4.00), Vec2::new(rect.width().max(240.00), 0.00));
+                            let r = ui.allocate_ui_at_rect(editor_rect, |ui| shape_editor(ui, &mut new_style));
+                            self.inline_editor = Some(r.response.rect);
+                            if new_style != style {
+                                block.style = Some(new_style);
+                                self.history.record(Command::Restyle { id: id.clone(), from: style, to: new_style });
+                                let result = self.persist.on_style_change(id, &new_style);
+                                self.error_banner.report("Could not save", result);
+                            }
+
+                            if original_data != block.block_data {
+                                self.history.record(Command::Edit { id: id.clone(), from: original_data.clone(), to: block.block_data.clone() });
+                                let result = self.persist.on_data_change(id, &block.block_data);
+                                self.error_banner.report("Could not save", result);
+                            }
+                        } else if !block.block_data.is_empty() {
+                            let font_id = egui::TextStyle::Body.resolve(ui.style());
+                            let galley = ui.painter().layout(block.block_data.clone(), font_id, text_color, text_rect.width());
+                            let text_pos = text_rect.center() - galley.size() / 2.0;
+                            ui.painter().with_clip_rect(rect).galley(text_pos, galley);
+                        }
+
+                        if self.selected_widgets.contains(id) {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::RED));
+                        } else if id == &self.hovered_widget {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::LIGHT_BLUE));
+                        }
+                    }
+                    BlockType::Stroke => {
+                        let stroke = StrokeData::parse(&block.block_data);
+                        let origin = Pos2::new(block_position.x, block_position.y);
+                        let points: Vec<Pos2> = stroke.points.iter().map(|p| self.view_state.world_to_screen(origin + p.to_vec2())).collect();
+                        ui.painter().add(stroke_shape(&points, stroke.color, stroke.width * zoom));
+
+                        let rect = Rect::from_min_size(position, block_position.size * zoom);
+                        if self.selected_widgets.contains(id) {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::RED));
+                        } else if id == &self.hovered_widget {
+                            ui.painter().rect_stroke(rect, 0.0, (1.0, Color32::LIGHT_BLUE));
+                        }
                     }
                     BlockType::Label => {
 
                         match self.board_state.sizes.get(id) {
                             None => {}
                             Some(size) => {
-                                let widget_rect = Rect::from_min_size(position, *size);
+                                let widget_rect = Rect::from_min_size(position, *size * zoom);
 
                                 ui.set_clip_rect(old_clip_rect);
 
-                                let r2 = match &self.selected_widget == id {
+                                let r2 = match &editing == id {
                                     true => {
+                                        // Fills the label's width, so it can be resized past the default text box width.
                                         ui.put(widget_rect, egui::TextEdit::multiline(&mut block.block_data)
+                                            .desired_width(f32::INFINITY)
                                             .hint_text("Type something!"))
                                     }
                                     false => {
-                                        ui.put(widget_rect, egui::Label::new(block.block_data.clone()).wrap(true))
+                                        let markdown = self.markdown.get(&block.block_data, &MarkdownStyle::from_style(ui.style()));
+                                        let r = ui.allocate_ui_at_rect(widget_rect, |ui| ui.add(&*markdown)).inner;
+                                        // Editing may have changed the text, so keep the measured size in step.
+                                        if size_changed(*size, r.rect.size() / zoom) {
+                                            self.board_state.sizes.insert(id.clone(), r.rect.size() / zoom);
+                                        }
+                                        r
                                     }
                                 };
 
-                                if block_position.size != r2.rect.size() {
-                                    block_position.size = r2.rect.size();
-                                    println!("block size change: {}, {}", r2.rect.size().x, r2.rect.size().y);
-                                    self.persist.on_size_change(id, r2.rect.size());
+                                let size = r2.rect.size() / zoom;
+                                // While a handle is dragged the handle decides the size.
+                                if id != &self.resizing_widget && size_changed(block_position.size, size) {
+                                    block_position.size = size;
+                                    let result = self.persist.on_size_change(id, block_rect(block_position));
+                                    self.error_banner.report("Could not save", result);
                                 }
 
-                                if id == &self.selected_widget {
+                                if self.selected_widgets.contains(id) {
                                     ui.painter().rect_stroke(r2.rect, 4.0, (1.0, Color32::RED));
 
                                     if original_data != block.block_data {
-                                        self.persist.on_data_change(id, block.block_data.clone());
+                                        self.history.record(Command::Edit { id: id.clone(), from: original_data.clone(), to: block.block_data.clone() });
+                                        let result = self.persist.on_data_change(id, &block.block_data);
+                                        self.error_banner.report("Could not save", result);
                                     }
                                 }
 
@@ -435,32 +1962,330 @@ impl eframe::App for App {
                     }
                 }
             }
+
+            for (_, handle) in self.resize_handles() {
+                let handle = Rect::from_center_size(self.view_state.world_to_screen(handle.center()), Vec2::splat(HANDLE_SIZE));
+                ui.painter().rect_filled(handle, 0.0, Color32::WHITE);
+                ui.painter().rect_stroke(handle, 0.0, (1.0, Color32::RED));
+            }
+
+            if !self.current_stroke.is_empty() {
+                let points: Vec<Pos2> = self.current_stroke.iter().map(|p| self.view_state.world_to_screen(*p)).collect();
+                ui.painter().add(stroke_shape(&points, self.pen_color, self.pen_width * zoom));
+            }
+
+            if self.tool == Tool::Eraser {
+                if let Some(hover) = ctx.input().pointer.hover_pos() {
+                    ui.painter().circle_stroke(hover, ERASER_SIZE / 2.0, (1.0, Color32::GRAY));
+                }
+            }
+
+            if let Some(start) = self.marquee_start {
+                let marquee = Rect::from_two_pos(self.view_state.world_to_screen(start), self.view_state.world_to_screen(self.marquee_end));
+                ui.painter().rect(marquee, 0.0, Color32::from_rgba_unmultiplied(100, 150, 255, 40), (1.0, Color32::LIGHT_BLUE));
+            }
         });
 
+        if let Some(action) = button_action {
+            self.run_button_action(ctx, action);
+        }
+        if let Some(id) = toggled_frame {
+            self.toggle_frame(&id);
+        }
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    if ui.button("New board").clicked() {
+                        self.new_board();
+                        ui.close_menu();
+                    }
+                    ui.menu_button("Open board", |ui| {
+                        for board in self.boards.clone() {
+                            if ui.selectable_label(board.id == self.board.id, &board.name).clicked() {
+                                if board.id != self.board.id {
+                                    self.save_view();
+                                    self.open_board(board);
+                                }
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    if ui.button("Rename board…").clicked() {
+                        self.renaming_board = Some(self.board.name.clone());
+                        ui.close_menu();
+                    }
+                    let delete_button = ui
+                        .add_enabled(self.boards.len() > 1, egui::Button::new("Delete board…"))
+                        .on_disabled_hover_text("The last board can't be deleted");
+                    if delete_button.clicked() {
+                        self.deleting_board = true;
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    let database = self.persist.database().display().to_string();
+                    if ui.button("Open…").on_hover_text(&database).clicked() {
+                        self.file_dialog = Some((FileDialog::Open, database.clone()));
+                        ui.close_menu();
+                    }
+                    if ui.button("Save As…").on_hover_text(&database).clicked() {
+                        self.file_dialog = Some((FileDialog::SaveAs, database));
+                        ui.close_menu();
+                    }
+                    if ui.button("Import JSON…").clicked() {
+                        self.file_dialog = Some((FileDialog::ImportJson, String::new()));
+                        ui.close_menu();
+                    }
+                    if ui.button("Export JSON…").clicked() {
+                        self.file_dialog = Some((FileDialog::ExportJson, format!("{}.json", self.board.name)));
+                        ui.close_menu();
+                    }
+                    if ui.button("Export SVG…").clicked() {
+                        self.file_dialog = Some((FileDialog::ExportSvg, format!("{}.svg", self.board.name)));
+                        ui.close_menu();
+                    }
+                    if ui.button("Export PNG…").clicked() {
+                        self.file_dialog = Some((FileDialog::ExportPng, format!("{}.png", self.board.name)));
+                        ui.close_menu();
+                    }
+                    ui.separator();
                     if ui.button("Quit").clicked() {
                         frame.quit();
                     }
                 });
+                ui.menu_button("Edit", |ui| {
+                    if ui.add_enabled(self.history.can_undo(), egui::Button::new("Undo")).clicked() {
+                        self.undo();
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(self.history.can_redo(), egui::Button::new("Redo")).clicked() {
+                        self.redo();
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    let has_selection = !self.selected_widgets.is_empty();
+                    let has_connector = !self.selected_connector.is_empty();
+                    if ui.add_enabled(has_selection, egui::Button::new("Copy")).clicked() {
+                        self.copy_selected(ui.ctx());
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(has_selection, egui::Button::new("Duplicate")).clicked() {
+                        self.duplicate_selected();
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(has_selection || has_connector, egui::Button::new("Delete")).clicked() {
+                        self.delete_selected();
+                        ui.close_menu();
+                    }
+                });
+                ui.menu_button("Insert", |ui| {
+                    if ui.button("Label").clicked() {
+                        let center = self.view_center();
+                        self.add_label(center.x, center.y);
+                        ui.close_menu();
+                    }
+                    if ui.button("Button").clicked() {
+                        let center = self.view_center();
+                        self.add_button(center.x, center.y);
+                        ui.close_menu();
+                    }
+                    if ui.button("Checklist").clicked() {
+                        let center = self.view_center();
+                        self.add_checklist(center.x, center.y);
+                        ui.close_menu();
+                    }
+                    if ui.button("Frame").clicked() {
+                        let center = self.view_center();
+                        self.add_frame(center);
+                        ui.close_menu();
+                    }
+                    let connector_button = ui
+                        .add_enabled(self.selected_widgets.len() == 2, egui::Button::new("Connector"))
+                        .on_hover_text("Connect two selected blocks, from the first selected to the second")
+                        .on_disabled_hover_text("Shift-click two blocks to connect them");
+                    if connector_button.clicked() {
+                        self.connect_selected();
+                        ui.close_menu();
+                    }
+                });
+
+                ui.separator();
+                ui.selectable_value(&mut self.tool, Tool::Select, "Select");
+                ui.selectable_value(&mut self.tool, Tool::Pen, "Pen");
+                ui.selectable_value(&mut self.tool, Tool::Eraser, "Eraser");
+                match self.tool {
+                    Tool::Select => {}
+                    Tool::Pen => {
+                        ui.color_edit_button_srgba(&mut self.pen_color);
+                        ui.add(egui::Slider::new(&mut self.pen_width, 1.0..=20.0).text("Width"));
+                    }
+                    Tool::Eraser => {
+                        ui.checkbox(&mut self.erase_whole_strokes, "Whole strokes");
+                    }
+                }
+
+                ui.separator();
+                for kind in ShapeKind::ALL {
+                    if ui.button(kind.name()).clicked() {
+                        let center = self.view_center();
+                        self.tool = Tool::Select;
+                        self.add_shape(kind, center);
+                    }
+                }
             });
         });
 
+        if let Some(mut name) = self.renaming_board.take() {
+            let (mut rename, mut cancel) = (false, false);
+            egui::Window::new("Rename board")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+                .show(ctx, |ui| {
+                    let response = ui.text_edit_singleline(&mut name);
+                    rename = response.lost_focus() && ui.input().key_pressed(Key::Enter);
+                    ui.horizontal(|ui| {
+                        rename |= ui.button("Rename").clicked();
+                        cancel = ui.button("Cancel").clicked();
+                    });
+                });
+            if rename && !name.trim().is_empty() {
+                self.rename_board(name.trim().to_string());
+            } else if !cancel {
+                self.renaming_board = Some(name);
+            }
+        }
+
+        if let Some((dialog, mut path)) = self.file_dialog.take() {
+            let (title, action) = match dialog {
+                FileDialog::Open => ("Open board file", "Open"),
+                FileDialog::SaveAs => ("Save board file as", "Save"),
+                FileDialog::ExportSvg => ("Export SVG", "Export"),
+                FileDialog::ExportPng => ("Export PNG", "Export"),
+                FileDialog::ExportJson => ("Export board as JSON", "Export"),
+                FileDialog::ImportJson => ("Import board from JSON", "Import"),
+            };
+            let (mut confirm, mut cancel) = (false, false);
+            egui::Window::new(title)
+                .collapsible(false)
+                .resizable(false)
+                .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+                .show(ctx, |ui| {
+                    if matches!(dialog, FileDialog::ExportSvg | FileDialog::ExportPng) {
+                        let (has_selection, has_frame) = (!self.selected_widgets.is_empty(), self.selected_frame().is_some());
+                        let available = move |scope: ExportScope| match scope {
+                            ExportScope::Selection => has_selection,
+                            ExportScope::Frame => has_frame,
+                            _ => true,
+                        };
+                        if !available(self.export_scope) {
+                            self.export_scope = ExportScope::Board;
+                        }
+                        ui.horizontal(|ui| {
+                            for scope in ExportScope::ALL {
+                                let enabled = available(scope);
+                                ui.add_enabled_ui(enabled, |ui| ui.radio_value(&mut self.export_scope, scope, scope.name()));
+                            }
+                        });
+                    }
+                    if dialog == FileDialog::ExportPng {
+                        ui.horizontal(|ui| {
+                            ui.label("Scale:");
+                            ui.add(egui::DragValue::new(&mut self.export_scale).speed(0.05).clamp_range(0.25..=8.00).suffix("×"));
+                        });
+                    }
+                    ui.label(match dialog {
+                        FileDialog::ExportSvg => "Path to the SVG file:",
+                        FileDialog::ExportPng => "Path to the PNG file:",
+                        FileDialog::ExportJson | FileDialog::ImportJson => "Path to the JSON file:",
+                        _ => "Path to the SQLite database:",
+                    });
+                    let response = ui.add(egui::TextEdit::singleline(&mut path).desired_width(400.0));
+                    confirm = response.lost_focus() && ui.input().key_pressed(Key::Enter);
+                    ui.horizontal(|ui| {
+                        confirm |= ui.button(action).clicked();
+                        cancel = ui.button("Cancel").clicked();
+                    });
+                });
+            if confirm && !path.trim().is_empty() {
+                match dialog {
+                    FileDialog::ExportSvg => self.export_svg(ctx, self.export_scope, PathBuf::from(path.trim())),
+                    FileDialog::ExportPng => self.export_png(ctx, self.export_scope, PathBuf::from(path.trim())),
+                    FileDialog::ExportJson => self.export_json(PathBuf::from(path.trim())),
+                    FileDialog::ImportJson => self.import_json(PathBuf::from(path.trim())),
+                    _ => self.open_database(ctx, dialog, PathBuf::from(path.trim())),
+                }
+            } else if !cancel {
+                self.file_dialog = Some((dialog, path));
+            }
+        }
+
+        if self.deleting_board {
+            let (mut delete, mut cancel) = (false, false);
+            egui::Window::new("Delete board")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.label(format!("Delete \"{}\" and everything on it? This can't be undone.", self.board.name));
+                    ui.horizontal(|ui| {
+                        delete = ui.button("Delete").clicked();
+                        cancel = ui.button("Cancel").clicked();
+                    });
+                });
+            if delete {
+                self.delete_board();
+            }
+            self.deleting_board = !(delete || cancel);
+        }
+
+        if let Some(message) = self.error_banner.message.clone() {
+            egui::TopBottomPanel::top("error_banner").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.colored_label(Color32::RED, message);
+                    if ui.button("Dismiss").clicked() {
+                        self.error_banner.message = None;
+                    }
+                });
+            });
+        }
+
         if self.debug_mode {
             egui::SidePanel::left("side_panel").show(ctx, |ui| {
 
                 ui.heading("Side Panel");
 
-                ui.add(egui::Slider::new(&mut self.pixels_per_point, 0.0..=10.00).text("Pixels Per Point"));
+                // Zooms around the middle of the screen, like the arrow keys.
+                let mut zoom = self.view_state.zoom;
+                if ui.add(egui::Slider::new(&mut zoom, MIN_ZOOM..=MAX_ZOOM).logarithmic(true).text("Zoom")).changed() {
+                    self.view_state.zoom_around(zoom / self.view_state.zoom, screen_rect.center());
+                    self.on_viewport_change();
+                }
 
-                if self.selected_widget != "" {
-                    match self.board_state.positions.get_mut(&self.selected_widget) {
+                if let Some(id) = self.editing_widget().cloned() {
+                    match self.board_state.positions.get_mut(&id) {
                         None => {}
                         Some(block_position) => {
-                            ui.add(egui::Slider::new(&mut block_position.size.x, 0.0..=1000.00).text("Selected Widget Width"));
-                            ui.add(egui::Slider::new(&mut block_position.size.y, 0.0..=1000.00).text("Selected Widget Height"));
+                            let from = block_rect(block_position);
+                            let width = ui.add(egui::Slider::new(&mut block_position.size.x, 0.0..=1000.00).text("Selected Widget Width"));
+                            let height = ui.add(egui::Slider::new(&mut block_position.size.y, 0.0..=1000.00).text("Selected Widget Height"));
+                            if width.drag_started() || height.drag_started() {
+                                self.slider_start = Some(from);
+                            }
+
+                            // Like a resize handle, a whole drag is one undo step, saved when it ends.
+                            let dragging = width.dragged() || height.dragged();
+                            let released = width.drag_released() || height.drag_released();
+                            if released || (!dragging && (width.changed() || height.changed())) {
+                                let from = self.slider_start.take().unwrap_or(from);
+                                let to = block_rect(block_position);
+                                if from != to {
+                                    let result = self.persist.on_size_change(&id, to);
+                                    self.error_banner.report("Could not save", result);
+                                    self.history.record(Command::Resize { id: id.clone(), from, to });
+                                }
+                            }
                         }
                     }
                 }
@@ -480,5 +2305,290 @@ impl eframe::App for App {
                 ui.label(format!("Total Blocks: {}", self.total_blocks));
             });
         }
+
+        if self.selected_widgets != self.last_selected || self.selected_connector != self.last_selected_connector {
+            // Typing into another block starts a new undo step.
+            self.history.seal();
+            self.last_selected = self.selected_widgets.clone();
+            self.last_selected_connector = self.selected_connector.clone();
+        }
+
+        self.markdown.end_frame();
+    }
+
+    /// Stops the loader and writes out the camera and edits still waiting in the write-behind queue.
+    fn on_exit(&mut self, _gl: &eframe::glow::Context) {
+        self.save_view();
+        if let Some(loader) = self.loader.as_mut() {
+            loader.shutdown();
+        }
+        if let Err(e) = self.persist.shutdown() {
+            log::error!("Could not save: {}", e);
+        }
+    }
+}
+
+/// Orders boards the way `Persistor::boards` returns them.
+fn sort_boards(boards: &mut [SavedBoard]) {
+    boards.sort_by(|a, b| (&a.name, &a.id).cmp(&(&b.name, &b.id)));
+}
+
+/// Whether a measured size differs enough from the saved one to be worth
+/// saving. Measurements at other zoom levels are off by fractions of a point.
+fn size_changed(saved: Vec2, measured: Vec2) -> bool {
+    (saved - measured).abs().max_elem() > 1.00
+}
+
+/// Whether only the width of `block` can be resized. Labels and checklists
+/// are as tall as their contents and collapsed frames as their title bar.
+fn has_fixed_height(block: &Block) -> bool {
+    match block.block_type {
+        BlockType::Label | BlockType::Checklist => true,
+        BlockType::Frame => FrameData::is_collapsed(&block.block_data),
+        _ => false,
+    }
+}
+
+/// The canvas style at `zoom`; panels and menus keep the unscaled style.
+fn zoomed_style(style: &egui::Style, zoom: f32) -> egui::Style {
+    let mut style = style.clone();
+    for font_id in style.text_styles.values_mut() {
+        // Rounded so zooming doesn't fill the font atlas with near identical sizes.
+        font_id.size = ((font_id.size * zoom * 2.00).round() / 2.00).max(1.00);
+    }
+    let spacing = &mut style.spacing;
+    spacing.item_spacing *= zoom;
+    spacing.button_padding *= zoom;
+    spacing.interact_size *= zoom;
+    spacing.icon_width *= zoom;
+    spacing.icon_width_inner *= zoom;
+    spacing.icon_spacing *= zoom;
+    spacing.text_edit_width *= zoom;
+    style
+}
+
+/// Reads a dropped PNG or JPEG file and decodes it, returning its bytes
+/// along with the image. Errors name the file.
+fn decode_dropped(file: &DroppedFile) -> Result<(Vec<u8>, RetainedImage), String> {
+    let name = match &file.path {
+        Some(path) => path.display().to_string(),
+        None => file.name.clone(),
+    };
+
+    let bytes = match (&file.bytes, &file.path) {
+        (Some(bytes), _) => bytes.to_vec(),
+        (None, Some(path)) => fs::read(path).map_err(|e| format!("{}: {}", name, e))?,
+        (None, None) => return Err(format!("{}: the file has no contents", name)),
+    };
+
+    if !matches!(image::guess_format(&bytes), Ok(ImageFormat::Png) | Ok(ImageFormat::Jpeg)) {
+        return Err(format!("{}: only PNG and JPEG images are supported", name));
+    }
+
+    let image = RetainedImage::from_image_bytes(name.clone(), &bytes).map_err(|e| format!("{}: {}", name, e))?;
+    Ok((bytes, image))
+}
+
+fn decode_image(persist: &mut Persistor, hash: &str) -> Result<RetainedImage, String> {
+    match persist.load_image(hash) {
+        Ok(Some(bytes)) => RetainedImage::from_image_bytes(hash, &bytes),
+        Ok(None) => Err(format!("image {} is missing from the database", hash)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Style controls shown under a selected shape.
+fn shape_editor(ui: &mut egui::Ui, style: &mut ShapeStyle) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("shape_kind")
+            .selected_text(style.kind.name())
+            .show_ui(ui, |ui| {
+                for kind in ShapeKind::ALL {
+                    ui.selectable_value(&mut style.kind, kind, kind.name());
+                }
+            });
+        ui.label("Fill");
+        ui.color_edit_button_srgba(&mut style.fill);
+        ui.label("Stroke");
+        ui.color_edit_button_srgba(&mut style.stroke);
+        ui.add(egui::DragValue::new(&mut style.stroke_width).clamp_range(0.0..=20.0).speed(0.1));
+    });
+}
+
+/// Inline editor shown halfway along a selected connector.
+fn connector_editor(ui: &mut egui::Ui, connector: &mut Connector) {
+    egui::TextEdit::singleline(&mut connector.label)
+        .hint_text("Label")
+        .ui(ui);
+
+    ui.horizontal(|ui| {
+        for style in ConnectorStyle::ALL {
+            ui.selectable_value(&mut connector.style, style, format!("{:?}", style));
+        }
+    });
+}
+
+/// Draws a checklist block. Items can be checked off and dragged into a new
+/// order by their handle at any time; while `editing` the title and items
+/// are text boxes and Enter adds an item below the current one. The screen
+/// areas that handle presses themselves are added to `controls`.
+fn checklist_ui(ui: &mut egui::Ui, id: &str, data: &mut ChecklistData, editing: bool, zoom: f32, controls: &mut Vec<Rect>) {
+    let item_id = |index: usize| egui::Id::new((id, "checklist item", index));
+    egui::Frame::group(ui.style())
+        .fill(ui.visuals().window_fill())
+        .inner_margin(6.00 * zoom)
+        .show(ui, |ui| {
+            ui.set_width(ui.available_width());
+            ui.with_layout(egui::Layout::right_to_left(), |ui| {
+                ui.label(egui::RichText::new(data.progress()).weak());
+                // The title takes the rest of the row, from the left.
+                ui.with_layout(egui::Layout::left_to_right(), |ui| {
+                    if editing {
+                        let title = egui::TextEdit::singleline(&mut data.title)
+                            .hint_text("Title")
+                            .desired_width(ui.available_width())
+                            .ui(ui);
+                        controls.push(title.rect);
+                    } else {
+                        ui.add(egui::Label::new(egui::RichText::new(&data.title).strong()).wrap(true));
+                    }
+                });
+            });
+
+            let mut rows = Vec::new();
+            let (mut dragged, mut dropped, mut insert_at, mut remove) = (None, None, None, None);
+            for (index, item) in data.items.iter_mut().enumerate() {
+                let row = ui.horizontal(|ui| {
+                    let handle = drag_handle(ui, egui::Id::new((id, "checklist handle", index)));
+                    controls.push(handle.rect);
+                    if handle.dragged() {
+                        dragged = Some(index);
+                    }
+                    if handle.drag_released() {
+                        dropped = Some(index);
+                    }
+
+                    let checkbox = ui.checkbox(&mut item.done, "");
+                    controls.push(checkbox.rect);
+
+                    if editing {
+                        ui.with_layout(egui::Layout::right_to_left(), |ui| {
+                            let delete = ui.small_button("✖").on_hover_text("Remove item");
+                            controls.push(delete.rect);
+                            if delete.clicked() {
+                                remove = Some(index);
+                            }
+                            let text = egui::TextEdit::singleline(&mut item.text)
+                                .id(item_id(index))
+                                .hint_text("Item")
+                                .desired_width(ui.available_width())
+                                .ui(ui);
+                            controls.push(text.rect);
+                            if text.lost_focus() && ui.input().key_pressed(Key::Enter) {
+                                insert_at = Some(index + 1);
+                            }
+                        });
+                    } else {
+                        let text = match item.done {
+                            true => egui::RichText::new(&item.text).strikethrough().weak(),
+                            false => egui::RichText::new(&item.text),
+                        };
+                        let label = ui.add(egui::Label::new(text).wrap(true).sense(egui::Sense::click()));
+                        controls.push(label.rect);
+                        if label.clicked() {
+                            item.done = !item.done;
+                        }
+                    }
+                });
+                rows.push(row.response.rect);
+            }
+
+            if editing {
+                let add = ui.button("Add item");
+                controls.push(add.rect);
+                if add.clicked() {
+                    insert_at = Some(data.items.len());
+                }
+            }
+
+            // Dragged items go to the gap nearest the pointer.
+            let pointer = ui.input().pointer.interact_pos();
+            if let (Some(from), Some(pointer)) = (dragged.or(dropped), pointer) {
+                let to = rows.iter().filter(|row| row.center().y < pointer.y).count();
+                match dropped {
+                    Some(_) => data.move_item(from, to),
+                    None => {
+                        let y = rows.get(to).map_or_else(|| rows[rows.len() - 1].bottom(), |row| row.top());
+                        ui.painter().hline(ui.min_rect().x_range(), y, (2.0, ui.visuals().selection.bg_fill));
+                    }
+                }
+            }
+            if let Some(index) = remove {
+                data.items.remove(index);
+            }
+            if let Some(index) = insert_at {
+                data.items.insert(index, ChecklistItem::new(""));
+                ui.memory().request_focus(item_id(index));
+            }
+        });
+}
+
+/// A grip of dots to drag checklist items by.
+fn drag_handle(ui: &mut egui::Ui, id: egui::Id) -> egui::Response {
+    let size = Vec2::new(ui.spacing().icon_width * 0.60, ui.spacing().interact_size.y);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let response = ui.interact(rect, id, egui::Sense::drag());
+    let color = ui.style().interact(&response).fg_stroke.color;
+    let spacing = rect.width() / 3.00;
+    for column in [-0.50, 0.50] {
+        for row in [-1.00, 0.00, 1.00] {
+            ui.painter().circle_filled(rect.center() + Vec2::new(column, row) * spacing, spacing / 3.00, color);
+        }
+    }
+    match response.dragged() {
+        true => response.on_hover_cursor(egui::CursorIcon::Grabbing),
+        false => response.on_hover_cursor(egui::CursorIcon::Grab),
+    }
+}
+
+/// Inline editor shown in place of a selected button block.
+fn button_editor(ui: &mut egui::Ui, data: &mut ButtonData, view_center: Pos2) {
+    egui::TextEdit::singleline(&mut data.label)
+        .hint_text("Button label")
+        .ui(ui);
+
+    ui.horizontal(|ui| {
+        let action = &mut data.action;
+        if ui.selectable_label(*action == ButtonAction::None, "None").clicked() {
+            *action = ButtonAction::None;
+        }
+        if ui.selectable_label(matches!(action, ButtonAction::OpenUrl(_)), "Open URL").clicked() && !matches!(action, ButtonAction::OpenUrl(_)) {
+            *action = ButtonAction::OpenUrl(String::new());
+        }
+        if ui.selectable_label(matches!(action, ButtonAction::JumpTo(_)), "Jump").clicked() && !matches!(action, ButtonAction::JumpTo(_)) {
+            *action = ButtonAction::JumpTo(Pos2::ZERO);
+        }
+        if ui.selectable_label(matches!(action, ButtonAction::Toggle(_)), "Toggle").clicked() && !matches!(action, ButtonAction::Toggle(_)) {
+            *action = ButtonAction::Toggle(false);
+        }
+    });
+
+    match &mut data.action {
+        ButtonAction::None => {}
+        ButtonAction::OpenUrl(url) => {
+            egui::TextEdit::singleline(url).hint_text("https://").ui(ui);
+        }
+        ButtonAction::JumpTo(point) => {
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut point.x).prefix("x: "));
+                ui.add(egui::DragValue::new(&mut point.y).prefix("y: "));
+                if ui.button("Here").on_hover_text("Jump to the current view").clicked() {
+                    *point = view_center;
+                }
+            });
+        }
+        ButtonAction::Toggle(state) => {
+            ui.checkbox(state, "Checked");
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::demo::{BlockType, Connector};
+use crate::export::Snapshot;
+use crate::persistor::{PersistError, Persistor, SavedBlock};
+
+/// Value of the `format` field, so other JSON files are rejected.
+const FORMAT: &str = "boardx-board";
+
+/// The newest format version this build writes and reads. Bump it whenever
+/// a change would make older builds misread a file.
+pub const VERSION: u32 = 2;
+
+/// A whole board as a portable JSON document.
+///
+/// ```json
+/// {
+///   "format": "boardx-board",
+///   "version": 2,
+///   "name": "Planning",
+///   "blocks": [
+///     {
+///       "size": { "x": 160.0, "y": 100.0 },
+///       "position": { "x": 40.0, "y": -20.0 },
+///       "id": "4b1d…",
+///       "type": "shape",
+///       "data": "Text on the shape",
+///       "style": { "kind": "ellipse", "fill": [255, 255, 255, 255], "stroke": [64, 64, 64, 255], "stroke_width": 2.0 }
+///     }
+///   ],
+///   "connectors": [
+///     { "id": "9f2c…", "from": "4b1d…", "to": "77e0…", "style": "elbow", "label": "" }
+///   ],
+///   "images": { "<content hash>": "<base64 bytes>" }
+/// }
+/// ```
+///
+/// Positions and sizes are in board units. `type` is one of `label`,
+/// `button`, `image`, `stroke`, `shape`, `checklist` and `frame`; `data` is
+/// stored as on the board, so it is the text of labels and shapes, the
+/// content hash of images and the encoded `StrokeData` of strokes,
+/// `ChecklistData` of checklists and `FrameData` of frames. Blocks inside a
+/// frame have the frame's id as their `parent`. `style` is only there for
+/// shapes, with colors as premultiplied RGBA. `kind` is one of `rectangle`,
+/// `rounded_rectangle`, `ellipse`, `diamond` and `sticky_note`, and a
+/// connector's `style` one of `straight`, `elbow` and `curved`. `connectors`
+/// and `images` may be left out.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct BoardFile {
+    format: String,
+    version: u32,
+    pub(crate) name: String,
+    pub(crate) blocks: Vec<SavedBlock>,
+    #[serde(default)]
+    pub(crate) connectors: Vec<Connector>,
+    /// Bytes of the images used by image blocks, base64 encoded, by content hash.
+    #[serde(default)]
+    pub(crate) images: BTreeMap<String, String>,
+}
+
+/// Why a file could not be read as a board.
+#[derive(Debug)]
+pub enum BoardFileError {
+    /// The file isn't valid JSON or doesn't have the expected fields.
+    Json(serde_json::Error),
+    /// Valid JSON, but not a board file.
+    NotABoard,
+    /// Written by a newer build in a format this one doesn't know.
+    Version(u32),
+    /// An image in the file isn't valid base64.
+    Image(String),
+}
+
+impl Display for BoardFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoardFileError::Json(e) => write!(f, "not a readable board file: {}", e),
+            BoardFileError::NotABoard => write!(f, "not a board file"),
+            BoardFileError::Version(version) => {
+                write!(f, "the file is version {} of the board format, this build only reads up to {}", version, VERSION)
+            }
+            BoardFileError::Image(hash) => write!(f, "image {} is damaged", hash),
+        }
+    }
+}
+
+impl std::error::Error for BoardFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BoardFileError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl BoardFile {
+    /// The board `name` made of the contents of `snapshot`.
+    pub fn new(name: &str, snapshot: Snapshot) -> BoardFile {
+        BoardFile {
+            format: String::from(FORMAT),
+            version: VERSION,
+            name: name.to_string(),
+            blocks: snapshot.blocks,
+            connectors: snapshot.connectors,
+            images: snapshot.images.iter().map(|(hash, bytes)| (hash.clone(), base64::encode(bytes))).collect(),
+        }
+    }
+
+    /// Everything on the board `id`, to be written out as `name`.
+    pub fn export(persist: &mut Persistor, id: &str, name: &str) -> Result<BoardFile, PersistError> {
+        Ok(BoardFile::new(name, Snapshot::board(persist, id)?))
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string_pretty(self).expect("boards always serialize")
+    }
+
+    pub fn parse(text: &str) -> Result<BoardFile, BoardFileError> {
+        // Check the header on its own first, so a file from a newer build
+        // says so rather than failing on whatever field changed.
+        #[derive(Deserialize)]
+        struct Header {
+            format: String,
+            version: u32,
+        }
+        let header: Header = serde_json::from_str(text).map_err(|e| match e.classify() {
+            serde_json::error::Category::Data => BoardFileError::NotABoard,
+            _ => BoardFileError::Json(e),
+        })?;
+        if header.format != FORMAT {
+            return Err(BoardFileError::NotABoard);
+        }
+        if header.version > VERSION {
+            return Err(BoardFileError::Version(header.version));
+        }
+        serde_json::from_str(text).map_err(BoardFileError::Json)
+    }
+
+    /// The decoded bytes of every image, by the hash used in the file.
+    pub fn image_bytes(&self) -> Result<Vec<(String, Vec<u8>)>, BoardFileError> {
+        self.images
+            .iter()
+            .map(|(hash, data)| match base64::decode(data) {
+                Ok(bytes) => Ok((hash.clone(), bytes)),
+                Err(_) => Err(BoardFileError::Image(hash.clone())),
+            })
+            .collect()
+    }
+
+    /// Gives every block and connector a new id, so the board can be
+    /// imported next to the one it was exported from, see `remap_ids`.
+    pub fn remap_ids(&mut self) {
+        remap_ids(&mut self.blocks, &mut self.connectors);
+    }
+
+    /// Points image blocks at the hashes their images were saved under.
+    pub fn rehash_images(&mut self, hashes: &HashMap<String, String>) {
+        for block in self.blocks.iter_mut().filter(|b| b.block_type == BlockType::Image) {
+            if let Some(hash) = hashes.get(&block.block_data) {
+                block.block_data = hash.clone();
+            }
+        }
+    }
+}
+
+/// Gives every block and connector a new id. Connectors and blocks in frames
+/// are pointed at the new ids; connectors to blocks that aren't among
+/// `blocks` are dropped, and blocks in frames that aren't taken out of them.
+pub(crate) fn remap_ids(blocks: &mut [SavedBlock], connectors: &mut Vec<Connector>) {
+    let mut ids = HashMap::new();
+    for block in blocks.iter_mut() {
+        let id = Uuid::new_v4().to_string();
+        ids.insert(std::mem::replace(&mut block.id, id.clone()), id);
+    }
+    for block in blocks.iter_mut() {
+        block.parent = block.parent.as_ref().and_then(|parent| ids.get(parent)).cloned();
+    }
+    *connectors = std::mem::take(connectors)
+        .into_iter()
+        .filter_map(|connector| {
+            Some(Connector {
+                id: Uuid::new_v4().to_string(),
+                from: ids.get(&connector.from)?.clone(),
+                to: ids.get(&connector.to)?.clone(),
+                ..connector
+            })
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::{ConnectorStyle, ShapeKind, ShapeStyle};
+    use crate::test_util::{block, TempDir};
+    use egui::{Pos2, Rect, Vec2};
+
+    fn board() -> BoardFile {
+        let blocks = vec![
+            block("a", BlockType::Label, "quotes \" and\nnew lines", Pos2::new(150.0, -20.0), Vec2::new(300.0, 80.0)),
+            SavedBlock {
+                style: Some(ShapeStyle::new(ShapeKind::StickyNote)),
+                ..block("b", BlockType::Shape, "note", Pos2::new(400.0, 0.0), Vec2::new(160.0, 160.0))
+            },
+            block("c", BlockType::Image, "abc123", Pos2::new(100.0, 40.0), Vec2::new(64.0, 64.0)),
+        ];
+        let connectors = vec![Connector {
+            id: String::from("ab"),
+            from: String::from("a"),
+            to: String::from("b"),
+            style: ConnectorStyle::Elbow,
+            label: String::from("next"),
+        }];
+        let mut images = HashMap::new();
+        images.insert(String::from("abc123"), vec![0, 1, 2, 255]);
+        let snapshot = Snapshot {
+            bounds: Rect::NOTHING,
+            blocks,
+            connectors,
+            images,
+        };
+        BoardFile::new("Plans", snapshot)
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        let board = board();
+        let text = board.encode();
+        assert!(text.contains(r#""type": "shape""#));
+        assert!(text.contains(r#""kind": "sticky_note""#));
+
+        let parsed = BoardFile::parse(&text).unwrap();
+        assert_eq!(parsed, board);
+        assert_eq!(parsed.image_bytes().unwrap(), [(String::from("abc123"), vec![0, 1, 2, 255])]);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(matches!(BoardFile::parse("not json"), Err(BoardFileError::Json(_))));
+        assert!(matches!(BoardFile::parse(r#"{"hello": "world"}"#), Err(BoardFileError::NotABoard)));
+        assert!(matches!(
+            BoardFile::parse(r#"{"format": "boardx-board", "version": 99, "whatever": []}"#),
+            Err(BoardFileError::Version(99))
+        ));
+        // Optional fields may be left out.
+        let minimal = BoardFile::parse(r#"{"format": "boardx-board", "version": 2, "name": "Empty", "blocks": []}"#).unwrap();
+        assert!(minimal.connectors.is_empty());
+    }
+
+    #[test]
+    fn only_newer_versions_are_rejected() {
+        let file = |version: u32| format!(r#"{{"format": "boardx-board", "version": {}, "name": "Empty", "blocks": []}}"#, version);
+        assert!(matches!(BoardFile::parse(&file(VERSION + 1)), Err(BoardFileError::Version(3))));
+        assert_eq!(BoardFile::parse(&file(1)).unwrap().version, 1);
+        assert_eq!(BoardFile::parse(&file(VERSION)).unwrap().version, VERSION);
+    }
+
+    #[test]
+    fn imported_ids_are_new() {
+        let mut board = board();
+        board.connectors.push(Connector {
+            id: String::from("dangling"),
+            from: String::from("a"),
+            to: String::from("gone"),
+            style: ConnectorStyle::Straight,
+            label: String::new(),
+        });
+        board.blocks[1].block_type = BlockType::Frame;
+        board.blocks[0].parent = Some(String::from("b"));
+        board.blocks[2].parent = Some(String::from("gone"));
+        board.remap_ids();
+
+        assert!(board.blocks.iter().all(|b| !["a", "b", "c"].contains(&b.id.as_str())));
+        assert_eq!(board.blocks[0].parent.as_ref(), Some(&board.blocks[1].id));
+        assert_eq!(board.blocks[2].parent, None);
+        assert_eq!(board.connectors.len(), 1);
+        assert_eq!(board.connectors[0].from, board.blocks[0].id);
+        assert_eq!(board.connectors[0].to, board.blocks[1].id);
+        assert_ne!(board.connectors[0].id, "ab");
+    }
+
+    #[test]
+    fn boards_round_trip_through_the_database() {
+        let dir = TempDir::new();
+        let mut persist = Persistor::new(dir.join("board.db"));
+        persist.setup().unwrap();
+        let board = persist.last_board().unwrap();
+
+        let mut original = self::board();
+        let hashes: HashMap<String, String> = original
+            .image_bytes()
+            .unwrap()
+            .iter()
+            .map(|(hash, bytes)| (hash.clone(), persist.save_image(bytes).unwrap()))
+            .collect();
+        original.rehash_images(&hashes);
+        for block in &original.blocks {
+            persist.on_add(block.clone()).unwrap();
+        }
+        for connector in &original.connectors {
+            persist.on_add_connector(connector).unwrap();
+        }
+
+        let mut exported = BoardFile::export(&mut persist, &board.id, "Plans").unwrap();
+        exported.blocks.sort_by(|a, b| a.id.cmp(&b.id));
+        let saved_hash = hashes["abc123"].clone();
+        assert_eq!(exported.image_bytes().unwrap(), [(saved_hash, vec![0, 1, 2, 255])]);
+        exported.images = original.images.clone();
+        assert_eq!(exported, original);
+    }
+}
@@ -0,0 +1,104 @@
+use egui::Pos2;
+
+/// What happens when a button block is clicked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ButtonAction {
+    None,
+    OpenUrl(String),
+    /// Centers the view on this board position.
+    JumpTo(Pos2),
+    /// A checkbox-style on/off state shown next to the label.
+    Toggle(bool),
+}
+
+/// The contents of a `BlockType::Button` block.
+///
+/// Stored in `block_data` as the encoded action on the first line followed by
+/// the label, so labels may span several lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ButtonData {
+    pub(crate) label: String,
+    pub(crate) action: ButtonAction,
+}
+
+impl Default for ButtonData {
+    fn default() -> Self {
+        Self {
+            label: String::from("Button"),
+            action: ButtonAction::None,
+        }
+    }
+}
+
+impl ButtonData {
+    pub fn parse(data: &str) -> ButtonData {
+        let (action, label) = match data.split_once('\n') {
+            Some((action, label)) => (action, label),
+            None => ("", data),
+        };
+
+        let action = match action.split_once(':') {
+            Some(("url", url)) => ButtonAction::OpenUrl(url.to_string()),
+            Some(("jump", point)) => point
+                .split_once(',')
+                .and_then(|(x, y)| Some(Pos2::new(x.parse().ok()?, y.parse().ok()?)))
+                .map_or(ButtonAction::None, ButtonAction::JumpTo),
+            Some(("toggle", state)) => ButtonAction::Toggle(state == "true"),
+            _ => ButtonAction::None,
+        };
+
+        ButtonData {
+            label: label.to_string(),
+            action,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let action = match &self.action {
+            ButtonAction::None => String::new(),
+            ButtonAction::OpenUrl(url) => format!("url:{}", url),
+            ButtonAction::JumpTo(point) => format!("jump:{},{}", point.x, point.y),
+            ButtonAction::Toggle(state) => format!("toggle:{}", state),
+        };
+        format!("{}\n{}", action, self.label)
+    }
+
+    /// The text drawn on the button itself.
+    pub fn caption(&self) -> String {
+        match self.action {
+            ButtonAction::Toggle(true) => format!("☑ {}", self.label),
+            ButtonAction::Toggle(false) => format!("☐ {}", self.label),
+            _ => self.label.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_round_trips() {
+        let actions = [
+            ButtonAction::None,
+            ButtonAction::OpenUrl(String::from("https://example.com/a:b")),
+            ButtonAction::JumpTo(Pos2::new(-120.5, 3000.0)),
+            ButtonAction::Toggle(true),
+        ];
+
+        for action in actions {
+            let data = ButtonData {
+                label: String::from("two\nlines"),
+                action,
+            };
+            assert_eq!(ButtonData::parse(&data.encode()), data);
+        }
+    }
+
+    #[test]
+    fn plain_text_is_a_label() {
+        let data = ButtonData::parse("Click me");
+        assert_eq!(data.label, "Click me");
+        assert_eq!(data.action, ButtonAction::None);
+    }
+}
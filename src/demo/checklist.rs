@@ -0,0 +1,236 @@
+/// One entry of a checklist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecklistItem {
+    pub(crate) text: String,
+    pub(crate) done: bool,
+}
+
+impl ChecklistItem {
+    pub fn new(text: &str) -> ChecklistItem {
+        ChecklistItem {
+            text: text.to_string(),
+            done: false,
+        }
+    }
+}
+
+/// The contents of a `BlockType::Checklist` block.
+///
+/// Kept in `block_data` as the title on the first line followed by a line
+/// per item: `[x] ` for checked items or `[ ] ` for open ones, then the item's
+/// text. Other lines are read as open items, so text pasted in as data still
+/// makes a usable list. The database stores only the title with the block
+/// and each item in a row of its own; see `ChecklistChange`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecklistData {
+    pub(crate) title: String,
+    pub(crate) items: Vec<ChecklistItem>,
+}
+
+impl Default for ChecklistData {
+    fn default() -> Self {
+        Self {
+            title: String::from("Checklist"),
+            items: vec![ChecklistItem::new("")],
+        }
+    }
+}
+
+impl ChecklistData {
+    pub fn parse(data: &str) -> ChecklistData {
+        let mut lines = data.lines();
+        let title = lines.next().unwrap_or_default().to_string();
+        let items = lines
+            .map(|line| {
+                let (done, text) = match (line.strip_prefix("[x] ").or_else(|| line.strip_prefix("[X] ")), line.strip_prefix("[ ] ")) {
+                    (Some(text), _) => (true, text),
+                    (None, Some(text)) => (false, text),
+                    (None, None) => (false, line),
+                };
+                ChecklistItem {
+                    text: text.to_string(),
+                    done,
+                }
+            })
+            .collect();
+        ChecklistData { title, items }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut data = single_line(&self.title);
+        for item in &self.items {
+            data.push_str(if item.done { "\n[x] " } else { "\n[ ] " });
+            data.push_str(&single_line(&item.text));
+        }
+        data
+    }
+
+    /// How many items are checked, out of how many, as shown in the header.
+    pub fn progress(&self) -> String {
+        let done = self.items.iter().filter(|item| item.done).count();
+        format!("{}/{}", done, self.items.len())
+    }
+
+    /// Moves the item at `from` so it ends up just before the item that is
+    /// at `to` now, or last if `to` is past the end.
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        if from >= self.items.len() {
+            return;
+        }
+        let item = self.items.remove(from);
+        let to = if to > from { to - 1 } else { to };
+        self.items.insert(to.min(self.items.len()), item);
+    }
+
+    /// The edits that turn this checklist into `to`. A single check, text
+    /// edit, move, insert or removal, which is all one frame on the canvas
+    /// does, is found as such; anything more rewrites every item.
+    pub fn changes(&self, to: &ChecklistData) -> Vec<ChecklistChange> {
+        let mut changes = Vec::new();
+        if self.title != to.title {
+            changes.push(ChecklistChange::Title(to.title.clone()));
+        }
+
+        let (old, new) = (&self.items, &to.items);
+        let first = old.iter().zip(new).position(|(a, b)| a != b).unwrap_or_else(|| old.len().min(new.len()));
+        if old.len() == new.len() {
+            let last = (0..old.len()).rev().find(|i| old[*i] != new[*i]).unwrap_or(first);
+            if first == old.len() {
+                // Nothing changed.
+            } else if first < last && old[first] == new[last] && old[first + 1..=last] == new[first..last] {
+                changes.push(ChecklistChange::Move { from: first, to: last });
+            } else if first < last && old[last] == new[first] && old[first..last] == new[first + 1..=last] {
+                changes.push(ChecklistChange::Move { from: last, to: first });
+            } else {
+                changes.extend(
+                    (first..=last)
+                        .filter(|i| old[*i] != new[*i])
+                        .map(|index| ChecklistChange::Item { index, item: new[index].clone() }),
+                );
+            }
+        } else if new.len() == old.len() + 1 && old[first..] == new[first + 1..] {
+            changes.push(ChecklistChange::Insert { index: first, item: new[first].clone() });
+        } else if old.len() == new.len() + 1 && old[first + 1..] == new[first..] {
+            changes.push(ChecklistChange::Remove(first));
+        } else {
+            changes.push(ChecklistChange::Items(new.clone()));
+        }
+        changes
+    }
+}
+
+/// One edit to a checklist, as found by `ChecklistData::changes`. Each is
+/// saved on its own, so checking an item off or moving it only writes the
+/// rows of the items involved.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChecklistChange {
+    Title(String),
+    /// The item at `index` now reads or is checked differently.
+    Item { index: usize, item: ChecklistItem },
+    Insert { index: usize, item: ChecklistItem },
+    Remove(usize),
+    /// The item at `from` ends up at `to`, the items in between shifting over.
+    Move { from: usize, to: usize },
+    /// Anything else, with every item as it is now.
+    Items(Vec<ChecklistItem>),
+}
+
+impl ChecklistChange {
+    pub fn apply(&self, data: &mut ChecklistData) {
+        match self {
+            ChecklistChange::Title(title) => data.title = title.clone(),
+            ChecklistChange::Item { index, item } => {
+                if let Some(old) = data.items.get_mut(*index) {
+                    *old = item.clone();
+                }
+            }
+            ChecklistChange::Insert { index, item } => data.items.insert((*index).min(data.items.len()), item.clone()),
+            ChecklistChange::Remove(index) => {
+                if *index < data.items.len() {
+                    data.items.remove(*index);
+                }
+            }
+            ChecklistChange::Move { from, to } => {
+                if *from < data.items.len() {
+                    let item = data.items.remove(*from);
+                    data.items.insert((*to).min(data.items.len()), item);
+                }
+            }
+            ChecklistChange::Items(items) => data.items = items.clone(),
+        }
+    }
+}
+
+/// Titles and items are one line each; pasted line breaks become spaces.
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_round_trips() {
+        let data = ChecklistData {
+            title: String::from("Stand-up"),
+            items: vec![
+                ChecklistItem { text: String::from("[x] looks checked"), done: false },
+                ChecklistItem { text: String::from("done"), done: true },
+                ChecklistItem::new(""),
+            ],
+        };
+        assert_eq!(data.encode(), "Stand-up\n[ ] [x] looks checked\n[x] done\n[ ] ");
+        assert_eq!(ChecklistData::parse(&data.encode()), data);
+        assert_eq!(data.progress(), "1/3");
+
+        let multiline = ChecklistData { title: String::from("two\nlines"), items: Vec::new() };
+        assert_eq!(ChecklistData::parse(&multiline.encode()).title, "two lines");
+    }
+
+    #[test]
+    fn plain_lines_are_open_items() {
+        let data = ChecklistData::parse("Groceries\nmilk\n[X] eggs");
+        assert_eq!(data.items, vec![ChecklistItem::new("milk"), ChecklistItem { text: String::from("eggs"), done: true }]);
+    }
+
+    #[test]
+    fn items_move_to_the_gap() {
+        let mut data = ChecklistData::parse("List\na\nb\nc\nd");
+        let texts = |data: &ChecklistData| data.items.iter().map(|i| i.text.clone()).collect::<String>();
+
+        data.move_item(0, 2);
+        assert_eq!(texts(&data), "bacd");
+        data.move_item(3, 0);
+        assert_eq!(texts(&data), "dbac");
+        data.move_item(1, 99);
+        assert_eq!(texts(&data), "dacb");
+        data.move_item(2, 2);
+        assert_eq!(texts(&data), "dacb");
+    }
+
+    #[test]
+    fn single_edits_are_found() {
+        let before = ChecklistData::parse("List\n[ ] a\n[ ] b\n[ ] c\n[ ] d");
+        let change = |edit: &dyn Fn(&mut ChecklistData)| {
+            let mut after = before.clone();
+            edit(&mut after);
+            let changes = before.changes(&after);
+            let mut applied = before.clone();
+            for change in &changes {
+                change.apply(&mut applied);
+            }
+            assert_eq!(applied, after);
+            changes
+        };
+
+        assert_eq!(change(&|_| {}), []);
+        assert_eq!(change(&|d| d.items[1].done = true), [ChecklistChange::Item { index: 1, item: ChecklistItem { text: String::from("b"), done: true } }]);
+        assert_eq!(change(&|d| d.move_item(0, 3)), [ChecklistChange::Move { from: 0, to: 2 }]);
+        assert_eq!(change(&|d| d.move_item(3, 1)), [ChecklistChange::Move { from: 3, to: 1 }]);
+        assert_eq!(change(&|d| d.items.insert(4, ChecklistItem::new("e"))), [ChecklistChange::Insert { index: 4, item: ChecklistItem::new("e") }]);
+        assert_eq!(change(&|d| drop(d.items.remove(1))), [ChecklistChange::Remove(1)]);
+        assert_eq!(change(&|d| d.title = String::from("Renamed")), [ChecklistChange::Title(String::from("Renamed"))]);
+        assert!(matches!(change(&|d| d.items.truncate(1))[..], [ChecklistChange::Items(_)]));
+    }
+}
@@ -0,0 +1,221 @@
+use egui::{Color32, Pos2, Rect, Shape, Stroke, Vec2};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+/// Points sampled along a curved connector.
+const CURVE_STEPS: usize = 24;
+
+/// Length of a connector's arrow head, in board units.
+pub const ARROW_SIZE: f32 = 10.00;
+
+/// How a connector is routed between its two blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectorStyle {
+    Straight,
+    /// Horizontal and vertical segments only.
+    Elbow,
+    Curved,
+}
+
+impl ConnectorStyle {
+    pub const ALL: [ConnectorStyle; 3] = [ConnectorStyle::Straight, ConnectorStyle::Elbow, ConnectorStyle::Curved];
+
+    /// The value stored in the `style` column. Like `BlockType::code`, these
+    /// are part of the on-disk format.
+    pub fn code(self) -> i64 {
+        match self {
+            ConnectorStyle::Straight => 0,
+            ConnectorStyle::Elbow => 1,
+            ConnectorStyle::Curved => 2,
+        }
+    }
+
+    pub fn from_code(code: i64) -> Option<ConnectorStyle> {
+        match code {
+            0 => Some(ConnectorStyle::Straight),
+            1 => Some(ConnectorStyle::Elbow),
+            2 => Some(ConnectorStyle::Curved),
+            _ => None,
+        }
+    }
+}
+
+impl ToSql for ConnectorStyle {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.code()))
+    }
+}
+
+impl FromSql for ConnectorStyle {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let code = value.as_i64()?;
+        ConnectorStyle::from_code(code).ok_or(FromSqlError::OutOfRange(code))
+    }
+}
+
+/// An arrow from the block `from` to the block `to`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Connector {
+    pub(crate) id: String,
+    pub(crate) from: String,
+    pub(crate) to: String,
+    pub(crate) style: ConnectorStyle,
+    /// Drawn halfway along the arrow; empty for none.
+    #[serde(default)]
+    pub(crate) label: String,
+}
+
+impl Connector {
+    /// The arrow between the blocks at `from` and `to` as a polyline, starting
+    /// and ending on the blocks' edges.
+    pub fn path(&self, from: Rect, to: Rect) -> Vec<Pos2> {
+        let delta = to.center() - from.center();
+        match self.style {
+            ConnectorStyle::Straight => vec![edge_point(from, to.center()), edge_point(to, from.center())],
+            ConnectorStyle::Elbow => {
+                let (start, end) = facing_sides(from, to);
+                if delta.x.abs() >= delta.y.abs() {
+                    let middle = (start.x + end.x) / 2.0;
+                    vec![start, Pos2::new(middle, start.y), Pos2::new(middle, end.y), end]
+                } else {
+                    let middle = (start.y + end.y) / 2.0;
+                    vec![start, Pos2::new(start.x, middle), Pos2::new(end.x, middle), end]
+                }
+            }
+            ConnectorStyle::Curved => {
+                let (start, end) = facing_sides(from, to);
+                let pull = if delta.x.abs() >= delta.y.abs() {
+                    Vec2::new((end.x - start.x) / 2.0, 0.0)
+                } else {
+                    Vec2::new(0.0, (end.y - start.y) / 2.0)
+                };
+                let (c1, c2) = (start + pull, end - pull);
+                (0..=CURVE_STEPS)
+                    .map(|i| {
+                        let t = i as f32 / CURVE_STEPS as f32;
+                        let u = 1.0 - t;
+                        let point = start.to_vec2() * (u * u * u)
+                            + c1.to_vec2() * (3.0 * u * u * t)
+                            + c2.to_vec2() * (3.0 * u * t * t)
+                            + end.to_vec2() * (t * t * t);
+                        point.to_pos2()
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Where the line from the center of `rect` towards `toward` leaves the rect.
+fn edge_point(rect: Rect, toward: Pos2) -> Pos2 {
+    let direction = toward - rect.center();
+    let half = rect.size() / 2.0;
+    let scale_x = if direction.x != 0.0 { half.x / direction.x.abs() } else { f32::INFINITY };
+    let scale_y = if direction.y != 0.0 { half.y / direction.y.abs() } else { f32::INFINITY };
+    let scale = scale_x.min(scale_y);
+    if scale.is_finite() {
+        rect.center() + direction * scale
+    } else {
+        rect.center()
+    }
+}
+
+/// The middles of the sides of `from` and `to` that face each other along
+/// the axis the blocks are furthest apart on.
+fn facing_sides(from: Rect, to: Rect) -> (Pos2, Pos2) {
+    let delta = to.center() - from.center();
+    if delta.x.abs() >= delta.y.abs() {
+        match delta.x >= 0.0 {
+            true => (from.right_center(), to.left_center()),
+            false => (from.left_center(), to.right_center()),
+        }
+    } else {
+        match delta.y >= 0.0 {
+            true => (from.center_bottom(), to.center_top()),
+            false => (from.center_top(), to.center_bottom()),
+        }
+    }
+}
+
+/// A connector line along `path` with an arrow head at its end, drawn at `zoom`.
+pub fn arrow_shapes(path: &[Pos2], color: Color32, zoom: f32) -> Vec<Shape> {
+    let mut shapes = vec![Shape::line(path.to_vec(), Stroke::new(1.5, color))];
+    if let [.., before, tip] = path {
+        let direction = (*tip - *before).normalized();
+        let size = ARROW_SIZE * zoom;
+        let back = *tip - direction * size;
+        let side = direction.rot90() * size / 2.0;
+        shapes.push(Shape::convex_polygon(vec![*tip, back + side, back - side], color, Stroke::none()));
+    }
+    shapes
+}
+
+/// The point halfway along `path`, measured by length.
+pub fn path_midpoint(path: &[Pos2]) -> Pos2 {
+    let length: f32 = path.windows(2).map(|s| s[0].distance(s[1])).sum();
+    let mut remaining = length / 2.0;
+    for segment in path.windows(2) {
+        let segment_length = segment[0].distance(segment[1]);
+        if segment_length >= remaining && segment_length > 0.0 {
+            return segment[0] + (segment[1] - segment[0]) * (remaining / segment_length);
+        }
+        remaining -= segment_length;
+    }
+    path.first().copied().unwrap_or_default()
+}
+
+/// The shortest distance from `point` to any segment of `path`.
+pub fn distance_to_path(path: &[Pos2], point: Pos2) -> f32 {
+    path.windows(2)
+        .map(|segment| {
+            let (a, b) = (segment[0], segment[1]);
+            let ab = b - a;
+            let t = if ab.length_sq() > 0.0 { ((point - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0) } else { 0.0 };
+            point.distance(a + ab * t)
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connector(style: ConnectorStyle) -> Connector {
+        Connector {
+            id: String::from("c"),
+            from: String::from("a"),
+            to: String::from("b"),
+            style,
+            label: String::new(),
+        }
+    }
+
+    #[test]
+    fn paths_end_on_block_edges() {
+        let from = Rect::from_min_size(Pos2::new(0.0, 0.0), Vec2::new(100.0, 50.0));
+        let to = Rect::from_min_size(Pos2::new(300.0, 100.0), Vec2::new(100.0, 50.0));
+
+        let straight = connector(ConnectorStyle::Straight).path(from, to);
+        assert!((straight[0] - Pos2::new(100.0, 25.0 + 50.0 / 3.0)).length() < 0.001);
+        assert!((straight[1] - Pos2::new(300.0, 125.0 - 50.0 / 3.0)).length() < 0.001);
+
+        let elbow = connector(ConnectorStyle::Elbow).path(from, to);
+        assert_eq!(
+            elbow,
+            vec![Pos2::new(100.0, 25.0), Pos2::new(200.0, 25.0), Pos2::new(200.0, 125.0), Pos2::new(300.0, 125.0)]
+        );
+
+        let curved = connector(ConnectorStyle::Curved).path(from, to);
+        assert_eq!(curved.first(), Some(&from.right_center()));
+        assert_eq!(curved.last(), Some(&to.left_center()));
+    }
+
+    #[test]
+    fn midpoint_and_distance() {
+        let path = [Pos2::new(0.0, 0.0), Pos2::new(100.0, 0.0), Pos2::new(100.0, 100.0)];
+        assert_eq!(path_midpoint(&path), Pos2::new(100.0, 0.0));
+        assert_eq!(distance_to_path(&path, Pos2::new(50.0, 10.0)), 10.0);
+        assert_eq!(distance_to_path(&path, Pos2::new(130.0, 50.0)), 30.0);
+    }
+}
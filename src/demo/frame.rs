@@ -0,0 +1,82 @@
+/// Height of a frame's title bar in board units, which is all that is left
+/// of a collapsed frame.
+pub const FRAME_TITLE_HEIGHT: f32 = 28.00;
+
+/// The contents of a `BlockType::Frame` block.
+///
+/// Stored in `block_data` as the state on the first line followed by the
+/// title. A collapsed frame is only as tall as its title bar; its first line
+/// is `collapsed:<height>`, with the height to give back when it is expanded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameData {
+    pub(crate) title: String,
+    /// The expanded height, while collapsed.
+    pub(crate) collapsed: Option<f32>,
+}
+
+impl Default for FrameData {
+    fn default() -> Self {
+        Self {
+            title: String::from("Frame"),
+            collapsed: None,
+        }
+    }
+}
+
+impl FrameData {
+    pub fn parse(data: &str) -> FrameData {
+        let (collapsed, title) = FrameData::split(data);
+        FrameData {
+            title: title.to_string(),
+            collapsed,
+        }
+    }
+
+    /// Whether the frame stored as `data` is collapsed, without copying its title.
+    pub fn is_collapsed(data: &str) -> bool {
+        FrameData::split(data).0.is_some()
+    }
+
+    /// The expanded height of a collapsed frame stored as `data`, and its title.
+    fn split(data: &str) -> (Option<f32>, &str) {
+        let (state, title) = match data.split_once('\n') {
+            Some((state, title)) => (state, title),
+            None => ("", data),
+        };
+
+        let collapsed = match state.split_once(':') {
+            Some(("collapsed", height)) => height.parse().ok(),
+            _ => None,
+        };
+        (collapsed, title)
+    }
+
+    pub fn encode(&self) -> String {
+        match self.collapsed {
+            Some(height) => format!("collapsed:{}\n{}", height, self.title),
+            None => format!("\n{}", self.title),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_round_trips() {
+        for collapsed in [None, Some(320.5)] {
+            let data = FrameData {
+                title: String::from("Sprint: week 3"),
+                collapsed,
+            };
+            assert_eq!(FrameData::parse(&data.encode()), data);
+        }
+    }
+
+    #[test]
+    fn plain_text_is_a_title() {
+        assert_eq!(FrameData::parse("Ideas"), FrameData { title: String::from("Ideas"), collapsed: None });
+        assert_eq!(FrameData::parse("collapsed:tall\nIdeas").collapsed, None);
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use egui::text::{Fonts, LayoutJob, TextFormat};
+use egui::{Color32, CursorIcon, FontId, Galley, Pos2, Response, Sense, Stroke, TextStyle, Ui, Vec2, Widget};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag};
+
+/// How far each level of a nested list is indented, in multiples of the font size.
+const LIST_INDENT: f32 = 1.5;
+
+/// The fonts and colors Markdown text is drawn with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkdownStyle {
+    pub(crate) font: FontId,
+    pub(crate) text: Color32,
+    /// Headings and bold text, as the default fonts have no bold face.
+    pub(crate) strong: Color32,
+    pub(crate) link: Color32,
+    pub(crate) code_background: Color32,
+}
+
+impl MarkdownStyle {
+    /// Body text as `style` draws it.
+    pub fn from_style(style: &egui::Style) -> MarkdownStyle {
+        MarkdownStyle {
+            font: TextStyle::Body.resolve(style),
+            text: style.visuals.text_color(),
+            strong: style.visuals.strong_text_color(),
+            link: style.visuals.hyperlink_color,
+            code_background: style.visuals.code_bg_color,
+        }
+    }
+
+    /// `text` colored text in `font` on a white background, for exports.
+    pub fn on_white(font: FontId, text: Color32) -> MarkdownStyle {
+        MarkdownStyle {
+            font,
+            text,
+            strong: Color32::BLACK,
+            link: Color32::from_rgb(0, 102, 204),
+            code_background: Color32::from_gray(235),
+        }
+    }
+}
+
+/// Label text rendered from Markdown: headings, bold, italics, strikethrough,
+/// inline code and code blocks, bullet, numbered and task lists and links.
+///
+/// Single line breaks are kept as they are, so plain text labels look the
+/// same as before they were rendered as Markdown.
+pub struct Markdown {
+    pub(crate) job: LayoutJob,
+    /// The target of every link, by the range of `job.sections` its text is in.
+    links: Vec<(Range<usize>, String)>,
+}
+
+impl Markdown {
+    pub fn parse(source: &str, style: &MarkdownStyle) -> Markdown {
+        let mut writer = Writer::new(style);
+        for event in Parser::new_ext(source, Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS) {
+            writer.event(event);
+        }
+        writer.finish()
+    }
+
+    /// The text laid out to wrap at `width`.
+    pub fn layout(&self, fonts: &Fonts, width: f32) -> Arc<Galley> {
+        let mut job = self.job.clone();
+        job.wrap.max_width = width;
+        fonts.layout_job(job)
+    }
+
+    /// The target of the link at `pos`, relative to the top left of `galley`.
+    pub fn link_at(&self, galley: &Galley, pos: Pos2) -> Option<&str> {
+        let row = galley.rows.iter().find(|row| row.rect.contains(pos))?;
+        let glyph = row.glyphs.iter().find(|glyph| glyph.pos.x <= pos.x && pos.x < glyph.max_x())?;
+        let section = glyph.section_index as usize;
+        self.links
+            .iter()
+            .find(|(sections, _)| sections.contains(&section))
+            .map(|(_, url)| url.as_str())
+    }
+}
+
+impl Widget for &Markdown {
+    /// Wraps at the available width; links open when clicked.
+    fn ui(self, ui: &mut Ui) -> Response {
+        let width = ui.available_width();
+        let galley = self.layout(&ui.fonts(), width);
+        let sense = if self.links.is_empty() { Sense::hover() } else { Sense::click() };
+        let (rect, mut response) = ui.allocate_exact_size(Vec2::new(width, galley.size().y), sense);
+
+        let link = response.hover_pos().and_then(|pos| self.link_at(&galley, pos - rect.min.to_vec2()));
+        if let Some(url) = link {
+            if response.clicked() {
+                ui.ctx().output().open_url(url);
+            }
+            response = response.on_hover_cursor(CursorIcon::PointingHand);
+        }
+
+        ui.painter().galley(rect.min, galley);
+        response
+    }
+}
+
+/// Labels parsed during the current frame, so measuring and then drawing a
+/// label parses it only once. Laying the parsed text out is cached by egui.
+#[derive(Default)]
+pub struct MarkdownCache {
+    style: Option<MarkdownStyle>,
+    /// Parsed text by source, and whether it was used this frame.
+    entries: HashMap<String, (Arc<Markdown>, bool)>,
+}
+
+impl MarkdownCache {
+    /// `source` parsed with `style`, parsing it if it wasn't already.
+    pub fn get(&mut self, source: &str, style: &MarkdownStyle) -> Arc<Markdown> {
+        if self.style.as_ref() != Some(style) {
+            self.entries.clear();
+            self.style = Some(style.clone());
+        }
+        if let Some((markdown, used)) = self.entries.get_mut(source) {
+            *used = true;
+            return markdown.clone();
+        }
+        let markdown = Arc::new(Markdown::parse(source, style));
+        self.entries.insert(source.to_string(), (markdown.clone(), true));
+        markdown
+    }
+
+    /// Forgets the labels that weren't used since the last call.
+    pub fn end_frame(&mut self) {
+        self.entries.retain(|_, (_, used)| std::mem::take(used));
+    }
+}
+
+/// Turns parser events into a `LayoutJob`, keeping track of the formatting
+/// that applies to the next piece of text.
+struct Writer<'a> {
+    style: &'a MarkdownStyle,
+    job: LayoutJob,
+    links: Vec<(Range<usize>, String)>,
+    strong: usize,
+    emphasis: usize,
+    strikethrough: usize,
+    quote: usize,
+    heading: Option<HeadingLevel>,
+    /// The first section of the link being written, and its target.
+    link: Option<(usize, String)>,
+    /// Text of the code block being written, which is only added once it is complete.
+    code_block: Option<String>,
+    /// The next number of every open list, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    /// The bullet, number or checkbox of a list item, written along with its first text.
+    item_prefix: Option<String>,
+}
+
+impl<'a> Writer<'a> {
+    fn new(style: &'a MarkdownStyle) -> Writer<'a> {
+        Writer {
+            style,
+            job: LayoutJob::default(),
+            links: Vec::new(),
+            strong: 0,
+            emphasis: 0,
+            strikethrough: 0,
+            quote: 0,
+            heading: None,
+            link: None,
+            code_block: None,
+            lists: Vec::new(),
+            item_prefix: None,
+        }
+    }
+
+    fn event(&mut self, event: Event<'_>) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match &mut self.code_block {
+                Some(code) => code.push_str(&text),
+                None => self.push(&text, self.format()),
+            },
+            Event::Code(code) => {
+                let mut format = self.format();
+                format.font_id = FontId::monospace(format.font_id.size);
+                format.background = self.style.code_background;
+                self.push(&code, format);
+            }
+            // Shown as written, there is nothing to render HTML with.
+            Event::Html(html) => self.push(html.trim_end_matches('\n'), self.format()),
+            Event::SoftBreak | Event::HardBreak => self.push("\n", self.format()),
+            Event::Rule => {
+                self.end_lines(2);
+                self.push(&"—".repeat(8), self.plain());
+            }
+            Event::TaskListMarker(checked) => {
+                self.item_prefix = Some(String::from(if checked { "☑ " } else { "☐ " }));
+            }
+            Event::FootnoteReference(name) => self.push(&format!("[{}]", name), self.format()),
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Paragraph => self.end_lines(if self.lists.is_empty() { 2 } else { 1 }),
+            Tag::Heading(level, ..) => {
+                self.end_lines(2);
+                self.heading = Some(level);
+            }
+            Tag::BlockQuote => {
+                self.end_lines(2);
+                self.quote += 1;
+            }
+            Tag::CodeBlock(_) => {
+                self.end_lines(2);
+                self.code_block = Some(String::new());
+            }
+            Tag::List(start) => {
+                self.end_lines(if self.lists.is_empty() { 2 } else { 1 });
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.end_lines(1);
+                self.item_prefix = Some(match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => String::from("• "),
+                });
+            }
+            Tag::Emphasis => self.emphasis += 1,
+            Tag::Strong => self.strong += 1,
+            Tag::Strikethrough => self.strikethrough += 1,
+            Tag::Link(_, url, _) => {
+                self.write_prefix();
+                self.link = Some((self.job.sections.len(), url.to_string()));
+            }
+            // Tables and footnotes aren't enabled; images show their alt text.
+            Tag::Table(_) | Tag::TableHead | Tag::TableRow | Tag::TableCell | Tag::FootnoteDefinition(_) | Tag::Image(..) => {}
+        }
+    }
+
+    fn end(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Heading(..) => self.heading = None,
+            Tag::BlockQuote => self.quote -= 1,
+            Tag::CodeBlock(_) => {
+                if let Some(code) = self.code_block.take() {
+                    let mut format = self.plain();
+                    format.font_id = FontId::monospace(format.font_id.size);
+                    format.background = self.style.code_background;
+                    self.push(code.trim_end_matches('\n'), format);
+                }
+            }
+            Tag::List(_) => {
+                self.lists.pop();
+            }
+            // An empty item still shows its bullet.
+            Tag::Item => self.write_prefix(),
+            Tag::Emphasis => self.emphasis -= 1,
+            Tag::Strong => self.strong -= 1,
+            Tag::Strikethrough => self.strikethrough -= 1,
+            Tag::Link(..) => {
+                if let Some((start, url)) = self.link.take() {
+                    self.links.push((start..self.job.sections.len(), url));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Body text without any formatting.
+    fn plain(&self) -> TextFormat {
+        TextFormat::simple(self.style.font.clone(), self.style.text)
+    }
+
+    /// The format for text at the current position.
+    fn format(&self) -> TextFormat {
+        let mut format = self.plain();
+        let scale = match self.heading {
+            Some(HeadingLevel::H1) => 1.6,
+            Some(HeadingLevel::H2) => 1.35,
+            Some(_) => 1.15,
+            None => 1.0,
+        };
+        format.font_id.size *= scale;
+        if self.heading.is_some() || self.strong > 0 {
+            format.color = self.style.strong;
+        }
+        format.italics = self.emphasis > 0 || self.quote > 0;
+        if self.strikethrough > 0 {
+            format.strikethrough = Stroke::new(1.0, format.color);
+        }
+        if self.link.is_some() {
+            format.color = self.style.link;
+            format.underline = Stroke::new(1.0, self.style.link);
+        }
+        format
+    }
+
+    fn push(&mut self, text: &str, format: TextFormat) {
+        self.write_prefix();
+        self.job.append(text, 0.0, format);
+    }
+
+    /// Writes the pending list item prefix, indented by how deeply the list is nested.
+    fn write_prefix(&mut self) {
+        if let Some(prefix) = self.item_prefix.take() {
+            let indent = self.lists.len().saturating_sub(1) as f32 * LIST_INDENT * self.style.font.size;
+            let format = self.plain();
+            self.job.append(&prefix, indent, format);
+        }
+    }
+
+    /// Makes the text so far end in `count` line breaks, so the next block
+    /// starts on a new line, or after an empty one. Nothing is added at the start.
+    fn end_lines(&mut self, count: usize) {
+        if self.job.text.is_empty() {
+            return;
+        }
+        let ending = self.job.text.chars().rev().take_while(|c| *c == '\n').count();
+        for _ in ending..count {
+            let format = self.plain();
+            self.job.append("\n", 0.0, format);
+        }
+    }
+
+    fn finish(mut self) -> Markdown {
+        if self.job.sections.is_empty() {
+            // An empty label is still one line high.
+            let format = self.plain();
+            self.job.append("", 0.0, format);
+        }
+        Markdown {
+            job: self.job,
+            links: self.links,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::text::FontDefinitions;
+    use egui::FontFamily;
+
+    fn style() -> MarkdownStyle {
+        MarkdownStyle::on_white(FontId::proportional(14.0), Color32::from_gray(30))
+    }
+
+    /// The format of the section `text` starts in.
+    fn format_of<'a>(markdown: &'a Markdown, text: &str) -> &'a TextFormat {
+        let start = markdown.job.text.find(text).unwrap();
+        let section = markdown.job.sections.iter().find(|s| s.byte_range.contains(&start)).unwrap();
+        &section.format
+    }
+
+    #[test]
+    fn plain_text_is_kept() {
+        let text = "first line\nsecond line\n\nnext paragraph";
+        assert_eq!(Markdown::parse(text, &style()).job.text, text);
+        assert_eq!(Markdown::parse("", &style()).job.sections.len(), 1);
+    }
+
+    #[test]
+    fn inline_formatting() {
+        let markdown = Markdown::parse("# Title\nSome **bold**, *italic*, ~~gone~~ and `code`", &style());
+        assert_eq!(markdown.job.text, "Title\n\nSome bold, italic, gone and code");
+
+        assert_eq!(format_of(&markdown, "Title").font_id.size, 14.0 * 1.6);
+        assert_eq!(format_of(&markdown, "Some").font_id.size, 14.0);
+        assert_eq!(format_of(&markdown, "bold").color, Color32::BLACK);
+        assert!(format_of(&markdown, "italic").italics);
+        assert!(format_of(&markdown, "gone").strikethrough.width > 0.0);
+        assert_eq!(format_of(&markdown, "code").font_id.family, FontFamily::Monospace);
+        assert_ne!(format_of(&markdown, "code").background, Color32::TRANSPARENT);
+    }
+
+    #[test]
+    fn lists() {
+        let markdown = Markdown::parse("Todo:\n\n- one\n- [x] done\n- [ ] open\n\n3. third\n4. fourth\n   - nested", &style());
+        assert_eq!(markdown.job.text, "Todo:\n\n• one\n☑ done\n☐ open\n\n3. third\n4. fourth\n• nested");
+
+        let nested = markdown.job.sections.iter().rev().find(|s| &markdown.job.text[s.byte_range.clone()] == "• ").unwrap();
+        assert_eq!(nested.leading_space, LIST_INDENT * 14.0);
+    }
+
+    #[test]
+    fn links_are_found_by_position() {
+        let markdown = Markdown::parse("see [the docs](https://example.com) now", &style());
+        assert_eq!(markdown.job.text, "see the docs now");
+        assert_eq!(format_of(&markdown, "the docs").underline.color, style().link);
+
+        let fonts = Fonts::new(1.0, 2048, FontDefinitions::default());
+        let galley = markdown.layout(&fonts, f32::INFINITY);
+        let glyph_center = |index: usize| galley.rows[0].glyphs[index].logical_rect().center();
+        assert_eq!(markdown.link_at(&galley, glyph_center(4)), Some("https://example.com"));
+        assert_eq!(markdown.link_at(&galley, glyph_center(0)), None);
+        assert_eq!(markdown.link_at(&galley, glyph_center(14)), None);
+        assert_eq!(markdown.link_at(&galley, Pos2::new(-5.0, 0.0)), None);
+    }
+
+    #[test]
+    fn labels_are_parsed_once_per_frame() {
+        let mut cache = MarkdownCache::default();
+        let first = cache.get("**bold**", &style());
+        assert!(Arc::ptr_eq(&first, &cache.get("**bold**", &style())));
+        cache.end_frame();
+
+        // Used last frame, so still there.
+        assert!(Arc::ptr_eq(&first, &cache.get("**bold**", &style())));
+        cache.end_frame();
+        cache.end_frame();
+        assert!(!Arc::ptr_eq(&first, &cache.get("**bold**", &style())));
+
+        // Another style parses again.
+        let other = MarkdownStyle { text: Color32::RED, ..style() };
+        let again = cache.get("**bold**", &style());
+        assert!(!Arc::ptr_eq(&again, &cache.get("**bold**", &other)));
+    }
+}
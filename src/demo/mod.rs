@@ -1,8 +1,25 @@
 use std::fmt::{Display, Formatter};
-use egui::{Vec2, Widget};
-use rusqlite::types::{FromSql, FromSqlResult, ValueRef};
+use egui::Vec2;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+mod button;
+mod checklist;
+mod connector;
+mod frame;
+mod markdown;
+mod shape;
+mod stroke;
+
+pub use button::{ButtonAction, ButtonData};
+pub use checklist::{ChecklistChange, ChecklistData, ChecklistItem};
+pub use connector::{arrow_shapes, distance_to_path, path_midpoint, Connector, ConnectorStyle, ARROW_SIZE};
+pub use frame::{FrameData, FRAME_TITLE_HEIGHT};
+pub use markdown::{Markdown, MarkdownCache, MarkdownStyle};
+pub use shape::{color_from_int, color_to_int, text_color_on, ShapeKind, ShapeStyle};
+pub use stroke::{smooth, stroke_shape, StrokeData};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockPosition {
     pub(crate) id: String,
     pub(crate) x: f32,
@@ -10,10 +27,50 @@ pub struct BlockPosition {
     pub(crate) size: Vec2
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum BlockType {
     Button,
-    Label
+    Label,
+    /// `block_data` holds the content hash of the image in the `images` table.
+    Image,
+    /// A freehand pen line, see `StrokeData`.
+    Stroke,
+    /// Drawn as described by the block's `ShapeStyle`, with `block_data` as its text.
+    Shape,
+    /// A title and items that can be checked off, see `ChecklistData`.
+    Checklist,
+    /// A titled area that holds the blocks placed inside it, see `FrameData`.
+    Frame,
+}
+
+impl BlockType {
+    /// The value stored in the `type` column. These codes are part of the
+    /// on-disk format: never renumber or reuse one.
+    pub fn code(self) -> i64 {
+        match self {
+            BlockType::Label => 0,
+            BlockType::Button => 1,
+            BlockType::Image => 2,
+            BlockType::Stroke => 3,
+            BlockType::Shape => 4,
+            BlockType::Checklist => 5,
+            BlockType::Frame => 6,
+        }
+    }
+
+    pub fn from_code(code: i64) -> Option<BlockType> {
+        match code {
+            0 => Some(BlockType::Label),
+            1 => Some(BlockType::Button),
+            2 => Some(BlockType::Image),
+            3 => Some(BlockType::Stroke),
+            4 => Some(BlockType::Shape),
+            5 => Some(BlockType::Checklist),
+            6 => Some(BlockType::Frame),
+            _ => None,
+        }
+    }
 }
 
 impl Display for BlockType {
@@ -22,16 +79,30 @@ impl Display for BlockType {
     }
 }
 
+impl ToSql for BlockType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.code()))
+    }
+}
+
 impl FromSql for BlockType {
     fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
-        let v = value.as_str().ok().unwrap();
-        return Ok(BlockType::Label);
+        let code = value.as_i64()?;
+        BlockType::from_code(code).ok_or(FromSqlError::OutOfRange(code))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub(crate) id: String,
+    #[serde(rename = "type")]
     pub(crate) block_type: BlockType,
+    #[serde(rename = "data")]
     pub(crate) block_data: String,
+    /// Set for `BlockType::Shape` blocks only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) style: Option<ShapeStyle>,
+    /// The frame this block is in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent: Option<String>,
 }
\ No newline at end of file
@@ -0,0 +1,218 @@
+use std::f32::consts::TAU;
+
+use egui::epaint::RectShape;
+use egui::{Color32, Pos2, Rect, Shape, Stroke, Vec2};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+/// Corners sampled along an ellipse outline.
+const ELLIPSE_STEPS: usize = 48;
+
+/// The outline of a `BlockType::Shape` block.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShapeKind {
+    Rectangle,
+    RoundedRectangle,
+    Ellipse,
+    Diamond,
+    /// A rectangle with a folded corner and a drop shadow.
+    StickyNote,
+}
+
+impl ShapeKind {
+    pub const ALL: [ShapeKind; 5] = [
+        ShapeKind::Rectangle,
+        ShapeKind::RoundedRectangle,
+        ShapeKind::Ellipse,
+        ShapeKind::Diamond,
+        ShapeKind::StickyNote,
+    ];
+
+    /// The value stored in the `kind` column of `shape_styles`. Like
+    /// `BlockType::code`, these are part of the on-disk format.
+    pub fn code(self) -> i64 {
+        match self {
+            ShapeKind::Rectangle => 0,
+            ShapeKind::RoundedRectangle => 1,
+            ShapeKind::Ellipse => 2,
+            ShapeKind::Diamond => 3,
+            ShapeKind::StickyNote => 4,
+        }
+    }
+
+    pub fn from_code(code: i64) -> Option<ShapeKind> {
+        match code {
+            0 => Some(ShapeKind::Rectangle),
+            1 => Some(ShapeKind::RoundedRectangle),
+            2 => Some(ShapeKind::Ellipse),
+            3 => Some(ShapeKind::Diamond),
+            4 => Some(ShapeKind::StickyNote),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShapeKind::Rectangle => "Rectangle",
+            ShapeKind::RoundedRectangle => "Rounded rectangle",
+            ShapeKind::Ellipse => "Ellipse",
+            ShapeKind::Diamond => "Diamond",
+            ShapeKind::StickyNote => "Sticky note",
+        }
+    }
+
+    /// Size of a newly inserted shape, in board units.
+    pub fn default_size(self) -> Vec2 {
+        match self {
+            ShapeKind::StickyNote => Vec2::splat(160.0),
+            _ => Vec2::new(160.0, 100.0),
+        }
+    }
+
+    /// The outline within `rect` as a convex polygon, for the kinds that
+    /// aren't drawn as rectangles.
+    pub fn polygon(self, rect: Rect) -> Option<Vec<Pos2>> {
+        match self {
+            ShapeKind::Ellipse => Some(
+                (0..ELLIPSE_STEPS)
+                    .map(|i| {
+                        let angle = i as f32 / ELLIPSE_STEPS as f32 * TAU;
+                        rect.center() + Vec2::new(angle.cos(), angle.sin()) * rect.size() / 2.0
+                    })
+                    .collect(),
+            ),
+            ShapeKind::Diamond => Some(vec![rect.center_top(), rect.right_center(), rect.center_bottom(), rect.left_center()]),
+            _ => None,
+        }
+    }
+
+    /// The part of `rect` that inner text is laid out in.
+    pub fn text_rect(self, rect: Rect) -> Rect {
+        match self {
+            // The largest rectangle inside an ellipse or diamond is this much of its bounds.
+            ShapeKind::Ellipse => Rect::from_center_size(rect.center(), rect.size() * std::f32::consts::FRAC_1_SQRT_2),
+            ShapeKind::Diamond => Rect::from_center_size(rect.center(), rect.size() / 2.0),
+            _ => rect.shrink((rect.width().min(rect.height()) * 0.08).min(8.0)),
+        }
+    }
+}
+
+impl ToSql for ShapeKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.code()))
+    }
+}
+
+impl FromSql for ShapeKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let code = value.as_i64()?;
+        ShapeKind::from_code(code).ok_or(FromSqlError::OutOfRange(code))
+    }
+}
+
+/// How a shape block is drawn. The shape's text lives in `block_data`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShapeStyle {
+    pub(crate) kind: ShapeKind,
+    pub(crate) fill: Color32,
+    pub(crate) stroke: Color32,
+    /// Outline width in board units.
+    pub(crate) stroke_width: f32,
+}
+
+impl ShapeStyle {
+    pub fn new(kind: ShapeKind) -> ShapeStyle {
+        match kind {
+            ShapeKind::StickyNote => ShapeStyle {
+                kind,
+                fill: Color32::from_rgb(255, 235, 130),
+                stroke: Color32::from_rgb(220, 190, 80),
+                stroke_width: 1.0,
+            },
+            _ => ShapeStyle {
+                kind,
+                fill: Color32::WHITE,
+                stroke: Color32::DARK_GRAY,
+                stroke_width: 2.0,
+            },
+        }
+    }
+
+    /// The outline and fill of a shape covering `rect`, drawn at `zoom`.
+    pub fn shapes(&self, rect: Rect, zoom: f32) -> Vec<Shape> {
+        let stroke = Stroke::new(self.stroke_width * zoom, self.stroke);
+        if let Some(points) = self.kind.polygon(rect) {
+            return vec![Shape::convex_polygon(points, self.fill, stroke)];
+        }
+
+        let outlined = |rounding: f32| Shape::Rect(RectShape { rect, rounding: rounding.into(), fill: self.fill, stroke });
+        match self.kind {
+            ShapeKind::RoundedRectangle => vec![outlined(rect.width().min(rect.height()) * 0.15)],
+            ShapeKind::StickyNote => {
+                let fold = rect.width().min(rect.height()) * 0.15;
+                let corner = rect.right_bottom();
+                let [r, g, b, a] = self.fill.to_array();
+                let shade = Color32::from_rgba_premultiplied(r / 8 * 7, g / 8 * 7, b / 8 * 7, a);
+                vec![
+                    Shape::rect_filled(rect.translate(Vec2::splat(4.00 * zoom)), 0.0, Color32::from_black_alpha(40)),
+                    Shape::convex_polygon(
+                        vec![rect.left_top(), rect.right_top(), corner - Vec2::new(0.0, fold), corner - Vec2::new(fold, 0.0), rect.left_bottom()],
+                        self.fill,
+                        stroke,
+                    ),
+                    Shape::convex_polygon(
+                        vec![corner - Vec2::new(0.0, fold), corner - Vec2::splat(fold), corner - Vec2::new(fold, 0.0)],
+                        shade,
+                        stroke,
+                    ),
+                ]
+            }
+            _ => vec![outlined(0.0)],
+        }
+    }
+}
+
+/// Black or white, whichever reads better on `fill`.
+pub fn text_color_on(fill: Color32) -> Color32 {
+    let [r, g, b, a] = fill.to_array();
+    if a < 128 || 299 * r as u32 + 587 * g as u32 + 114 * b as u32 > 140_000 {
+        Color32::BLACK
+    } else {
+        Color32::WHITE
+    }
+}
+
+/// A color as stored in the database: premultiplied RGBA packed into one integer.
+pub fn color_to_int(color: Color32) -> i64 {
+    u32::from_be_bytes(color.to_array()) as i64
+}
+
+pub fn color_from_int(value: i64) -> Color32 {
+    let [r, g, b, a] = (value as u32).to_be_bytes();
+    Color32::from_rgba_premultiplied(r, g, b, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_round_trip() {
+        for color in [Color32::WHITE, Color32::TRANSPARENT, Color32::from_rgba_premultiplied(10, 20, 30, 40)] {
+            assert_eq!(color_from_int(color_to_int(color)), color);
+        }
+    }
+
+    #[test]
+    fn polygons_stay_inside_their_rect() {
+        let rect = Rect::from_min_size(Pos2::new(10.0, 10.0), Vec2::new(200.0, 80.0));
+        for kind in ShapeKind::ALL {
+            assert_eq!(ShapeKind::from_code(kind.code()), Some(kind));
+            for point in kind.polygon(rect).unwrap_or_default() {
+                assert!(rect.expand(0.001).contains(point), "{:?} at {:?}", kind, point);
+            }
+            assert!(rect.contains_rect(kind.text_rect(rect)));
+        }
+    }
+}
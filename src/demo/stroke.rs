@@ -0,0 +1,175 @@
+use egui::{Color32, Pos2, Rect, Shape, Stroke, Vec2};
+
+/// The contents of a `BlockType::Stroke` block: a freehand pen line.
+///
+/// Stored in `block_data` as the color (`rrggbbaa`, premultiplied) and width
+/// on the first line and the points on the second, as `x,y` pairs separated
+/// by spaces. Points are relative to the block's position, which is the top
+/// left corner of the stroke's bounding box.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrokeData {
+    pub(crate) color: Color32,
+    /// Line width in board units.
+    pub(crate) width: f32,
+    pub(crate) points: Vec<Pos2>,
+}
+
+impl StrokeData {
+    /// Parses `block_data`, falling back to an empty stroke for anything malformed.
+    pub fn parse(data: &str) -> StrokeData {
+        let (style, points) = data.split_once('\n').unwrap_or((data, ""));
+        let (color, width) = style.split_once(' ').unwrap_or((style, ""));
+
+        let color = u32::from_str_radix(color, 16)
+            .map(|rgba| {
+                let [r, g, b, a] = rgba.to_be_bytes();
+                Color32::from_rgba_premultiplied(r, g, b, a)
+            })
+            .unwrap_or(Color32::BLACK);
+
+        let points = points
+            .split_whitespace()
+            .filter_map(|point| {
+                let (x, y) = point.split_once(',')?;
+                Some(Pos2::new(x.parse().ok()?, y.parse().ok()?))
+            })
+            .collect();
+
+        StrokeData {
+            color,
+            width: width.parse().unwrap_or(1.0),
+            points,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let [r, g, b, a] = self.color.to_array();
+        let points: Vec<String> = self.points.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+        format!("{:02x}{:02x}{:02x}{:02x} {}\n{}", r, g, b, a, self.width, points.join(" "))
+    }
+
+    /// Builds a stroke through `points` in board coordinates, returning it
+    /// with the board area it covers.
+    pub fn from_board_points(points: &[Pos2], color: Color32, width: f32) -> (StrokeData, Rect) {
+        let bounds = points
+            .iter()
+            .fold(Rect::NOTHING, |bounds, point| bounds.union(Rect::from_min_max(*point, *point)))
+            .expand(width / 2.0);
+        let stroke = StrokeData {
+            color,
+            width,
+            points: points.iter().map(|p| (*p - bounds.min).to_pos2()).collect(),
+        };
+        (stroke, bounds)
+    }
+
+    /// Erases the parts of the stroke within `radius` of `center`, for a
+    /// stroke block at `origin`. Returns `None` if the eraser missed, otherwise
+    /// the pieces left over in board coordinates, which may be none at all.
+    pub fn erase(&self, origin: Pos2, center: Pos2, radius: f32) -> Option<Vec<Vec<Pos2>>> {
+        let points: Vec<Pos2> = self.points.iter().map(|p| origin + p.to_vec2()).collect();
+        let reach = radius + self.width / 2.0;
+
+        if let [point] = points.as_slice() {
+            return (point.distance(center) <= reach).then(Vec::new);
+        }
+
+        let mut pieces = Vec::new();
+        let mut piece = points.first().into_iter().copied().collect::<Vec<_>>();
+        let mut erased = false;
+        for segment in points.windows(2) {
+            if segment_distance(segment[0], segment[1], center) <= reach {
+                // Drop the whole segment and start a new piece after it.
+                erased = true;
+                if piece.len() >= 2 {
+                    pieces.push(piece);
+                }
+                piece = vec![segment[1]];
+            } else {
+                piece.push(segment[1]);
+            }
+        }
+        if piece.len() >= 2 {
+            pieces.push(piece);
+        }
+
+        erased.then(|| pieces)
+    }
+}
+
+/// A pen line through `points`, which are in the coordinates it is drawn in.
+pub fn stroke_shape(points: &[Pos2], color: Color32, width: f32) -> Shape {
+    match points {
+        [] => Shape::Noop,
+        [point] => Shape::circle_filled(*point, width / 2.0, color),
+        _ => Shape::line(points.to_vec(), Stroke::new(width, color)),
+    }
+}
+
+/// Rounds off the corners of a captured pointer path (Chaikin's algorithm),
+/// keeping both of its ends in place.
+pub fn smooth(points: &[Pos2]) -> Vec<Pos2> {
+    let mut points = points.to_vec();
+    for _ in 0..2 {
+        if points.len() < 3 {
+            break;
+        }
+        let mut smoothed = Vec::with_capacity(points.len() * 2);
+        smoothed.push(points[0]);
+        for segment in points.windows(2) {
+            let step: Vec2 = segment[1] - segment[0];
+            smoothed.push(segment[0] + step * 0.25);
+            smoothed.push(segment[0] + step * 0.75);
+        }
+        smoothed.push(points[points.len() - 1]);
+        points = smoothed;
+    }
+    points
+}
+
+fn segment_distance(a: Pos2, b: Pos2, point: Pos2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_sq() > 0.0 { ((point - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0) } else { 0.0 };
+    point.distance(a + ab * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_round_trips() {
+        let points = [Pos2::new(10.0, 10.0), Pos2::new(20.5, 15.0), Pos2::new(40.0, 12.25)];
+        let (stroke, bounds) = StrokeData::from_board_points(&points, Color32::from_rgb(200, 30, 10), 4.0);
+
+        assert_eq!(bounds, Rect::from_min_max(Pos2::new(8.0, 8.0), Pos2::new(42.0, 17.0)));
+        assert_eq!(stroke.points[0], Pos2::new(2.0, 2.0));
+        assert_eq!(StrokeData::parse(&stroke.encode()), stroke);
+    }
+
+    #[test]
+    fn erasing_splits_at_the_erased_segment() {
+        let stroke = StrokeData {
+            color: Color32::BLACK,
+            width: 2.0,
+            points: (0..=10).map(|i| Pos2::new(i as f32 * 10.0, 0.0)).collect(),
+        };
+        let origin = Pos2::new(100.0, 100.0);
+
+        assert_eq!(stroke.erase(origin, Pos2::new(150.0, 150.0), 5.0), None);
+
+        let pieces = stroke.erase(origin, Pos2::new(155.0, 103.0), 2.0).unwrap();
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].last(), Some(&Pos2::new(150.0, 100.0)));
+        assert_eq!(pieces[1].first(), Some(&Pos2::new(160.0, 100.0)));
+    }
+
+    #[test]
+    fn smoothing_keeps_the_ends() {
+        let points = [Pos2::new(0.0, 0.0), Pos2::new(10.0, 10.0), Pos2::new(20.0, 0.0)];
+        let smoothed = smooth(&points);
+        assert_eq!(smoothed.first(), points.first());
+        assert_eq!(smoothed.last(), points.last());
+        assert!(smoothed.len() > points.len());
+    }
+}
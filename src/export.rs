@@ -0,0 +1,829 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use egui::epaint::{tessellator, ClippedShape, RectShape, Shape, Stroke, TessellationOptions, TextureId};
+use egui::text::{FontDefinitions, Fonts, Galley, LayoutJob, TextFormat};
+use egui::{Color32, FontFamily, FontId, Pos2, Rect, TextStyle, Vec2};
+use image::{ImageFormat, RgbaImage};
+
+use crate::demo::{arrow_shapes, path_midpoint, stroke_shape, text_color_on, BlockType, ButtonData, ChecklistData, Connector, FrameData, Markdown, MarkdownStyle, ShapeKind, ShapeStyle, StrokeData, ARROW_SIZE, FRAME_TITLE_HEIGHT};
+use crate::persistor::{PersistError, Persistor, SavedArea, SavedBlock};
+use crate::raster::{Canvas, Texture};
+use crate::state::{block_rect, BoardState};
+
+/// Space left around the blocks when exporting the whole board or a selection.
+pub const EXPORT_MARGIN: f32 = 20.00;
+
+/// Label text color in exports, which always have a white background.
+const TEXT_COLOR: Color32 = Color32::from_rgb(30, 30, 30);
+
+const CONNECTOR_COLOR: Color32 = Color32::GRAY;
+
+const BUTTON_FILL: Color32 = Color32::from_gray(230);
+const BUTTON_STROKE: Color32 = Color32::from_gray(180);
+
+/// Space inside a checklist's border, and between its rows.
+const CHECKLIST_MARGIN: f32 = 6.00;
+const CHECKLIST_SPACING: f32 = 4.00;
+
+const FRAME_FILL: Color32 = Color32::from_gray(248);
+const FRAME_TITLE_FILL: Color32 = Color32::from_gray(235);
+
+/// Space left of a frame's title.
+const FRAME_TITLE_MARGIN: f32 = 8.00;
+
+/// Width labels that were never measured wrap at, as on the canvas.
+const LABEL_WIDTH: f32 = 300.00;
+
+/// Largest PNG width or height, in pixels.
+pub const MAX_PNG_SIDE: f32 = 16384.00;
+
+/// Most pixels in a PNG, which keeps the canvas it is drawn on to 256 MB.
+pub const MAX_PNG_PIXELS: f32 = 8192.00 * 8192.00;
+
+/// Anything that can go wrong while exporting.
+#[derive(Debug)]
+pub enum ExportError {
+    /// Reading the board failed.
+    Persist(PersistError),
+    /// There is no board with this name or id.
+    NoBoard(String),
+    /// The database has no boards at all.
+    NoBoards,
+    /// There is no frame with this title or id on the board.
+    NoFrame(String),
+    /// The image would be larger than `MAX_PNG_SIDE` on a side, or have
+    /// more than `MAX_PNG_PIXELS`.
+    TooLarge { width: f32, height: f32 },
+    /// Encoding or writing the image failed.
+    Image(image::ImageError),
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Persist(e) => e.fmt(f),
+            ExportError::NoBoard(board) => write!(f, "there is no board called {}", board),
+            ExportError::NoBoards => write!(f, "there are no boards in the database"),
+            ExportError::NoFrame(frame) => write!(f, "there is no frame called {} on the board", frame),
+            ExportError::TooLarge { width, height } => write!(
+                f,
+                "a {:.0} by {:.0} pixel image is too large, try a smaller scale",
+                width, height
+            ),
+            ExportError::Image(e) => write!(f, "could not write the image: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Persist(e) => Some(e),
+            ExportError::Image(e) => Some(e),
+            ExportError::NoBoard(_) | ExportError::NoBoards | ExportError::NoFrame(_) | ExportError::TooLarge { .. } => None,
+        }
+    }
+}
+
+impl From<PersistError> for ExportError {
+    fn from(e: PersistError) -> Self {
+        ExportError::Persist(e)
+    }
+}
+
+/// Which part of the board to export.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExportScope {
+    Board,
+    /// What is currently on screen.
+    Viewport,
+    Selection,
+    /// The selected frame with everything in it, cut to the frame's edges.
+    Frame,
+}
+
+impl ExportScope {
+    pub const ALL: [ExportScope; 4] = [ExportScope::Board, ExportScope::Viewport, ExportScope::Selection, ExportScope::Frame];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportScope::Board => "Whole board",
+            ExportScope::Viewport => "Visible area",
+            ExportScope::Selection => "Selection",
+            ExportScope::Frame => "Frame",
+        }
+    }
+}
+
+/// Everything needed to draw part of a board without the database.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub(crate) blocks: Vec<SavedBlock>,
+    pub(crate) connectors: Vec<Connector>,
+    /// Image bytes keyed by content hash, for the image blocks in `blocks`.
+    pub(crate) images: HashMap<String, Vec<u8>>,
+    /// The board area to export.
+    pub(crate) bounds: Rect,
+}
+
+impl Snapshot {
+    /// Everything on `board`, fitted to its blocks.
+    pub fn board(persist: &mut Persistor, board: &str) -> Result<Snapshot, PersistError> {
+        let area = persist.load(board, f32::MIN, f32::MAX, f32::MIN, f32::MAX)?;
+        Snapshot::new(persist, area.blocks, area.connectors, None)
+    }
+
+    /// A snapshot of `blocks` and the `connectors` between them, covering
+    /// `bounds` or, without it, fitted to the blocks. Images are read from
+    /// `persist`.
+    ///
+    /// Blocks are put in the order the canvas draws them in, and those
+    /// hidden in collapsed frames are left out.
+    pub fn new(persist: &mut Persistor, blocks: Vec<SavedBlock>, connectors: Vec<Connector>, bounds: Option<Rect>) -> Result<Snapshot, PersistError> {
+        let state = BoardState::from(SavedArea { blocks, connectors: Vec::new() });
+        let blocks: Vec<SavedBlock> = state.draw_order().iter().filter_map(|id| state.saved_block(id)).collect();
+        let mut images = HashMap::new();
+        for block in blocks.iter().filter(|b| b.block_type == BlockType::Image) {
+            if !images.contains_key(&block.block_data) {
+                if let Some(bytes) = persist.load_image(&block.block_data)? {
+                    images.insert(block.block_data.clone(), bytes);
+                }
+            }
+        }
+        Ok(Snapshot {
+            bounds: bounds.unwrap_or_else(|| Snapshot::fit(&blocks)),
+            blocks,
+            connectors,
+            images,
+        })
+    }
+
+    /// The area covered by `blocks` with `EXPORT_MARGIN` around it.
+    pub fn fit(blocks: &[SavedBlock]) -> Rect {
+        if blocks.is_empty() {
+            return Rect::from_min_size(Pos2::ZERO, Vec2::ZERO).expand(EXPORT_MARGIN);
+        }
+        blocks
+            .iter()
+            .map(|b| Rect::from_min_size(b.position, b.size))
+            .fold(Rect::NOTHING, |bounds, rect| bounds.union(rect))
+            .expand(EXPORT_MARGIN)
+    }
+
+    /// The positions of a connector's blocks, if both are in the snapshot.
+    pub fn connector_path(&self, connector: &Connector) -> Option<Vec<Pos2>> {
+        let rect = |id: &str| {
+            self.blocks
+                .iter()
+                .find(|b| b.id == id)
+                .map(|b| Rect::from_min_size(b.position, b.size))
+        };
+        Some(connector.path(rect(&connector.from)?, rect(&connector.to)?))
+    }
+}
+
+/// One piece of an exported drawing, in board coordinates. Blocks and
+/// connectors are laid out into these once, then drawn by the SVG writer
+/// and the PNG renderer alike.
+enum Part<'a> {
+    Rect { rect: Rect, rounding: f32, fill: Color32, stroke: Stroke },
+    Text { position: Pos2, galley: Arc<Galley> },
+    /// A connector's line and arrow head.
+    Arrow(Vec<Pos2>),
+    /// An image block, by content hash.
+    Image { rect: Rect, hash: &'a str },
+    /// A freehand stroke, already moved to its block's position.
+    Line { points: Vec<Pos2>, color: Color32, width: f32 },
+    Shape { rect: Rect, style: ShapeStyle },
+}
+
+impl Part<'_> {
+    fn rect(rect: Rect, rounding: f32, fill: Color32, stroke: Stroke) -> Self {
+        Part::Rect { rect, rounding, fill, stroke }
+    }
+
+    fn text(position: Pos2, galley: Arc<Galley>) -> Self {
+        Part::Text { position, galley }
+    }
+}
+
+/// The parts `snapshot` is drawn from: connectors first, so they go
+/// underneath the blocks they join as on the canvas, then the blocks in
+/// order. Text is laid out with `fonts` in `font`.
+fn parts<'a>(snapshot: &'a Snapshot, fonts: &Fonts, font: &FontId) -> Vec<Part<'a>> {
+    let mut parts = Vec::new();
+
+    for connector in &snapshot.connectors {
+        if let Some(path) = snapshot.connector_path(connector) {
+            let label = (!connector.label.is_empty()).then(|| {
+                let galley = fonts.layout_no_wrap(connector.label.clone(), font.clone(), TEXT_COLOR);
+                (Rect::from_center_size(path_midpoint(&path), galley.size()), galley)
+            });
+            parts.push(Part::Arrow(path));
+            if let Some((rect, galley)) = label {
+                parts.push(Part::rect(rect.expand(2.00), 2.0, Color32::WHITE, Stroke::none()));
+                parts.push(Part::text(rect.min, galley));
+            }
+        }
+    }
+
+    for block in &snapshot.blocks {
+        let rect = Rect::from_min_size(block.position, block.size);
+        match block.block_type {
+            BlockType::Label => {
+                parts.push(Part::text(rect.min, label_galley(block, fonts, font)));
+            }
+            BlockType::Button => {
+                let caption = ButtonData::parse(&block.block_data).caption();
+                let galley = fonts.layout_no_wrap(caption, font.clone(), TEXT_COLOR);
+                parts.push(Part::rect(rect, 2.0, BUTTON_FILL, Stroke::new(1.0, BUTTON_STROKE)));
+                parts.push(Part::text(rect.center() - galley.size() / 2.0, galley));
+            }
+            BlockType::Checklist => {
+                parts.push(Part::rect(rect, 4.0, Color32::WHITE, Stroke::new(1.0, BUTTON_STROKE)));
+                for (position, galley) in checklist_text(block, fonts, font) {
+                    parts.push(Part::text(position, galley));
+                }
+            }
+            BlockType::Frame => {
+                let (title_bar, position, title) = frame_title(block, fonts, font);
+                parts.push(Part::rect(rect, 0.0, FRAME_FILL, Stroke::none()));
+                parts.push(Part::rect(title_bar, 0.0, FRAME_TITLE_FILL, Stroke::none()));
+                parts.push(Part::rect(rect, 0.0, Color32::TRANSPARENT, Stroke::new(1.0, BUTTON_STROKE)));
+                parts.push(Part::text(position, title));
+            }
+            BlockType::Image => {
+                if snapshot.images.contains_key(&block.block_data) {
+                    parts.push(Part::Image { rect, hash: &block.block_data });
+                } else {
+                    parts.push(Part::rect(rect, 0.0, BUTTON_FILL, Stroke::none()));
+                }
+            }
+            BlockType::Stroke => {
+                let stroke = StrokeData::parse(&block.block_data);
+                let points = stroke.points.iter().map(|p| block.position + p.to_vec2()).collect();
+                parts.push(Part::Line { points, color: stroke.color, width: stroke.width });
+            }
+            BlockType::Shape => {
+                let style = block.style.unwrap_or_else(|| ShapeStyle::new(ShapeKind::Rectangle));
+                parts.push(Part::Shape { rect, style });
+                if !block.block_data.is_empty() {
+                    let text_rect = style.kind.text_rect(rect);
+                    let galley = fonts.layout(block.block_data.clone(), font.clone(), text_color_on(style.fill), text_rect.width());
+                    parts.push(Part::text(text_rect.center() - galley.size() / 2.0, galley));
+                }
+            }
+        }
+    }
+
+    parts
+}
+
+/// Draws `snapshot` as an SVG document, one SVG unit per board unit.
+///
+/// Text is laid out with `fonts` in `font` and written a `tspan` per line
+/// and format, so it wraps exactly where it does on the canvas at 100% zoom.
+/// Labels keep their Markdown formatting.
+pub fn svg(snapshot: &Snapshot, fonts: &Fonts, font: &FontId) -> String {
+    let bounds = snapshot.bounds;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="{x} {y} {w} {h}">"#,
+        x = bounds.min.x,
+        y = bounds.min.y,
+        w = bounds.width(),
+        h = bounds.height()
+    );
+    write_rect(&mut out, bounds, 0.0, Color32::WHITE, Stroke::none());
+
+    for part in parts(snapshot, fonts, font) {
+        match part {
+            Part::Rect { rect, rounding, fill, stroke } => write_rect(&mut out, rect, rounding, fill, stroke),
+            Part::Text { position, galley } => write_text(&mut out, &galley, position),
+            Part::Arrow(path) => write_arrow(&mut out, &path),
+            Part::Image { rect, hash } => {
+                let bytes = &snapshot.images[hash];
+                let mime = match image::guess_format(bytes) {
+                    Ok(image::ImageFormat::Jpeg) => "image/jpeg",
+                    _ => "image/png",
+                };
+                let _ = writeln!(
+                    out,
+                    r#"<image x="{}" y="{}" width="{}" height="{}" preserveAspectRatio="none" href="data:{};base64,{}"/>"#,
+                    rect.min.x,
+                    rect.min.y,
+                    rect.width(),
+                    rect.height(),
+                    mime,
+                    base64::encode(bytes)
+                );
+            }
+            Part::Line { points, color, width } => match points.as_slice() {
+                [] => {}
+                [point] => {
+                    let _ = writeln!(
+                        out,
+                        r#"<circle cx="{}" cy="{}" r="{}"{}/>"#,
+                        point.x,
+                        point.y,
+                        width / 2.0,
+                        paint("fill", color)
+                    );
+                }
+                _ => {
+                    let _ = writeln!(
+                        out,
+                        r#"<polyline points="{}" fill="none" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"{}/>"#,
+                        points_attribute(&points),
+                        width,
+                        paint("stroke", color)
+                    );
+                }
+            },
+            Part::Shape { rect, style } => write_shape(&mut out, rect, &style),
+        }
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Writes a rect, leaving out the corner radius and stroke when there are none.
+fn write_rect(out: &mut String, rect: Rect, rounding: f32, fill: Color32, stroke: Stroke) {
+    let rx = if rounding > 0.0 { format!(r#" rx="{}""#, rounding) } else { String::new() };
+    let _ = writeln!(
+        out,
+        r#"<rect x="{}" y="{}" width="{}" height="{}"{}{}/>"#,
+        rect.min.x,
+        rect.min.y,
+        rect.width(),
+        rect.height(),
+        rx,
+        look(fill, stroke)
+    );
+}
+
+/// The fill and stroke attributes of an SVG element.
+fn look(fill: Color32, stroke: Stroke) -> String {
+    let mut attributes = paint("fill", fill);
+    if stroke.width > 0.0 && stroke.color != Color32::TRANSPARENT {
+        let _ = write!(attributes, r#"{} stroke-width="{}""#, paint("stroke", stroke.color), stroke.width);
+    }
+    attributes
+}
+
+fn write_shape(out: &mut String, rect: Rect, style: &ShapeStyle) {
+    let stroke = Stroke::new(style.stroke_width, style.stroke);
+    if let Some(points) = style.kind.polygon(rect) {
+        let _ = writeln!(out, r#"<polygon points="{}"{}/>"#, points_attribute(&points), look(style.fill, stroke));
+        return;
+    }
+
+    match style.kind {
+        ShapeKind::StickyNote => {
+            let fold = rect.width().min(rect.height()) * 0.15;
+            let corner = rect.right_bottom();
+            write_rect(out, rect.translate(Vec2::splat(4.00)), 0.0, Color32::from_black_alpha(40), Stroke::none());
+            let outline = [rect.left_top(), rect.right_top(), corner - Vec2::new(0.0, fold), corner - Vec2::new(fold, 0.0), rect.left_bottom()];
+            let _ = writeln!(out, r#"<polygon points="{}"{}/>"#, points_attribute(&outline), look(style.fill, stroke));
+            let [r, g, b, a] = style.fill.to_array();
+            let shade = Color32::from_rgba_premultiplied(r / 8 * 7, g / 8 * 7, b / 8 * 7, a);
+            let flap = [corner - Vec2::new(0.0, fold), corner - Vec2::splat(fold), corner - Vec2::new(fold, 0.0)];
+            let _ = writeln!(out, r#"<polygon points="{}"{}/>"#, points_attribute(&flap), look(shade, stroke));
+        }
+        kind => {
+            let radius = match kind {
+                ShapeKind::RoundedRectangle => rect.width().min(rect.height()) * 0.15,
+                _ => 0.0,
+            };
+            write_rect(out, rect, radius, style.fill, stroke);
+        }
+    }
+}
+
+fn write_arrow(out: &mut String, path: &[Pos2]) {
+    let _ = writeln!(
+        out,
+        r#"<polyline points="{}" fill="none" stroke-width="1.5"{}/>"#,
+        points_attribute(path),
+        paint("stroke", CONNECTOR_COLOR)
+    );
+
+    if let [.., before, tip] = path {
+        let direction = (*tip - *before).normalized();
+        let back = *tip - direction * ARROW_SIZE;
+        let side = direction.rot90() * ARROW_SIZE / 2.0;
+        let head = [*tip, back + side, back - side];
+        let _ = writeln!(out, r#"<polygon points="{}"{}/>"#, points_attribute(&head), paint("fill", CONNECTOR_COLOR));
+    }
+}
+
+/// Writes the rows of `galley` laid out from `origin`, each at the position
+/// the canvas would draw it, with a `tspan` for every run of glyphs that
+/// share a format.
+fn write_text(out: &mut String, galley: &Galley, origin: Pos2) {
+    let mut spans = String::new();
+    for row in &galley.rows {
+        let mut glyphs = &row.glyphs[..];
+        while let Some(first) = glyphs.first() {
+            let length = glyphs.iter().position(|g| g.section_index != first.section_index).unwrap_or(glyphs.len());
+            let (run, rest) = glyphs.split_at(length);
+            glyphs = rest;
+
+            let text: String = run.iter().map(|g| g.chr).collect();
+            if text.trim().is_empty() {
+                continue;
+            }
+            let format = &galley.job.sections[first.section_index as usize].format;
+            let start = origin + first.pos.to_vec2();
+            if format.background != Color32::TRANSPARENT {
+                let background = Rect::from_min_size(start, Vec2::new(run[run.len() - 1].max_x() - first.pos.x, first.size.y));
+                write_rect(out, background, 2.0, format.background, Stroke::none());
+            }
+
+            let family = match format.font_id.family {
+                FontFamily::Monospace => "monospace",
+                _ => "sans-serif",
+            };
+            let mut attributes = format!(r#"font-family="{}" font-size="{}"{}"#, family, format.font_id.size, paint("fill", format.color));
+            if format.italics {
+                attributes.push_str(r#" font-style="italic""#);
+            }
+            let decorations: Vec<&str> = [(format.underline, "underline"), (format.strikethrough, "line-through")]
+                .iter()
+                .filter(|(stroke, _)| stroke.width > 0.0)
+                .map(|(_, name)| *name)
+                .collect();
+            if !decorations.is_empty() {
+                let _ = write!(attributes, r#" text-decoration="{}""#, decorations.join(" "));
+            }
+            // SVG positions text by its baseline, which is about one font size below the top of the glyphs.
+            let _ = write!(
+                spans,
+                r#"<tspan x="{}" y="{}" {}>{}</tspan>"#,
+                start.x,
+                start.y + format.font_id.size,
+                attributes,
+                escape(&text)
+            );
+        }
+    }
+    if !spans.is_empty() {
+        let _ = writeln!(out, r#"<text xml:space="preserve">{}</text>"#, spans);
+    }
+}
+
+/// The title, progress and items of the checklist `block`, each with where
+/// its top left corner goes.
+fn checklist_text(block: &SavedBlock, fonts: &Fonts, font: &FontId) -> Vec<(Pos2, Arc<Galley>)> {
+    let data = ChecklistData::parse(&block.block_data);
+    let rect = Rect::from_min_size(block.position, block.size).shrink(CHECKLIST_MARGIN);
+
+    let progress = fonts.layout_no_wrap(data.progress(), font.clone(), CONNECTOR_COLOR);
+    let title = fonts.layout(data.title.clone(), font.clone(), Color32::BLACK, rect.width() - progress.size().x - CHECKLIST_SPACING);
+    let mut y = rect.min.y + title.size().y.max(progress.size().y) + CHECKLIST_SPACING;
+    let mut text = vec![(Pos2::new(rect.max.x - progress.size().x, rect.min.y), progress), (rect.min, title)];
+
+    for item in &data.items {
+        let mut job = LayoutJob::default();
+        job.append(if item.done { "☑ " } else { "☐ " }, 0.0, TextFormat::simple(font.clone(), TEXT_COLOR));
+        let mut format = TextFormat::simple(font.clone(), TEXT_COLOR);
+        if item.done {
+            format.color = CONNECTOR_COLOR;
+            format.strikethrough = Stroke::new(1.0, CONNECTOR_COLOR);
+        }
+        job.append(&item.text, 0.0, format);
+        job.wrap.max_width = rect.width();
+        let galley = fonts.layout_job(job);
+        let height = galley.size().y;
+        text.push((Pos2::new(rect.min.x, y), galley));
+        y += height + CHECKLIST_SPACING;
+    }
+    text
+}
+
+/// The title bar of the frame `block`, with its title and where that goes.
+fn frame_title(block: &SavedBlock, fonts: &Fonts, font: &FontId) -> (Rect, Pos2, Arc<Galley>) {
+    let title_bar = Rect::from_min_size(block.position, Vec2::new(block.size.x, FRAME_TITLE_HEIGHT.min(block.size.y)));
+    let data = FrameData::parse(&block.block_data);
+    let galley = fonts.layout_no_wrap(data.title, font.clone(), TEXT_COLOR);
+    let position = Pos2::new(title_bar.min.x + FRAME_TITLE_MARGIN, title_bar.center().y - galley.size().y / 2.0);
+    (title_bar, position, galley)
+}
+
+/// The label of `block` rendered from Markdown, wrapped as on the canvas.
+fn label_galley(block: &SavedBlock, fonts: &Fonts, font: &FontId) -> Arc<Galley> {
+    let width = if block.size.x > 0.0 { block.size.x } else { LABEL_WIDTH };
+    Markdown::parse(&block.block_data, &MarkdownStyle::on_white(font.clone(), TEXT_COLOR)).layout(fonts, width)
+}
+
+/// A color as an SVG paint attribute named `name`, with its opacity.
+fn paint(name: &str, color: Color32) -> String {
+    let [r, g, b, a] = color.to_array();
+    if a == 0 {
+        return format!(r#" {}="none""#, name);
+    }
+    // Colors are premultiplied; SVG wants them straight.
+    let straight = |c: u8| (c as u32 * 255 / a as u32).min(255);
+    let mut attribute = format!(r##" {}="#{:02x}{:02x}{:02x}""##, name, straight(r), straight(g), straight(b));
+    if a < 255 {
+        let _ = write!(attribute, r#" {}-opacity="{:.3}""#, name, a as f32 / 255.0);
+    }
+    attribute
+}
+
+fn points_attribute(points: &[Pos2]) -> String {
+    let points: Vec<String> = points.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+    points.join(" ")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders `snapshot` to an image with `scale` pixels per board unit,
+/// without a window or GPU.
+///
+/// Blocks are turned into the same egui shapes as on the canvas, tessellated
+/// and then filled in on the CPU.
+pub fn png(snapshot: &Snapshot, font: &FontId, scale: f32) -> Result<RgbaImage, ExportError> {
+    let size = snapshot.bounds.size() * scale;
+    if size.x > MAX_PNG_SIDE || size.y > MAX_PNG_SIDE || size.x.ceil() * size.y.ceil() > MAX_PNG_PIXELS {
+        return Err(ExportError::TooLarge { width: size.x, height: size.y });
+    }
+
+    // Glyphs are rasterized for this scale, so text stays sharp at any size.
+    let fonts = Fonts::new(scale, 8192, FontDefinitions::default());
+    let mut textures = HashMap::new();
+    let mut image_textures = HashMap::new();
+    for (hash, bytes) in &snapshot.images {
+        if let Ok(image) = image::load_from_memory(bytes) {
+            let id = TextureId::User(textures.len() as u64);
+            textures.insert(id, Texture::from_image(&image.to_rgba8()));
+            image_textures.insert(hash.clone(), id);
+        }
+    }
+
+    let shapes = shapes(snapshot, &fonts, font, &image_textures)
+        .into_iter()
+        .map(|shape| ClippedShape(Rect::EVERYTHING, shape))
+        .collect();
+    if let Some(delta) = fonts.font_image_delta() {
+        Texture::update(&mut textures, TextureId::default(), &delta);
+    }
+    let primitives = tessellator::tessellate_shapes(scale, TessellationOptions::default(), shapes, fonts.font_image_size());
+
+    let mut canvas = Canvas::new(size.x.ceil().max(1.0) as u32, size.y.ceil().max(1.0) as u32, Color32::WHITE);
+    let origin = snapshot.bounds.min;
+    canvas.paint(&primitives, &textures, |p| ((p - origin) * scale).to_pos2());
+    Ok(canvas.into_image())
+}
+
+/// Renders `snapshot` with `png` and writes it to `path`.
+pub fn write_png(snapshot: &Snapshot, font: &FontId, scale: f32, path: &Path) -> Result<(), ExportError> {
+    png(snapshot, font, scale)?
+        .save_with_format(path, ImageFormat::Png)
+        .map_err(ExportError::Image)
+}
+
+/// Exports a board of the database at `database` to a PNG at `output`,
+/// for use from the command line. The database is only read, so it has to
+/// exist and be at the current schema version.
+///
+/// `board` is a board name or id, or `None` for the board opened last.
+/// With `frame`, a frame title or id, only that frame is exported; with
+/// `viewport`, only what a window of that size shows from the board's saved
+/// camera. Otherwise the whole board is.
+pub fn export_board_png(
+    database: PathBuf,
+    board: Option<&str>,
+    frame: Option<&str>,
+    viewport: Option<Vec2>,
+    scale: f32,
+    output: &Path,
+) -> Result<(), ExportError> {
+    let mut persist = Persistor::read_only(database)?;
+    let saved = match board {
+        Some(board) => persist
+            .boards()?
+            .into_iter()
+            .find(|b| b.id == board || b.name == board)
+            .ok_or_else(|| ExportError::NoBoard(board.to_string()))?,
+        None => persist.latest_board()?.ok_or(ExportError::NoBoards)?,
+    };
+
+    let snapshot = match (frame, viewport) {
+        (Some(frame), _) => frame_snapshot(&mut persist, &saved.id, frame)?,
+        (None, Some(size)) => {
+            let area = Rect::from_min_size(saved.offset.to_pos2(), size / saved.zoom);
+            let loaded = persist.load(&saved.id, area.min.x, area.max.x, area.min.y, area.max.y)?;
+            Snapshot::new(&mut persist, loaded.blocks, loaded.connectors, Some(area))?
+        }
+        (None, None) => Snapshot::board(&mut persist, &saved.id)?,
+    };
+    let font = TextStyle::Body.resolve(&egui::Style::default());
+    write_png(&snapshot, &font, scale, output)
+}
+
+/// The frame titled or with the id `frame` on `board`, with everything in it.
+fn frame_snapshot(persist: &mut Persistor, board: &str, frame: &str) -> Result<Snapshot, ExportError> {
+    let area = persist.load(board, f32::MIN, f32::MAX, f32::MIN, f32::MAX)?;
+    let state = BoardState::from(area);
+    let id = state
+        .ids
+        .iter()
+        .filter(|id| state.blocks[*id].block_type == BlockType::Frame)
+        .find(|id| *id == frame || FrameData::parse(&state.blocks[*id].block_data).title == frame)
+        .ok_or_else(|| ExportError::NoFrame(frame.to_string()))?;
+    let bounds = block_rect(&state.positions[id]);
+    let ids = state.with_descendants(std::slice::from_ref(id));
+    let blocks: Vec<SavedBlock> = ids.iter().filter_map(|id| state.saved_block(id)).collect();
+    Ok(Snapshot::new(persist, blocks, state.connectors_of(&ids), Some(bounds))?)
+}
+
+/// The egui shapes for `snapshot`, in board coordinates. Text is laid out
+/// with `fonts`; image blocks are drawn from `images`, keyed by hash.
+fn shapes(snapshot: &Snapshot, fonts: &Fonts, font: &FontId, images: &HashMap<String, TextureId>) -> Vec<Shape> {
+    let mut shapes = Vec::new();
+    for part in parts(snapshot, fonts, font) {
+        match part {
+            Part::Rect { rect, rounding, fill, stroke } => {
+                shapes.push(Shape::Rect(RectShape { rect, rounding: rounding.into(), fill, stroke }));
+            }
+            Part::Text { position, galley } => shapes.push(Shape::galley(position, galley)),
+            Part::Arrow(path) => shapes.extend(arrow_shapes(&path, CONNECTOR_COLOR, 1.0)),
+            Part::Image { rect, hash } => match images.get(hash) {
+                Some(texture) => {
+                    let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+                    shapes.push(Shape::image(*texture, rect, uv, Color32::WHITE));
+                }
+                None => shapes.push(Shape::rect_filled(rect, 0.0, BUTTON_FILL)),
+            },
+            Part::Line { points, color, width } => shapes.push(stroke_shape(&points, color, width)),
+            Part::Shape { rect, style } => shapes.extend(style.shapes(rect, 1.0)),
+        }
+    }
+    shapes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::ConnectorStyle;
+    use crate::test_util::{block, TempDir};
+
+    #[test]
+    fn svg_contains_every_block() {
+        let fonts = Fonts::new(1.0, 2048, FontDefinitions::default());
+        let font = FontId::proportional(14.0);
+        let blocks = vec![
+            block("label", BlockType::Label, "a label that is long enough to wrap <twice>", Pos2::ZERO, Vec2::new(80.0, 60.0)),
+            SavedBlock {
+                style: Some(ShapeStyle::new(ShapeKind::Ellipse)),
+                ..block("shape", BlockType::Shape, "inside", Pos2::new(200.0, 0.0), Vec2::new(160.0, 100.0))
+            },
+            block("stroke", BlockType::Stroke, "ff0000ff 2\n0,0 10,10", Pos2::new(0.0, 200.0), Vec2::new(10.0, 10.0)),
+        ];
+        let snapshot = Snapshot {
+            bounds: Snapshot::fit(&blocks),
+            connectors: vec![Connector {
+                id: String::from("c"),
+                from: String::from("label"),
+                to: String::from("shape"),
+                style: ConnectorStyle::Straight,
+                label: String::from("to & fro"),
+            }],
+            blocks,
+            images: HashMap::new(),
+        };
+
+        let svg = svg(&snapshot, &fonts, &font);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(r#"viewBox="-20 -20 400 250""#));
+        // The label wraps onto several lines, escaped.
+        assert!(svg.matches("<tspan").count() >= 4);
+        assert!(svg.contains("&lt;twice&gt;"));
+        assert!(svg.contains("to &amp; fro"));
+        assert!(svg.contains(r##"stroke="#ff0000""##));
+        assert_eq!(svg.matches("<polygon").count(), 2);
+    }
+
+    #[test]
+    fn svg_labels_keep_their_markdown_formatting() {
+        let fonts = Fonts::new(1.0, 2048, FontDefinitions::default());
+        let font = FontId::proportional(14.0);
+        let blocks = vec![block("label", BlockType::Label, "# Plan\n- **now** and `later`\n- [see](https://example.com)", Pos2::ZERO, Vec2::ZERO)];
+        let snapshot = Snapshot {
+            bounds: Snapshot::fit(&blocks),
+            blocks,
+            connectors: Vec::new(),
+            images: HashMap::new(),
+        };
+
+        let svg = svg(&snapshot, &fonts, &font);
+        assert!(svg.contains(r##"font-size="22.4" fill="#000000">Plan</tspan>"##));
+        assert!(svg.contains(">• </tspan>"));
+        assert!(svg.contains(r#"font-family="monospace""#));
+        assert!(svg.contains(r#"text-decoration="underline">see</tspan>"#));
+        // Inline code has a background drawn behind it.
+        assert!(svg.contains(r##"fill="#ebebeb"/>"##));
+    }
+
+    #[test]
+    fn png_is_rendered_at_scale() {
+        let mut shape = block("shape", BlockType::Shape, "", Pos2::new(10.0, 10.0), Vec2::new(40.0, 20.0));
+        shape.style = Some(ShapeStyle { fill: Color32::RED, ..ShapeStyle::new(ShapeKind::Rectangle) });
+        let blocks = vec![shape, block("label", BlockType::Label, "text", Pos2::new(10.0, 40.0), Vec2::new(40.0, 20.0))];
+        let snapshot = Snapshot {
+            bounds: Snapshot::fit(&blocks),
+            blocks,
+            connectors: Vec::new(),
+            images: HashMap::new(),
+        };
+
+        let image = png(&snapshot, &FontId::proportional(14.0), 2.0).unwrap();
+        assert_eq!(image.dimensions(), (160, 180));
+        // The image starts at board (-10, -10); the shape's middle is at (30, 20).
+        assert_eq!(image.get_pixel(80, 60).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(2, 2).0, [255, 255, 255, 255]);
+        // Some of the label's text is drawn.
+        let label = (40..120).flat_map(|x| (100..140).map(move |y| (x, y)));
+        assert!(label.into_iter().any(|(x, y)| image.get_pixel(x, y).0[0] < 128));
+
+        assert!(matches!(png(&snapshot, &FontId::proportional(14.0), 1000.0), Err(ExportError::TooLarge { .. })));
+        // 9600 by 10800 pixels fits on either side, but is too many pixels.
+        assert!(matches!(png(&snapshot, &FontId::proportional(14.0), 120.0), Err(ExportError::TooLarge { .. })));
+    }
+
+    #[test]
+    fn command_line_exports_only_read_the_database() {
+        let dir = TempDir::new();
+        let (database, png) = (dir.join("board.db"), dir.join("board.png"));
+        let export = || export_board_png(database.clone(), None, None, None, 1.0, &png);
+
+        assert!(matches!(export(), Err(ExportError::Persist(PersistError::Open(_)))));
+        assert!(!database.exists());
+
+        let connection = rusqlite::Connection::open(&database).unwrap();
+        assert!(matches!(export(), Err(ExportError::Persist(PersistError::Version(0)))));
+        assert_eq!(crate::persistor::schema_version(&connection).unwrap(), 0);
+
+        Persistor::new(database.clone()).setup().unwrap();
+        export().unwrap();
+        assert!(png.exists());
+
+        connection.execute("DELETE FROM boards", []).unwrap();
+        assert!(matches!(export(), Err(ExportError::NoBoards)));
+        let boards: i64 = connection.query_row("SELECT count(*) FROM boards", [], |row| row.get(0)).unwrap();
+        assert_eq!(boards, 0);
+    }
+
+    #[test]
+    fn frames_export_with_their_contents() {
+        let dir = TempDir::new();
+        let mut persist = Persistor::new(dir.join("board.db"));
+        persist.setup().unwrap();
+        let board = persist.last_board().unwrap();
+
+        let frame_data = FrameData { title: String::from("Sprint"), collapsed: None };
+        let frame = block("frame", BlockType::Frame, &frame_data.encode(), Pos2::ZERO, Vec2::new(400.0, 300.0));
+        let mut inside = block("inside", BlockType::Label, "inside", Pos2::new(20.0, 40.0), Vec2::new(80.0, 40.0));
+        inside.parent = Some(String::from("frame"));
+        let outside = block("outside", BlockType::Label, "outside", Pos2::new(1000.0, 0.0), Vec2::new(80.0, 40.0));
+        for block in [&inside, &frame, &outside] {
+            persist.on_add(block.clone()).unwrap();
+        }
+
+        let snapshot = frame_snapshot(&mut persist, &board.id, "Sprint").unwrap();
+        assert_eq!(snapshot.bounds, Rect::from_min_size(Pos2::ZERO, Vec2::new(400.0, 300.0)));
+        let ids: Vec<&str> = snapshot.blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["frame", "inside"]);
+        let svg = svg(&snapshot, &Fonts::new(1.0, 2048, FontDefinitions::default()), &FontId::proportional(14.0));
+        assert!(svg.contains(">Sprint</tspan>"));
+        assert!(matches!(frame_snapshot(&mut persist, &board.id, "Backlog"), Err(ExportError::NoFrame(_))));
+
+        // Blocks in a collapsed frame aren't drawn.
+        let collapsed = FrameData { collapsed: Some(300.0), ..frame_data };
+        persist.on_data_change("frame", &collapsed.encode()).unwrap();
+        let snapshot = Snapshot::board(&mut persist, &board.id).unwrap();
+        assert!(snapshot.blocks.iter().all(|b| b.id != "inside"));
+    }
+
+    #[test]
+    fn colors_are_unpremultiplied() {
+        assert_eq!(paint("fill", Color32::TRANSPARENT), r#" fill="none""#);
+        assert_eq!(paint("fill", Color32::from_rgb(255, 0, 16)), r##" fill="#ff0010""##);
+        assert_eq!(
+            paint("stroke", Color32::from_rgba_premultiplied(50, 0, 0, 100)),
+            r##" stroke="#7f0000" stroke-opacity="0.392""##
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+
+use egui::{Pos2, Vec2};
+
+use crate::board_file;
+use crate::demo::{color_from_int, color_to_int, BlockType, Connector, ConnectorStyle, ShapeKind, ShapeStyle};
+use crate::persistor::SavedBlock;
+
+/// First line of every copied fragment, so pasting unrelated text is ignored.
+const HEADER: &str = "boardx-fragment 3";
+
+/// A set of blocks travelling through the system clipboard.
+///
+/// The text format is the header line followed by one line per block, per
+/// block in a copied frame, per connector and per image:
+///
+/// ```text
+/// block <id> <type code> <x> <y> <width> <height> <escaped data> [<shape style>]
+/// parent <id> <frame id>
+/// connector <from id> <to id> <style code> <escaped label>
+/// image <hash> <base64 bytes>
+/// ```
+///
+/// The shape style is only there for shape blocks, as
+/// `<kind code>,<fill>,<stroke>,<stroke width>` with colors packed as in the database.
+///
+/// Block positions are relative to the top left corner of the fragment and
+/// fields are separated by tabs. Ids are only used within the fragment, to
+/// tie blocks to their frames and connectors to their blocks; pasting gives
+/// everything new ones, see `remap_ids`. Images used by image blocks come
+/// along, so the fragment can be pasted into another board or database.
+#[derive(Debug, Default, PartialEq)]
+pub struct Fragment {
+    pub(crate) blocks: Vec<SavedBlock>,
+    /// Connectors between the blocks of the fragment.
+    pub(crate) connectors: Vec<Connector>,
+    pub(crate) images: Vec<(String, Vec<u8>)>,
+}
+
+impl Fragment {
+    /// Builds a fragment from blocks in board coordinates and the connectors
+    /// between them. Blocks stay in the frames that are copied with them;
+    /// the rest go into whichever frame they are pasted onto. Connectors to
+    /// blocks that aren't copied are left out.
+    pub fn new(mut blocks: Vec<SavedBlock>, connectors: Vec<Connector>, images: Vec<(String, Vec<u8>)>) -> Fragment {
+        let origin = blocks
+            .iter()
+            .map(|b| b.position)
+            .reduce(|a, b| a.min(b))
+            .unwrap_or(Pos2::ZERO);
+        let ids: HashSet<String> = blocks.iter().map(|b| b.id.clone()).collect();
+        for block in &mut blocks {
+            block.position = (block.position - origin).to_pos2();
+            if !block.parent.as_ref().map_or(false, |parent| ids.contains(parent)) {
+                block.parent = None;
+            }
+        }
+        let copied = |id: &String| ids.contains(id);
+        let connectors = connectors.into_iter().filter(|c| copied(&c.from) && copied(&c.to)).collect();
+        Fragment { blocks, connectors, images }
+    }
+
+    /// Gives the blocks and connectors new ids, to be pasted as new blocks.
+    pub fn remap_ids(&mut self) {
+        board_file::remap_ids(&mut self.blocks, &mut self.connectors);
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = String::from(HEADER);
+        for block in &self.blocks {
+            text.push_str(&format!(
+                "\nblock\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                escape(&block.id),
+                block.block_type.code(),
+                block.position.x,
+                block.position.y,
+                block.size.x,
+                block.size.y,
+                escape(&block.block_data)
+            ));
+            if let Some(style) = &block.style {
+                text.push_str(&format!(
+                    "\t{},{},{},{}",
+                    style.kind.code(),
+                    color_to_int(style.fill),
+                    color_to_int(style.stroke),
+                    style.stroke_width
+                ));
+            }
+        }
+        for block in &self.blocks {
+            if let Some(parent) = &block.parent {
+                text.push_str(&format!("\nparent\t{}\t{}", escape(&block.id), escape(parent)));
+            }
+        }
+        for connector in &self.connectors {
+            text.push_str(&format!(
+                "\nconnector\t{}\t{}\t{}\t{}",
+                escape(&connector.from),
+                escape(&connector.to),
+                connector.style.code(),
+                escape(&connector.label)
+            ));
+        }
+        for (hash, bytes) in &self.images {
+            text.push_str(&format!("\nimage\t{}\t{}", hash, base64::encode(bytes)));
+        }
+        text
+    }
+
+    /// Parses clipboard text, returning `None` if it isn't a fragment this build understands.
+    pub fn parse(text: &str) -> Option<Fragment> {
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+
+        let mut fragment = Fragment::default();
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["block", id, code, x, y, width, height, data, style @ ..] if style.len() <= 1 => fragment.blocks.push(SavedBlock {
+                    size: Vec2::new(width.parse().ok()?, height.parse().ok()?),
+                    position: Pos2::new(x.parse().ok()?, y.parse().ok()?),
+                    id: unescape(id),
+                    block_type: BlockType::from_code(code.parse().ok()?)?,
+                    block_data: unescape(data),
+                    style: match style.first() {
+                        Some(style) => Some(parse_style(style)?),
+                        None => None,
+                    },
+                    parent: None,
+                }),
+                ["parent", id, parent] => {
+                    let id = unescape(id);
+                    fragment.blocks.iter_mut().find(|b| b.id == id)?.parent = Some(unescape(parent));
+                }
+                ["connector", from, to, style, label] => fragment.connectors.push(Connector {
+                    id: String::new(),
+                    from: unescape(from),
+                    to: unescape(to),
+                    style: ConnectorStyle::from_code(style.parse().ok()?)?,
+                    label: unescape(label),
+                }),
+                ["image", hash, bytes] => fragment.images.push((hash.to_string(), base64::decode(bytes).ok()?)),
+                _ => return None,
+            }
+        }
+        Some(fragment)
+    }
+}
+
+fn parse_style(text: &str) -> Option<ShapeStyle> {
+    match text.split(',').collect::<Vec<_>>().as_slice() {
+        [kind, fill, stroke, stroke_width] => Some(ShapeStyle {
+            kind: ShapeKind::from_code(kind.parse().ok()?)?,
+            fill: color_from_int(fill.parse().ok()?),
+            stroke: color_from_int(stroke.parse().ok()?),
+            stroke_width: stroke_width.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+fn escape(data: &str) -> String {
+    data.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(data: &str) -> String {
+    let mut text = String::with_capacity(data.len());
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => text.push('\t'),
+            Some('n') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::block;
+
+    #[test]
+    fn encode_round_trips() {
+        let blocks = vec![
+            block("a", BlockType::Label, "tab\there\nnew line \\n literal", Pos2::new(150.0, -20.0), Vec2::new(300.0, 80.0)),
+            block("b", BlockType::Image, "abc123", Pos2::new(100.0, 40.0), Vec2::new(64.0, 64.0)),
+            SavedBlock {
+                style: Some(ShapeStyle::new(ShapeKind::StickyNote)),
+                parent: Some(String::from("b")),
+                ..block("c", BlockType::Shape, "note", Pos2::new(400.0, 0.0), Vec2::new(160.0, 160.0))
+            },
+            SavedBlock {
+                parent: Some(String::from("not copied")),
+                ..block("d", BlockType::Label, "", Pos2::new(420.0, 20.0), Vec2::new(10.0, 10.0))
+            },
+        ];
+        let connectors = vec![
+            Connector {
+                id: String::from("ac"),
+                from: String::from("a"),
+                to: String::from("c"),
+                style: ConnectorStyle::Curved,
+                label: String::from("with\ttabs"),
+            },
+            Connector {
+                id: String::from("ax"),
+                from: String::from("a"),
+                to: String::from("not copied"),
+                style: ConnectorStyle::Straight,
+                label: String::new(),
+            },
+        ];
+        let mut fragment = Fragment::new(blocks, connectors, vec![(String::from("abc123"), vec![0, 1, 2, 255])]);
+
+        assert_eq!(fragment.blocks[0].position, Pos2::new(50.0, 0.0));
+        assert_eq!(fragment.blocks[1].position, Pos2::new(0.0, 60.0));
+        assert_eq!(fragment.connectors.len(), 1);
+        // Only frames that are copied along are kept.
+        assert_eq!(fragment.blocks[2].parent.as_deref(), Some("b"));
+        assert_eq!(fragment.blocks[3].parent, None);
+
+        // Connector ids aren't copied, pasting gives them new ones.
+        fragment.connectors[0].id = String::new();
+        assert_eq!(Fragment::parse(&fragment.encode()).unwrap(), fragment);
+    }
+
+    #[test]
+    fn pasted_connectors_join_the_pasted_blocks() {
+        let text = "boardx-fragment 3\nblock\ta\t6\t0\t0\t100\t100\t\nblock\tb\t0\t20\t0\t10\t10\t\nparent\tb\ta\nconnector\ta\tb\t1\tnext";
+        let mut fragment = Fragment::parse(text).unwrap();
+        fragment.remap_ids();
+
+        // Ids and the frame the second block is in are remapped together.
+        assert_eq!(fragment.blocks[1].parent.as_ref(), Some(&fragment.blocks[0].id));
+
+        let connector = &fragment.connectors[0];
+        assert_eq!((&connector.from, &connector.to), (&fragment.blocks[0].id, &fragment.blocks[1].id));
+        assert!(!["", "a", "b"].contains(&connector.from.as_str()));
+        assert_eq!(connector.style, ConnectorStyle::Elbow);
+        assert!(!connector.id.is_empty());
+    }
+
+    #[test]
+    fn other_text_is_not_a_fragment() {
+        assert_eq!(Fragment::parse("hello world"), None);
+        assert_eq!(Fragment::parse("boardx-fragment 3\nblock\ta\t99\t0\t0\t1\t1\t"), None);
+        // A frame for a block that isn't in the fragment.
+        assert_eq!(Fragment::parse("boardx-fragment 3\nparent\ta\tb"), None);
+        assert_eq!(Fragment::parse("boardx-fragment 1\nblock\t0\t0\t0\t1\t1\t"), None);
+    }
+}
@@ -0,0 +1,135 @@
+use egui::{CursorIcon, Pos2, Rect, Vec2};
+
+/// One of the eight drag handles around a selected block.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResizeHandle {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl ResizeHandle {
+    pub const ALL: [ResizeHandle; 8] = [
+        ResizeHandle::North,
+        ResizeHandle::NorthEast,
+        ResizeHandle::East,
+        ResizeHandle::SouthEast,
+        ResizeHandle::South,
+        ResizeHandle::SouthWest,
+        ResizeHandle::West,
+        ResizeHandle::NorthWest,
+    ];
+
+    /// Which edge the handle moves on each axis: -1 for the left or top
+    /// edge, 1 for the right or bottom edge and 0 for neither.
+    fn sides(self) -> (f32, f32) {
+        match self {
+            ResizeHandle::North => (0.0, -1.0),
+            ResizeHandle::NorthEast => (1.0, -1.0),
+            ResizeHandle::East => (1.0, 0.0),
+            ResizeHandle::SouthEast => (1.0, 1.0),
+            ResizeHandle::South => (0.0, 1.0),
+            ResizeHandle::SouthWest => (-1.0, 1.0),
+            ResizeHandle::West => (-1.0, 0.0),
+            ResizeHandle::NorthWest => (-1.0, -1.0),
+        }
+    }
+
+    /// Whether the handle changes the height of the block.
+    pub fn is_vertical(self) -> bool {
+        self.sides().1 != 0.0
+    }
+
+    /// Where the handle sits on the outline of `rect`.
+    pub fn position(self, rect: Rect) -> Pos2 {
+        let (x, y) = self.sides();
+        rect.center() + Vec2::new(x, y) * rect.size() / 2.0
+    }
+
+    pub fn cursor(self) -> CursorIcon {
+        match self {
+            ResizeHandle::North => CursorIcon::ResizeNorth,
+            ResizeHandle::NorthEast => CursorIcon::ResizeNorthEast,
+            ResizeHandle::East => CursorIcon::ResizeEast,
+            ResizeHandle::SouthEast => CursorIcon::ResizeSouthEast,
+            ResizeHandle::South => CursorIcon::ResizeSouth,
+            ResizeHandle::SouthWest => CursorIcon::ResizeSouthWest,
+            ResizeHandle::West => CursorIcon::ResizeWest,
+            ResizeHandle::NorthWest => CursorIcon::ResizeNorthWest,
+        }
+    }
+
+    /// The bounds of a block that was at `start` when this handle was grabbed
+    /// and has since been dragged by `delta`. The edges opposite the handle
+    /// stay in place and the result is never smaller than `min_size`.
+    pub fn resize(self, start: Rect, delta: Vec2, min_size: Vec2, keep_aspect: bool) -> Rect {
+        let (x, y) = self.sides();
+        let mut size = (start.size() + Vec2::new(x * delta.x, y * delta.y)).max(min_size);
+
+        if keep_aspect && start.width() > 0.0 && start.height() > 0.0 {
+            let scale = match (x != 0.0, y != 0.0) {
+                (true, false) => size.x / start.width(),
+                (false, true) => size.y / start.height(),
+                _ => (size.x / start.width()).max(size.y / start.height()),
+            };
+            size = start.size() * scale;
+            size *= (min_size.x / size.x).max(min_size.y / size.y).max(1.0);
+        }
+
+        // Each axis is anchored on the edge opposite the handle, or on the
+        // middle when the handle sits halfway along that axis.
+        let anchor = |side: f32, min: f32, max: f32, length: f32| match side {
+            s if s < 0.0 => max - length,
+            s if s > 0.0 => min,
+            _ => (min + max - length) / 2.0,
+        };
+        let min = Pos2::new(
+            anchor(x, start.min.x, start.max.x, size.x),
+            anchor(y, start.min.y, start.max.y, size.y),
+        );
+        Rect::from_min_size(min, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start() -> Rect {
+        Rect::from_min_size(Pos2::new(100.0, 100.0), Vec2::new(200.0, 100.0))
+    }
+
+    #[test]
+    fn opposite_edges_stay_put() {
+        let rect = ResizeHandle::West.resize(start(), Vec2::new(-50.0, 30.0), Vec2::splat(20.0), false);
+        assert_eq!(rect, Rect::from_min_max(Pos2::new(50.0, 100.0), Pos2::new(300.0, 200.0)));
+
+        let rect = ResizeHandle::SouthEast.resize(start(), Vec2::new(10.0, 20.0), Vec2::splat(20.0), false);
+        assert_eq!(rect, Rect::from_min_max(Pos2::new(100.0, 100.0), Pos2::new(310.0, 220.0)));
+
+        for handle in ResizeHandle::ALL {
+            assert!(start().expand(0.001).contains(handle.position(start())));
+        }
+    }
+
+    #[test]
+    fn size_is_clamped() {
+        let rect = ResizeHandle::NorthWest.resize(start(), Vec2::new(500.0, 500.0), Vec2::new(40.0, 30.0), false);
+        assert_eq!(rect, Rect::from_min_max(Pos2::new(260.0, 170.0), Pos2::new(300.0, 200.0)));
+    }
+
+    #[test]
+    fn aspect_is_kept() {
+        let rect = ResizeHandle::East.resize(start(), Vec2::new(200.0, 0.0), Vec2::splat(20.0), true);
+        assert_eq!(rect, Rect::from_min_max(Pos2::new(100.0, 50.0), Pos2::new(500.0, 250.0)));
+
+        let rect = ResizeHandle::SouthWest.resize(start(), Vec2::new(190.0, -95.0), Vec2::splat(20.0), true);
+        assert_eq!(rect.size(), Vec2::new(40.0, 20.0));
+        assert_eq!(rect.right_top(), start().right_top());
+    }
+}
@@ -0,0 +1,177 @@
+use egui::{Pos2, Rect};
+
+use crate::demo::{Connector, ShapeStyle};
+use crate::persistor::SavedBlock;
+
+/// Oldest steps are dropped once the undo stack grows past this.
+const MAX_HISTORY: usize = 500;
+
+/// A reversible edit to the board.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Create(SavedBlock),
+    Delete(SavedBlock),
+    Move { id: String, from: Pos2, to: Pos2 },
+    /// New bounds for a block, which move it when resized from the top or left.
+    Resize { id: String, from: Rect, to: Rect },
+    Edit { id: String, from: String, to: String },
+    Restyle { id: String, from: ShapeStyle, to: ShapeStyle },
+    /// A block put into a frame, taken out of one or moved between two.
+    Reparent { id: String, from: Option<String>, to: Option<String> },
+    Connect(Connector),
+    Disconnect(Connector),
+    /// A change to a connector's style or label.
+    EditConnector { from: Connector, to: Connector },
+    /// Several commands undone and redone as one step.
+    Batch(Vec<Command>),
+}
+
+impl Command {
+    /// The command that undoes this one.
+    pub fn inverse(&self) -> Command {
+        match self.clone() {
+            Command::Create(block) => Command::Delete(block),
+            Command::Delete(block) => Command::Create(block),
+            Command::Move { id, from, to } => Command::Move { id, from: to, to: from },
+            Command::Resize { id, from, to } => Command::Resize { id, from: to, to: from },
+            Command::Edit { id, from, to } => Command::Edit { id, from: to, to: from },
+            Command::Restyle { id, from, to } => Command::Restyle { id, from: to, to: from },
+            Command::Reparent { id, from, to } => Command::Reparent { id, from: to, to: from },
+            Command::Connect(connector) => Command::Disconnect(connector),
+            Command::Disconnect(connector) => Command::Connect(connector),
+            Command::EditConnector { from, to } => Command::EditConnector { from: to, to: from },
+            Command::Batch(commands) => Command::Batch(commands.iter().rev().map(Command::inverse).collect()),
+        }
+    }
+}
+
+/// Undo and redo stacks for board edits.
+///
+/// Commands are recorded after they have been applied. Consecutive text and
+/// style edits to the same block or connector are merged into one step until `seal` is called, which
+/// the app does whenever the selection changes.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+    open_edit: bool,
+}
+
+impl History {
+    pub fn record(&mut self, command: Command) {
+        if self.open_edit {
+            let merged = match (self.undo.last_mut(), &command) {
+                (Some(Command::Edit { id: last_id, to: last_to, .. }), Command::Edit { id, to, .. }) if last_id == id => {
+                    *last_to = to.clone();
+                    true
+                }
+                (Some(Command::EditConnector { to: last_to, .. }), Command::EditConnector { to, .. }) if last_to.id == to.id => {
+                    *last_to = to.clone();
+                    true
+                }
+                (Some(Command::Restyle { id: last_id, to: last_to, .. }), Command::Restyle { id, to, .. }) if last_id == id => {
+                    *last_to = *to;
+                    true
+                }
+                _ => false,
+            };
+            if merged {
+                self.redo.clear();
+                return;
+            }
+        }
+
+        self.open_edit = matches!(command, Command::Edit { .. } | Command::EditConnector { .. } | Command::Restyle { .. });
+        self.undo.push(command);
+        self.redo.clear();
+
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Ends the current run of merged text edits.
+    pub fn seal(&mut self) {
+        self.open_edit = false;
+    }
+
+    /// Returns the command to apply to undo the last step.
+    pub fn undo(&mut self) -> Option<Command> {
+        self.seal();
+        let command = self.undo.pop()?;
+        let inverse = command.inverse();
+        self.redo.push(command);
+        Some(inverse)
+    }
+
+    /// Returns the command to apply to redo the last undone step.
+    pub fn redo(&mut self) -> Option<Command> {
+        self.seal();
+        let command = self.redo.pop()?;
+        self.undo.push(command.clone());
+        Some(command)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(id: &str, from: &str, to: &str) -> Command {
+        Command::Edit {
+            id: id.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn undo_then_redo() {
+        let mut history = History::default();
+        let step = Command::Move {
+            id: String::from("a"),
+            from: Pos2::new(0.0, 0.0),
+            to: Pos2::new(10.0, 5.0),
+        };
+        history.record(step.clone());
+
+        assert_eq!(history.undo(), Some(step.inverse()));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), Some(step));
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn typing_is_one_step_until_sealed() {
+        let mut history = History::default();
+        history.record(edit("a", "", "h"));
+        history.record(edit("a", "h", "hi"));
+        history.record(edit("b", "", "x"));
+        history.seal();
+        history.record(edit("b", "x", "xy"));
+
+        assert_eq!(history.undo(), Some(edit("b", "xy", "x")));
+        assert_eq!(history.undo(), Some(edit("b", "x", "")));
+        assert_eq!(history.undo(), Some(edit("a", "hi", "")));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn recording_clears_redo() {
+        let mut history = History::default();
+        history.record(edit("a", "", "1"));
+        history.undo();
+        assert!(history.can_redo());
+
+        history.record(edit("a", "", "2"));
+        assert!(!history.can_redo());
+    }
+}
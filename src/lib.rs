@@ -1,12 +1,24 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+mod board_file;
 mod demo;
+mod export;
+mod fragment;
+mod handles;
+mod history;
+mod loader;
 mod persistor;
+mod raster;
 mod state;
+#[cfg(test)]
+mod test_util;
 mod view;
+mod write_behind;
 
 pub use app::App;
+pub use export::{export_board_png, ExportError};
+pub use persistor::DEFAULT_DATABASE;
 
 // ----------------------------------------------------------------------------
 // When compiling for web:
@@ -27,5 +39,5 @@ pub fn start(canvas_id: &str) -> Result<(), eframe::wasm_bindgen::JsValue> {
     // Redirect tracing to console.log and friends:
     tracing_wasm::set_as_global_default();
 
-    eframe::start_web(canvas_id, Box::new(|cc| Box::new(App::new(cc))))
+    eframe::start_web(canvas_id, Box::new(|cc| Box::new(App::new(cc, DEFAULT_DATABASE.into()))))
 }
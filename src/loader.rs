@@ -0,0 +1,220 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use egui::Rect;
+use rusqlite::{Connection, ErrorCode, InterruptHandle};
+
+use crate::persistor::{PersistError, Persistor};
+use crate::state::BoardState;
+
+/// What the loader thread hands back for each requested area.
+pub(crate) type LoadResult = Result<BoardState, PersistError>;
+
+/// A finished load.
+pub(crate) struct Loaded {
+    /// The board it was loaded from.
+    pub(crate) board: String,
+    /// When the load was requested; every edit made before then is in it.
+    pub(crate) requested: Instant,
+    pub(crate) result: LoadResult,
+}
+
+enum Request {
+    /// Load the blocks of a board overlapping an area of it.
+    Load { board: String, area: Rect, requested: Instant },
+    Shutdown,
+}
+
+/// Loads board areas on a worker thread, so panning never waits on the database.
+///
+/// Only the newest request matters: older ones still waiting are dropped and
+/// a load that is already running is interrupted when a new one comes in.
+pub struct Loader {
+    requests: Sender<Request>,
+    results: Receiver<Loaded>,
+    /// Cancels the query the worker is running, once it has a connection.
+    interrupt: Arc<Mutex<Option<InterruptHandle>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Loader {
+    /// Starts the worker, which loads through `persist` and repaints `ctx`
+    /// whenever a result is ready.
+    pub fn spawn(persist: Persistor, ctx: egui::Context) -> Loader {
+        Loader::spawn_watched(persist, ctx, |_| ())
+    }
+
+    /// Like `spawn`, but first hands the worker's connection to `watch`.
+    fn spawn_watched(persist: Persistor, ctx: egui::Context, watch: impl FnOnce(&Connection) + Send + 'static) -> Loader {
+        let (requests, request_receiver) = channel();
+        let (result_sender, results) = channel();
+        let interrupt = Arc::new(Mutex::new(None));
+
+        let worker_interrupt = interrupt.clone();
+        let worker = thread::spawn(move || {
+            if let Ok(handle) = persist.interrupt_handle() {
+                *worker_interrupt.lock().unwrap() = Some(handle);
+            }
+            if let Ok(connection) = persist.connection() {
+                watch(&connection);
+            }
+
+            while let Ok(Request::Load { mut board, mut area, mut requested }) = request_receiver.recv() {
+                for newer in request_receiver.try_iter() {
+                    match newer {
+                        Request::Load { board: newer_board, area: newer_area, requested: newer_requested } => {
+                            board = newer_board;
+                            area = newer_area;
+                            requested = newer_requested;
+                        }
+                        Request::Shutdown => return,
+                    }
+                }
+
+                let result = persist.load(&board, area.min.x, area.max.x, area.min.y, area.max.y);
+                if let Err(PersistError::Query(rusqlite::Error::SqliteFailure(e, _))) = &result {
+                    if e.code == ErrorCode::OperationInterrupted {
+                        // Superseded by a newer request, which is already queued.
+                        continue;
+                    }
+                }
+
+                if result_sender.send(Loaded { board, requested, result: result.map(BoardState::from) }).is_err() {
+                    return;
+                }
+                ctx.request_repaint();
+            }
+        });
+
+        Loader {
+            requests,
+            results,
+            interrupt,
+            worker: Some(worker),
+        }
+    }
+
+    /// Asks for the blocks of `board` in `area`, cancelling any load still running.
+    pub fn request(&self, board: &str, area: Rect) {
+        self.cancel();
+        let _ = self.requests.send(Request::Load { board: board.to_string(), area, requested: Instant::now() });
+    }
+
+    /// The next finished load, if there is one.
+    pub fn try_recv(&self) -> Option<Loaded> {
+        self.results.try_recv().ok()
+    }
+
+    /// Cancels any running load and waits for the worker to stop.
+    pub fn shutdown(&mut self) {
+        self.cancel();
+        let _ = self.requests.send(Request::Shutdown);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+
+    fn cancel(&self) {
+        if let Some(handle) = self.interrupt.lock().unwrap().as_ref() {
+            handle.interrupt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistor::DEFAULT_BOARD;
+    use crate::test_util::TempDir;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// A loader whose loads only end by being interrupted while `slow` has a
+    /// row: shape styles come from a view that counts forever. In WAL mode
+    /// the row can be deleted while a slow load is still counting. The
+    /// receiver gets a message once a load is counting.
+    fn slow_loader(dir: &Path) -> (Loader, Receiver<()>) {
+        let mut persist = Persistor::new(dir.join("board.db"));
+        persist.setup().unwrap();
+        persist
+            .connection()
+            .unwrap()
+            .execute_batch(
+                "PRAGMA journal_mode = WAL;
+                INSERT INTO blocks (id, type, data, x, y, width, height) VALUES ('a', 0, '', 0, 0, 10, 10);
+                CREATE TABLE slow (x);
+                INSERT INTO slow VALUES (1);
+                DROP TABLE shape_styles;
+                CREATE VIEW shape_styles AS
+                    WITH RECURSIVE count(n) AS (SELECT 0 FROM slow UNION ALL SELECT n + 1 FROM count)
+                    SELECT '' AS id, 0 AS kind, 0 AS fill, 0 AS stroke, 0 AS stroke_width FROM count WHERE n < 0;",
+            )
+            .unwrap();
+
+        let (counting, running) = channel();
+        let counting = Mutex::new(counting);
+        let loader = Loader::spawn_watched(persist, egui::Context::default(), move |connection| {
+            // Far more steps than any load of this small database takes otherwise.
+            connection.progress_handler(100_000, Some(move || {
+                let _ = counting.lock().unwrap().send(());
+                false
+            }));
+        });
+        (loader, running)
+    }
+
+    /// Makes loads that start from now on fast again.
+    fn speed_up(dir: &Path) {
+        let connection = Connection::open(dir.join("board.db")).unwrap();
+        connection.execute("DELETE FROM slow", []).unwrap();
+    }
+
+    fn area() -> Rect {
+        Rect::from_min_max(egui::Pos2::new(-50.0, -50.0), egui::Pos2::new(50.0, 50.0))
+    }
+
+    #[test]
+    fn running_loads_are_interrupted() {
+        let dir = TempDir::new();
+        let (mut loader, running) = slow_loader(dir.path());
+
+        loader.request(DEFAULT_BOARD, area());
+        running.recv_timeout(Duration::from_secs(10)).unwrap();
+        speed_up(dir.path());
+        loader.request("other", area());
+
+        // Would never come if the slow load wasn't interrupted.
+        let loaded = loader.results.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(loaded.board, "other");
+        assert!(loaded.result.unwrap().ids.is_empty());
+
+        // The slow load can't end any other way than by being interrupted,
+        // and an interrupted load sends nothing.
+        loader.shutdown();
+        assert!(loader.try_recv().is_none());
+    }
+
+    #[test]
+    fn stale_requests_are_dropped() {
+        let dir = TempDir::new();
+        let (mut loader, running) = slow_loader(dir.path());
+
+        loader.request(DEFAULT_BOARD, area());
+        running.recv_timeout(Duration::from_secs(10)).unwrap();
+        speed_up(dir.path());
+        // Queued behind the running load without interrupting it.
+        for i in 0..5 {
+            loader
+                .requests
+                .send(Request::Load { board: format!("stale {}", i), area: area(), requested: Instant::now() })
+                .unwrap();
+        }
+        loader.request("newest", area());
+
+        assert_eq!(loader.results.recv_timeout(Duration::from_secs(10)).unwrap().board, "newest");
+        loader.shutdown();
+        assert!(loader.try_recv().is_none());
+    }
+}
@@ -1,13 +1,106 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use std::path::PathBuf;
+
 use egui::Vec2;
 
+#[cfg(not(target_arch = "wasm32"))]
+const USAGE: &str = "usage: boardx [DATABASE] [--export-png FILE [--board NAME] [--frame TITLE | --viewport WIDTHxHEIGHT] [--scale N]]";
+
+/// What the command line asks for.
+#[cfg(not(target_arch = "wasm32"))]
+struct Arguments {
+    /// The board file to open: the first plain argument, else the
+    /// `BOARDX_DB` environment variable, else `boardx::DEFAULT_DATABASE`.
+    database: PathBuf,
+    /// Render a board to this PNG and exit instead of opening a window.
+    png: Option<PathBuf>,
+    /// Name or id of the board to export; the one opened last otherwise.
+    board: Option<String>,
+    /// Title or id of the frame to export, rather than the whole board.
+    frame: Option<String>,
+    /// Pixels per board unit in the PNG.
+    scale: f32,
+    /// Export only what a window of this size shows, rather than the whole board.
+    viewport: Option<Vec2>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut database = None;
+    let mut arguments = Arguments {
+        database: PathBuf::new(),
+        png: None,
+        board: None,
+        frame: None,
+        scale: 1.0,
+        viewport: None,
+    };
+
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .and_then(|v| v.into_string().ok())
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match arg.to_str() {
+            Some("--export-png") => arguments.png = Some(PathBuf::from(value("--export-png")?)),
+            Some("--board") => arguments.board = Some(value("--board")?),
+            Some("--frame") => arguments.frame = Some(value("--frame")?),
+            Some("--scale") => {
+                arguments.scale = match value("--scale")?.parse() {
+                    Ok(scale) if scale > 0.0 => scale,
+                    _ => return Err(String::from("--scale needs a positive number")),
+                }
+            }
+            Some("--viewport") => {
+                let size = value("--viewport")?;
+                let parsed = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some(Vec2::new(w.parse().ok()?, h.parse().ok()?)));
+                arguments.viewport = Some(parsed.ok_or_else(|| format!("--viewport needs WIDTHxHEIGHT, not {}", size))?);
+            }
+            Some(flag) if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ if database.is_none() => database = Some(PathBuf::from(arg)),
+            _ => return Err(String::from("only one database can be given")),
+        }
+    }
+
+    if arguments.frame.is_some() && arguments.viewport.is_some() {
+        return Err(String::from("--frame and --viewport can't be used together"));
+    }
+
+    arguments.database = database
+        .or_else(|| std::env::var_os("BOARDX_DB").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(boardx::DEFAULT_DATABASE));
+    Ok(arguments)
+}
+
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
     // Log to stdout (if you run with `RUST_LOG=debug`).
 
+    let arguments = match parse_arguments() {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let database = arguments.database;
+
+    if let Some(png) = arguments.png {
+        let (board, frame) = (arguments.board.as_deref(), arguments.frame.as_deref());
+        if let Err(e) = boardx::export_board_png(database, board, frame, arguments.viewport, arguments.scale, &png) {
+            eprintln!("Could not export {}: {}", png.display(), e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let native_options = eframe::NativeOptions {
         drag_and_drop_support: true,
         initial_window_size: Some(Vec2::new(1920.00, 1080.00)),
@@ -16,6 +109,6 @@ fn main() {
     eframe::run_native(
         "boardx",
         native_options,
-        Box::new(|cc| Box::new(boardx::App::new(cc))),
+        Box::new(|cc| Box::new(boardx::App::new(cc, database))),
     );
 }
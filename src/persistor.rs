@@ -1,103 +1,1707 @@
-use egui::{Pos2, Vec2};
-use egui::Key::N;
-use rusqlite::{Connection, MappedRows, params, Row};
-use std::sync::{Arc, RwLock};
+use egui::{Pos2, Rect, Vec2};
+use rusqlite::{params, Connection, InterruptHandle, OpenFlags, OptionalExtension, Row, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, SendError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 
-use crate::demo::BlockType;
-use crate::state::BoardState;
+use crate::demo::{color_from_int, color_to_int, BlockType, ChecklistChange, ChecklistData, ChecklistItem, Connector, ShapeStyle};
+use crate::write_behind::{self, Message};
 
-pub struct Persistor {}
+/// Reads and writes the board database.
+///
+/// Once `start_write_behind` has been called, board edits are queued for a
+/// background thread instead of being written straight away; see `write_behind`.
+pub struct Persistor {
+    /// The database file, shared with the persistors of the loader and
+    /// write-behind threads so they follow when it changes.
+    database: Arc<Mutex<PathBuf>>,
+    /// Whether the file is opened without creating or changing it; see `read_only`.
+    read_only: bool,
+    /// The board new blocks are added to.
+    board: String,
+    queue: Option<Sender<Message>>,
+    errors: Option<Receiver<PersistError>>,
+    writer: Option<JoinHandle<()>>,
+}
 
-#[derive(Debug)]
+impl Default for Persistor {
+    fn default() -> Self {
+        Persistor::new(PathBuf::from(DEFAULT_DATABASE))
+    }
+}
+
+impl Persistor {
+    pub fn new(database: PathBuf) -> Persistor {
+        Persistor {
+            database: Arc::new(Mutex::new(database)),
+            read_only: false,
+            board: String::from(DEFAULT_BOARD),
+            queue: None,
+            errors: None,
+            writer: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SavedBlock {
     pub(crate) size: Vec2,
     pub(crate) position: Pos2,
     pub(crate) id: String,
+    #[serde(rename = "type")]
     pub(crate) block_type: BlockType,
+    #[serde(rename = "data")]
     pub(crate) block_data: String,
+    /// Set for `BlockType::Shape` blocks only, saved in `shape_styles`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) style: Option<ShapeStyle>,
+    /// Id of the frame the block is in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent: Option<String>,
 }
 
-impl Default for Persistor {
-    fn default() -> Self {
-        return Self {};
+/// One of the boards in the database, each its own infinite canvas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedBoard {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    /// Top left corner of the camera when the board was last viewed, in board coordinates.
+    pub(crate) offset: Vec2,
+    pub(crate) zoom: f32,
+}
+
+impl SavedBoard {
+    pub fn new(id: String, name: String) -> SavedBoard {
+        SavedBoard {
+            id,
+            name,
+            offset: Vec2::ZERO,
+            zoom: 1.0,
+        }
     }
 }
 
+/// Where the board database is kept unless another file is given.
+pub const DEFAULT_DATABASE: &str = "./boardx.db";
+
+/// The board that blocks from before boards existed were moved to.
+pub(crate) const DEFAULT_BOARD: &str = "default";
+
+/// Everything loaded for one viewport.
+#[derive(Debug, Default)]
+pub struct SavedArea {
+    /// The blocks overlapping the viewport, followed by the off-screen ends
+    /// of `connectors` and the frames and frame contents that go with them.
+    pub(crate) blocks: Vec<SavedBlock>,
+    pub(crate) connectors: Vec<Connector>,
+}
+
+/// Anything that can go wrong while reading or writing the board database.
+#[derive(Debug)]
+pub enum PersistError {
+    /// The database file could not be opened.
+    Open(rusqlite::Error),
+    /// Upgrading the schema to `version` failed; the database is left at the previous version.
+    Migrate { version: usize, source: rusqlite::Error },
+    /// A query against an open, migrated database failed.
+    Query(rusqlite::Error),
+    /// Saving a copy of the database would have overwritten this file.
+    Exists(PathBuf),
+    /// A database opened read-only is at another schema version than this build's.
+    Version(usize),
+    /// SQLite can only be given paths that are valid UTF-8.
+    NotUtf8(PathBuf),
+}
+
+impl Display for PersistError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersistError::Open(e) => write!(f, "could not open the board database: {}", e),
+            PersistError::Migrate { version, source } => {
+                write!(f, "could not upgrade the board database to version {}: {}", version, source)
+            }
+            PersistError::Query(e) => write!(f, "board database query failed: {}", e),
+            PersistError::Exists(path) => write!(f, "{} already exists", path.display()),
+            PersistError::Version(version) => write!(
+                f,
+                "the board database is at version {} instead of {}, open it in boardx first",
+                version,
+                MIGRATIONS.len()
+            ),
+            PersistError::NotUtf8(path) => write!(f, "{} is not a valid UTF-8 path", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for PersistError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PersistError::Open(e) | PersistError::Query(e) => Some(e),
+            PersistError::Migrate { source, .. } => Some(source),
+            PersistError::Exists(_) | PersistError::NotUtf8(_) | PersistError::Version(_) => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for PersistError {
+    fn from(e: rusqlite::Error) -> Self {
+        PersistError::Query(e)
+    }
+}
+
+/// A single schema upgrade, moving the database from `user_version` N to N + 1.
+type Migration = fn(&Transaction<'_>) -> rusqlite::Result<()>;
+
+/// Schema migrations in the order they must be applied. The database's
+/// `PRAGMA user_version` records how many of these have already run, so
+/// new steps must only ever be appended to the end of this list.
+const MIGRATIONS: &[Migration] = &[
+    create_blocks,
+    add_block_size,
+    add_block_bounds,
+    encode_block_types,
+    create_images,
+    create_connectors,
+    create_shape_styles,
+    create_boards,
+    add_block_parents,
+    add_connector_bounds,
+    create_checklist_items,
+];
+
+/// Version 1: the original `blocks` table. Databases created before versioning
+/// already have it, which is why this uses `IF NOT EXISTS`.
+fn create_blocks(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS blocks (id TEXT, type INTEGER, data TEXT, x REAL, y REAL)",
+        params![],
+    )?;
+    Ok(())
+}
+
+/// Version 2: persist the measured size of each block.
+fn add_block_size(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    for column in ["width", "height"] {
+        if !has_column(tx, "blocks", column)? {
+            tx.execute(
+                &format!("ALTER TABLE blocks ADD COLUMN {} REAL NOT NULL DEFAULT 0", column),
+                params![],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Version 3: an R*Tree over block bounds so viewport loads only visit the
+/// blocks that overlap the requested area. Triggers keep it in sync with every
+/// write to `blocks`; entries are keyed by the block's rowid.
+fn add_block_bounds(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS blocks_id ON blocks (id);
+        CREATE VIRTUAL TABLE block_bounds USING rtree (key, min_x, max_x, min_y, max_y);
+        INSERT INTO block_bounds SELECT rowid, x, x + width, y, y + height FROM blocks;
+        CREATE TRIGGER block_bounds_insert AFTER INSERT ON blocks BEGIN
+            INSERT INTO block_bounds VALUES (new.rowid, new.x, new.x + new.width, new.y, new.y + new.height);
+        END;
+        CREATE TRIGGER block_bounds_update AFTER UPDATE OF x, y, width, height ON blocks BEGIN
+            UPDATE block_bounds
+            SET min_x = new.x, max_x = new.x + new.width, min_y = new.y, max_y = new.y + new.height
+            WHERE key = new.rowid;
+        END;
+        CREATE TRIGGER block_bounds_delete AFTER DELETE ON blocks BEGIN
+            DELETE FROM block_bounds WHERE key = old.rowid;
+        END;",
+    )
+}
+
+/// Version 4: block types used to be written as their `Display` name; store
+/// the stable `BlockType::code` instead. Names this build doesn't know are
+/// left alone so `load_blocks` can skip them.
+fn encode_block_types(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    for block_type in [BlockType::Label, BlockType::Button] {
+        tx.execute(
+            "UPDATE blocks SET type = ? WHERE type = ?",
+            params![block_type, block_type.to_string()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Version 5: image bytes, stored once per distinct image and keyed by
+/// `content_hash` so duplicated image blocks share a row.
+fn create_images(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute(
+        "CREATE TABLE images (hash TEXT PRIMARY KEY, data BLOB NOT NULL)",
+        params![],
+    )?;
+    Ok(())
+}
+
+/// Version 6: arrows between two blocks. Connectors don't have bounds of
+/// their own, they are found through the bounds of the blocks they join.
+fn create_connectors(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE connectors (
+            id TEXT PRIMARY KEY,
+            from_id TEXT NOT NULL,
+            to_id TEXT NOT NULL,
+            style INTEGER NOT NULL DEFAULT 0,
+            label TEXT NOT NULL DEFAULT ''
+        );
+        CREATE INDEX connectors_from ON connectors (from_id);
+        CREATE INDEX connectors_to ON connectors (to_id);",
+    )
+}
+
+/// Version 7: the style of shape blocks, one row per shape keyed by block id.
+/// Colors are premultiplied RGBA packed into an integer, see `color_to_int`.
+fn create_shape_styles(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE shape_styles (
+            id TEXT PRIMARY KEY,
+            kind INTEGER NOT NULL,
+            fill INTEGER NOT NULL,
+            stroke INTEGER NOT NULL,
+            stroke_width REAL NOT NULL
+        );
+        CREATE TRIGGER shape_styles_delete AFTER DELETE ON blocks BEGIN
+            DELETE FROM shape_styles WHERE id = old.id;
+        END;",
+    )
+}
+
+/// Version 8: separate boards in one database. Existing blocks all go to
+/// `DEFAULT_BOARD`, which is also the first board of a new database.
+/// Deleting a board deletes its blocks and their connectors.
+fn create_boards(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE boards (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            x REAL NOT NULL DEFAULT 0,
+            y REAL NOT NULL DEFAULT 0,
+            zoom REAL NOT NULL DEFAULT 1,
+            opened_at INTEGER NOT NULL DEFAULT 0
+        );
+        INSERT INTO boards (id, name) VALUES ('default', 'Board');
+        ALTER TABLE blocks ADD COLUMN board_id TEXT NOT NULL DEFAULT 'default';
+        CREATE INDEX blocks_board ON blocks (board_id);
+        CREATE TRIGGER boards_delete AFTER DELETE ON boards BEGIN
+            DELETE FROM connectors WHERE from_id IN (SELECT id FROM blocks WHERE board_id = old.id)
+                OR to_id IN (SELECT id FROM blocks WHERE board_id = old.id);
+            DELETE FROM blocks WHERE board_id = old.id;
+        END;",
+    )
+}
+
+/// Version 9: the frame each block is in. Loading a frame loads the blocks
+/// inside it too, which are found through the index.
+fn add_block_parents(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE blocks ADD COLUMN parent TEXT;
+        CREATE INDEX blocks_parent ON blocks (parent);",
+    )
+}
+
+/// Version 10: an R*Tree over the area spanned by each connector's two
+/// blocks, so viewport loads only visit the connectors crossing the viewport.
+/// Entries are keyed by the connector's rowid and kept up to date by
+/// triggers, which find the connectors of a block through their indexes.
+fn add_connector_bounds(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    let bounds = "SELECT connectors.rowid, min(a.x, b.x), max(a.x + a.width, b.x + b.width),
+            min(a.y, b.y), max(a.y + a.height, b.y + b.height)
+        FROM connectors JOIN blocks AS a ON a.id = connectors.from_id JOIN blocks AS b ON b.id = connectors.to_id";
+    tx.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE connector_bounds USING rtree (key, min_x, max_x, min_y, max_y);
+        INSERT INTO connector_bounds {bounds};
+        CREATE TRIGGER connector_bounds_insert AFTER INSERT ON connectors BEGIN
+            INSERT INTO connector_bounds {bounds} WHERE connectors.rowid = new.rowid;
+        END;
+        CREATE TRIGGER connector_bounds_delete AFTER DELETE ON connectors BEGIN
+            DELETE FROM connector_bounds WHERE key = old.rowid;
+        END;
+        CREATE TRIGGER connector_bounds_block_insert AFTER INSERT ON blocks BEGIN
+            DELETE FROM connector_bounds WHERE key IN
+                (SELECT rowid FROM connectors WHERE from_id = new.id UNION SELECT rowid FROM connectors WHERE to_id = new.id);
+            INSERT INTO connector_bounds {bounds} WHERE connectors.from_id = new.id
+                UNION {bounds} WHERE connectors.to_id = new.id;
+        END;
+        CREATE TRIGGER connector_bounds_block_update AFTER UPDATE OF x, y, width, height ON blocks BEGIN
+            DELETE FROM connector_bounds WHERE key IN
+                (SELECT rowid FROM connectors WHERE from_id = new.id UNION SELECT rowid FROM connectors WHERE to_id = new.id);
+            INSERT INTO connector_bounds {bounds} WHERE connectors.from_id = new.id
+                UNION {bounds} WHERE connectors.to_id = new.id;
+        END;",
+        bounds = bounds
+    ))
+}
+
+/// Version 11: checklist items in rows of their own, ordered by `position`,
+/// so checking one off or moving it doesn't rewrite the whole list. The
+/// block keeps only the title. Items go with their block when it is deleted.
+fn create_checklist_items(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE checklist_items (
+            block_id TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            done INTEGER NOT NULL
+        );
+        CREATE INDEX checklist_items_block ON checklist_items (block_id, position);
+        CREATE TRIGGER checklist_items_delete AFTER DELETE ON blocks BEGIN
+            DELETE FROM checklist_items WHERE block_id = old.id;
+        END;",
+    )?;
+
+    let checklists = {
+        let mut stmt = tx.prepare("SELECT id, data FROM blocks WHERE type = ?")?;
+        let rows = stmt.query_map([BlockType::Checklist], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    for (id, data) in checklists {
+        let data = ChecklistData::parse(&data);
+        apply_checklist_change(tx, &id, &ChecklistChange::Title(data.title))?;
+        apply_checklist_change(tx, &id, &ChecklistChange::Items(data.items))?;
+    }
+    Ok(())
+}
+
+fn has_column(connection: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let mut names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    names.try_fold(false, |found, name| Ok(found || name? == column))
+}
+
+/// Hex encoded SHA-256 of `bytes`, used as the key of the `images` table.
+pub(crate) fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Deletes stored images that no image block uses any more, such as those of
+/// deleted blocks and boards. Only done when a database is opened, so that
+/// undoing a deletion made since still finds its image.
+fn delete_unused_images(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute(
+        "DELETE FROM images WHERE NOT EXISTS (SELECT 1 FROM blocks WHERE type = ? AND data = images.hash)",
+        [BlockType::Image.code()],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn schema_version(connection: &Connection) -> rusqlite::Result<usize> {
+    connection.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Brings the database up to the latest schema, applying each pending
+/// migration in its own transaction together with the version bump.
+pub(crate) fn migrate(connection: &Connection) -> Result<(), PersistError> {
+    let current = schema_version(connection)?;
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let apply = || {
+            let tx = connection.unchecked_transaction()?;
+            migration(&tx)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()
+        };
+        apply().map_err(|source| PersistError::Migrate { version: version + 1, source })?;
+    }
+
+    Ok(())
+}
+
+fn load_blocks(
+    connection: &Connection,
+    board: &str,
+    x_min: f32,
+    x_max: f32,
+    y_min: f32,
+    y_max: f32,
+) -> rusqlite::Result<Vec<SavedBlock>> {
+    let mut stmt = connection.prepare_cached(
+            "SELECT blocks.id, blocks.type, blocks.data, blocks.x, blocks.y, blocks.width, blocks.height,
+                shape_styles.kind, shape_styles.fill, shape_styles.stroke, shape_styles.stroke_width, blocks.parent
+            FROM block_bounds JOIN blocks ON blocks.rowid = block_bounds.key
+            LEFT JOIN shape_styles ON shape_styles.id = blocks.id
+            WHERE block_bounds.max_x > ? AND block_bounds.min_x < ? AND block_bounds.max_y > ? AND block_bounds.min_y < ?
+            AND blocks.board_id = ?",
+    )?;
+
+    let block_iter = stmt.query_map(params![x_min, x_max, y_min, y_max, board], saved_block)?;
+
+    let mut blocks: Vec<SavedBlock> = Vec::new();
+
+    for block in block_iter {
+        blocks.extend(block?);
+    }
+
+    Ok(blocks)
+}
+
+/// Reads a row of `id, type, data, x, y, width, height` followed by the
+/// `kind, fill, stroke, stroke_width` of its shape style, which may be null,
+/// and the block's `parent`.
+fn saved_block(row: &Row<'_>) -> rusqlite::Result<Option<SavedBlock>> {
+    let id: String = row.get(0)?;
+    let (block_type, kind) = match (row.get(1), row.get::<_, Option<_>>(7)) {
+        (Ok(block_type), Ok(kind)) => (block_type, kind),
+        // Blocks of a kind this build doesn't understand stay untouched in
+        // the database instead of being loaded (and saved back) as something else.
+        (Err(rusqlite::Error::IntegralValueOutOfRange(..)), _)
+        | (Err(rusqlite::Error::InvalidColumnType(..)), _)
+        | (_, Err(rusqlite::Error::IntegralValueOutOfRange(..))) => {
+            log::warn!("skipping block {} with unknown type {:?} {:?}", id, row.get_ref(1)?, row.get_ref(7)?);
+            return Ok(None);
+        }
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    };
+    let style = match kind {
+        Some(kind) => Some(ShapeStyle {
+            kind,
+            fill: color_from_int(row.get(8)?),
+            stroke: color_from_int(row.get(9)?),
+            stroke_width: row.get(10)?,
+        }),
+        None => None,
+    };
+    Ok(Some(SavedBlock {
+        size: Vec2::new(row.get(5)?, row.get(6)?),
+        position: Pos2::new(row.get(3)?, row.get(4)?),
+        id,
+        block_type,
+        block_data: row.get(2)?,
+        style,
+        parent: row.get(11)?,
+    }))
+}
+
+/// The blocks `seeds` together with the frames they are in and everything
+/// inside those frames, however deeply, found in one recursive query through
+/// the `id` and `parent` indexes.
+fn load_related(connection: &Connection, seeds: &[&String]) -> rusqlite::Result<Vec<SavedBlock>> {
+    let mut stmt = connection.prepare_cached(
+        "WITH RECURSIVE related(id) AS (
+            SELECT value FROM json_each(?)
+            UNION
+            SELECT blocks.parent FROM related JOIN blocks ON blocks.id = related.id WHERE blocks.parent IS NOT NULL
+            UNION
+            SELECT blocks.id FROM related JOIN blocks ON blocks.parent = related.id
+        )
+        SELECT blocks.id, blocks.type, blocks.data, blocks.x, blocks.y, blocks.width, blocks.height,
+            shape_styles.kind, shape_styles.fill, shape_styles.stroke, shape_styles.stroke_width, blocks.parent
+        FROM related JOIN blocks ON blocks.id = related.id
+        LEFT JOIN shape_styles ON shape_styles.id = blocks.id",
+    )?;
+    let seeds = serde_json::to_string(seeds).expect("ids always serialize");
+    let related = stmt.query_map([seeds], saved_block)?;
+
+    let mut blocks = Vec::new();
+    for block in related {
+        blocks.extend(block?);
+    }
+    Ok(blocks)
+}
+
+/// Connectors whose two blocks together span an area overlapping the given
+/// one, so an arrow crossing the viewport is found even when both of its
+/// ends are off-screen. Found through `connector_bounds`.
+fn load_connectors(
+    connection: &Connection,
+    board: &str,
+    x_min: f32,
+    x_max: f32,
+    y_min: f32,
+    y_max: f32,
+) -> rusqlite::Result<Vec<Connector>> {
+    let mut stmt = connection.prepare_cached(
+        "SELECT connectors.id, connectors.from_id, connectors.to_id, connectors.style, connectors.label
+        FROM connector_bounds JOIN connectors ON connectors.rowid = connector_bounds.key
+        JOIN blocks AS a ON a.id = connectors.from_id JOIN blocks AS b ON b.id = connectors.to_id
+        WHERE connector_bounds.max_x > ? AND connector_bounds.min_x < ? AND connector_bounds.max_y > ? AND connector_bounds.min_y < ?
+        AND a.board_id = ?",
+    )?;
+
+    let connector_iter = stmt.query_map(params![x_min, x_max, y_min, y_max, board], |row| {
+        let id: String = row.get(0)?;
+        let style = match row.get(3) {
+            Ok(style) => style,
+            Err(rusqlite::Error::IntegralValueOutOfRange(..)) | Err(rusqlite::Error::InvalidColumnType(..)) => {
+                log::warn!("skipping connector {} with unknown style {:?}", id, row.get_ref(3)?);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Some(Connector {
+            id,
+            from: row.get(1)?,
+            to: row.get(2)?,
+            style,
+            label: row.get(4)?,
+        }))
+    })?;
+
+    let mut connectors = Vec::new();
+    for connector in connector_iter {
+        connectors.extend(connector?);
+    }
+    Ok(connectors)
+}
+
+/// Inserts a block on `board` together with its shape style or checklist
+/// items, if it has any. Writes are always applied in a transaction, so
+/// they go in together.
+fn add_block(connection: &Connection, board: &str, block: &SavedBlock) -> rusqlite::Result<()> {
+    let checklist = (block.block_type == BlockType::Checklist).then(|| ChecklistData::parse(&block.block_data));
+    connection.execute(
+        "INSERT INTO blocks (id, board_id, type, data, x, y, width, height, parent) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            block.id,
+            board,
+            block.block_type,
+            checklist.as_ref().map_or(&block.block_data, |checklist| &checklist.title),
+            block.position.x,
+            block.position.y,
+            block.size.x,
+            block.size.y,
+            block.parent
+        ],
+    )?;
+    if let Some(style) = &block.style {
+        save_style(connection, &block.id, style)?;
+    }
+    if let Some(checklist) = checklist {
+        apply_checklist_change(connection, &block.id, &ChecklistChange::Items(checklist.items))?;
+    }
+    Ok(())
+}
+
+/// Writes one checklist edit, touching only the rows of the items it is about.
+fn apply_checklist_change(connection: &Connection, id: &str, change: &ChecklistChange) -> rusqlite::Result<()> {
+    let insert = |position: usize, item: &ChecklistItem| {
+        connection.execute(
+            "INSERT INTO checklist_items (block_id, position, text, done) VALUES (?, ?, ?, ?)",
+            params![id, position, item.text, item.done],
+        )
+    };
+    match change {
+        ChecklistChange::Title(title) => {
+            connection.execute("UPDATE blocks SET data = ? WHERE id = ?", params![title, id])?;
+        }
+        ChecklistChange::Item { index, item } => {
+            connection.execute(
+                "UPDATE checklist_items SET text = ?, done = ? WHERE block_id = ? AND position = ?",
+                params![item.text, item.done, id, index],
+            )?;
+        }
+        ChecklistChange::Insert { index, item } => {
+            connection.execute(
+                "UPDATE checklist_items SET position = position + 1 WHERE block_id = ? AND position >= ?",
+                params![id, index],
+            )?;
+            insert(*index, item)?;
+        }
+        ChecklistChange::Remove(index) => {
+            connection.execute("DELETE FROM checklist_items WHERE block_id = ? AND position = ?", params![id, index])?;
+            connection.execute(
+                "UPDATE checklist_items SET position = position - 1 WHERE block_id = ? AND position > ?",
+                params![id, index],
+            )?;
+        }
+        ChecklistChange::Move { from, to } => {
+            let (low, high, shift) = if from < to { (from, to, -1) } else { (to, from, 1) };
+            connection.execute(
+                "UPDATE checklist_items SET position = CASE position WHEN ? THEN ? ELSE position + ? END
+                WHERE block_id = ? AND position BETWEEN ? AND ?",
+                params![from, to, shift, id, low, high],
+            )?;
+        }
+        ChecklistChange::Items(items) => {
+            connection.execute("DELETE FROM checklist_items WHERE block_id = ?", params![id])?;
+            for (position, item) in items.iter().enumerate() {
+                insert(position, item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Puts the items of the checklists among `blocks` back into their
+/// `block_data`, which the database only keeps the title in.
+fn load_checklist_items(connection: &Connection, blocks: &mut [SavedBlock]) -> rusqlite::Result<()> {
+    let mut stmt = connection.prepare_cached("SELECT text, done FROM checklist_items WHERE block_id = ? ORDER BY position")?;
+    for block in blocks.iter_mut().filter(|b| b.block_type == BlockType::Checklist) {
+        let items = stmt.query_map([&block.id], |row| Ok(ChecklistItem { text: row.get(0)?, done: row.get(1)? }))?;
+        let data = ChecklistData {
+            title: std::mem::take(&mut block.block_data),
+            items: items.collect::<rusqlite::Result<_>>()?,
+        };
+        block.block_data = data.encode();
+    }
+    Ok(())
+}
+
+fn load_boards(connection: &Connection) -> rusqlite::Result<Vec<SavedBoard>> {
+    let mut stmt = connection.prepare_cached("SELECT id, name, x, y, zoom FROM boards ORDER BY name, id")?;
+    let boards = stmt.query_map([], saved_board)?;
+    boards.collect()
+}
+
+/// Reads a row of `id, name, x, y, zoom` from `boards`.
+fn saved_board(row: &Row<'_>) -> rusqlite::Result<SavedBoard> {
+    Ok(SavedBoard {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        offset: Vec2::new(row.get(2)?, row.get(3)?),
+        zoom: row.get(4)?,
+    })
+}
+
+fn save_style(connection: &Connection, id: &str, style: &ShapeStyle) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT OR REPLACE INTO shape_styles (id, kind, fill, stroke, stroke_width) VALUES (?, ?, ?, ?, ?)",
+        params![id, style.kind, color_to_int(style.fill), color_to_int(style.stroke), style.stroke_width],
+    )?;
+    Ok(())
+}
+
+/// A single edit to the board, as queued by the write-behind thread.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Write {
+    /// Boxed, as blocks are much larger than every other write.
+    AddBlock { board: String, block: Box<SavedBlock> },
+    DeleteBlock(String),
+    Move { id: String, position: Pos2 },
+    /// New bounds for a block, which also moves it when resized from the top or left edge.
+    Resize { id: String, bounds: Rect },
+    Data { id: String, data: String },
+    /// One edit to a checklist's items or title.
+    Checklist { id: String, change: ChecklistChange },
+    Style { id: String, style: ShapeStyle },
+    /// Put a block in a frame, or take it out of any with `None`.
+    Parent { id: String, parent: Option<String> },
+    AddConnector(Connector),
+    ChangeConnector(Connector),
+    DeleteConnector(String),
+    AddBoard(SavedBoard),
+    RenameBoard { id: String, name: String },
+    /// Remember where the camera over a board was.
+    BoardView { id: String, offset: Vec2, zoom: f32 },
+    /// Make a board the one opened on the next start.
+    OpenBoard(String),
+    DeleteBoard(String),
+}
+
+pub(crate) fn apply_write(connection: &Connection, write: &Write) -> rusqlite::Result<()> {
+    match write {
+        Write::AddBlock { board, block } => return add_block(connection, board, block),
+        Write::DeleteBlock(id) => {
+            connection.execute("DELETE FROM blocks WHERE id = ?", params![id])?;
+        }
+        Write::Move { id, position } => {
+            connection.execute(
+                "UPDATE blocks SET x = ?, y = ? WHERE id = ?",
+                params![position.x, position.y, id],
+            )?;
+        }
+        Write::Resize { id, bounds } => {
+            connection.execute(
+                "UPDATE blocks SET x = ?, y = ?, width = ?, height = ? WHERE id = ?",
+                params![bounds.min.x, bounds.min.y, bounds.width(), bounds.height(), id],
+            )?;
+        }
+        Write::Data { id, data } => {
+            let checklist: Option<bool> = connection
+                .query_row("SELECT type = ? FROM blocks WHERE id = ?", params![BlockType::Checklist, id], |row| row.get(0))
+                .optional()?;
+            if checklist == Some(true) {
+                let data = ChecklistData::parse(data);
+                apply_checklist_change(connection, id, &ChecklistChange::Title(data.title))?;
+                return apply_checklist_change(connection, id, &ChecklistChange::Items(data.items));
+            }
+            connection.execute("UPDATE blocks SET data = ? WHERE id = ?", params![data, id])?;
+        }
+        Write::Checklist { id, change } => return apply_checklist_change(connection, id, change),
+        Write::Style { id, style } => return save_style(connection, id, style),
+        Write::Parent { id, parent } => {
+            connection.execute("UPDATE blocks SET parent = ? WHERE id = ?", params![parent, id])?;
+        }
+        Write::AddConnector(connector) => {
+            connection.execute(
+                "INSERT INTO connectors (id, from_id, to_id, style, label) VALUES (?, ?, ?, ?, ?)",
+                params![connector.id, connector.from, connector.to, connector.style, connector.label],
+            )?;
+        }
+        Write::ChangeConnector(connector) => {
+            connection.execute(
+                "UPDATE connectors SET style = ?, label = ? WHERE id = ?",
+                params![connector.style, connector.label, connector.id],
+            )?;
+        }
+        Write::DeleteConnector(id) => {
+            connection.execute("DELETE FROM connectors WHERE id = ?", params![id])?;
+        }
+        Write::AddBoard(board) => {
+            connection.execute(
+                "INSERT INTO boards (id, name, x, y, zoom) VALUES (?, ?, ?, ?, ?)",
+                params![board.id, board.name, board.offset.x, board.offset.y, board.zoom],
+            )?;
+        }
+        Write::RenameBoard { id, name } => {
+            connection.execute("UPDATE boards SET name = ? WHERE id = ?", params![name, id])?;
+        }
+        Write::BoardView { id, offset, zoom } => {
+            connection.execute(
+                "UPDATE boards SET x = ?, y = ?, zoom = ? WHERE id = ?",
+                params![offset.x, offset.y, zoom, id],
+            )?;
+        }
+        Write::OpenBoard(id) => {
+            connection.execute(
+                "UPDATE boards SET opened_at = (SELECT max(opened_at) FROM boards) + 1 WHERE id = ?",
+                params![id],
+            )?;
+        }
+        Write::DeleteBoard(id) => {
+            connection.execute("DELETE FROM boards WHERE id = ?", params![id])?;
+        }
+    }
+    Ok(())
+}
+
+/// The blocks and connectors to show for a viewport. Blocks at the far end of
+/// a loaded connector are included even when they are off-screen, so the
+/// arrow can be drawn to them, and so are the frames loaded blocks are in and
+/// everything inside loaded frames, so a frame is always moved, collapsed
+/// and deleted as a whole.
+fn load_area(
+    connection: &Connection,
+    board: &str,
+    x_min: f32,
+    x_max: f32,
+    y_min: f32,
+    y_max: f32,
+) -> rusqlite::Result<SavedArea> {
+    let mut blocks = load_blocks(connection, board, x_min, x_max, y_min, y_max)?;
+    let connectors = load_connectors(connection, board, x_min, x_max, y_min, y_max)?;
+
+    let mut loaded: HashSet<String> = blocks.iter().map(|b| b.id.clone()).collect();
+    let mut seeds: Vec<&String> = blocks.iter().map(|b| &b.id).collect();
+    seeds.extend(connectors.iter().flat_map(|c| [&c.from, &c.to]));
+    let related = load_related(connection, &seeds)?;
+    blocks.extend(related.into_iter().filter(|block| loaded.insert(block.id.clone())));
+
+    load_checklist_items(connection, &mut blocks)?;
+    Ok(SavedArea { blocks, connectors })
+}
+
 impl Persistor {
     thread_local! {
-        static CONNECTION: RwLock<Arc<Connection>> = RwLock::new(Arc::new(Connection::open("./boardx.db").unwrap()));
+        static CONNECTION: RefCell<Option<(PathBuf, bool, Rc<Connection>)>> = const { RefCell::new(None) };
+    }
+
+    /// A persistor that only reads the existing database at `database`,
+    /// which must already be at the current schema version. Writes fail.
+    pub fn read_only(database: PathBuf) -> Result<Persistor, PersistError> {
+        let persist = Persistor {
+            read_only: true,
+            ..Persistor::new(database)
+        };
+        let version = schema_version(&*persist.connection()?)?;
+        if version != MIGRATIONS.len() {
+            return Err(PersistError::Version(version));
+        }
+        Ok(persist)
+    }
+
+    /// Returns this thread's connection to the database, opening it on first
+    /// use and again whenever the database has changed since.
+    pub(crate) fn connection(&self) -> Result<Rc<Connection>, PersistError> {
+        let database = self.database();
+        Persistor::CONNECTION.with(|c| {
+            let mut c = c.borrow_mut();
+            if let Some((path, read_only, connection)) = c.as_ref() {
+                if *path == database && *read_only == self.read_only {
+                    return Ok(connection.clone());
+                }
+            }
+            let connection = if self.read_only {
+                Connection::open_with_flags(&database, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+            } else {
+                Connection::open(&database)
+            };
+            let connection = Rc::new(connection.map_err(PersistError::Open)?);
+            *c = Some((database, self.read_only, connection.clone()));
+            Ok(connection)
+        })
+    }
+
+    pub fn database(&self) -> PathBuf {
+        self.database.lock().unwrap().clone()
+    }
+
+    pub fn setup(&mut self) -> Result<(), PersistError> {
+        let connection = self.connection()?;
+        migrate(&connection)?;
+        Ok(delete_unused_images(&connection)?)
+    }
+
+    /// Points every thread at another database file, creating and upgrading
+    /// it as needed. Queued writes go to the current file first. Nothing
+    /// changes if they can't be written or the new file can't be set up.
+    pub fn switch_database(&mut self, database: PathBuf) -> Result<(), PersistError> {
+        self.flush()?;
+        let connection = Connection::open(&database).map_err(PersistError::Open)?;
+        migrate(&connection)?;
+        delete_unused_images(&connection)?;
+        *self.database.lock().unwrap() = database;
+        Ok(())
+    }
+
+    /// Copies the database to a new file and switches to the copy. Edits
+    /// made so far can still be undone there, so its images are all kept.
+    pub fn save_as(&mut self, database: PathBuf) -> Result<(), PersistError> {
+        if database.exists() {
+            return Err(PersistError::Exists(database));
+        }
+        let path = database.to_str().ok_or_else(|| PersistError::NotUtf8(database.clone()))?;
+        let connection = self.flushed_connection()?;
+        connection.execute("VACUUM INTO ?", [path])?;
+        *self.database.lock().unwrap() = database;
+        Ok(())
+    }
+
+    /// Moves board edits onto a background thread that coalesces them and
+    /// writes them in batches. Flush failures are picked up with `take_errors`.
+    pub fn start_write_behind(&mut self) {
+        let (errors_sender, errors) = channel();
+        let writer = Persistor {
+            database: self.database.clone(),
+            ..Persistor::default()
+        };
+        let (queue, writer) = write_behind::spawn(writer, errors_sender);
+        self.queue = Some(queue);
+        self.errors = Some(errors);
+        self.writer = Some(writer);
+    }
+
+    /// A persistor for another thread that shares this one's database and
+    /// write queue, so its loads see every edit queued before them.
+    pub fn reader(&self) -> Persistor {
+        Persistor {
+            database: self.database.clone(),
+            read_only: self.read_only,
+            board: self.board.clone(),
+            queue: self.queue.clone(),
+            ..Persistor::default()
+        }
+    }
+
+    /// Errors from background flushes since the last call.
+    pub fn take_errors(&mut self) -> Vec<PersistError> {
+        match &self.errors {
+            Some(errors) => errors.try_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Writes out everything still queued and stops the background thread.
+    pub fn shutdown(&mut self) -> Result<(), PersistError> {
+        if let Some(queue) = self.queue.take() {
+            let _ = queue.send(Message::Shutdown);
+        }
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        match self.take_errors().pop() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, write: Write) -> Result<(), PersistError> {
+        self.write_all(vec![write])
+    }
+
+    /// Queues `writes` to be flushed together, or applies them in one
+    /// transaction right away when there is no write-behind thread to take them.
+    fn write_all(&mut self, writes: Vec<Write>) -> Result<(), PersistError> {
+        let writes = match &self.queue {
+            Some(queue) => match queue.send(Message::Write(writes)) {
+                Ok(()) => return Ok(()),
+                Err(SendError(Message::Write(writes))) => writes,
+                Err(_) => unreachable!(),
+            },
+            None => writes,
+        };
+        let connection = self.connection()?;
+        let transaction = connection.unchecked_transaction()?;
+        for write in &writes {
+            apply_write(&transaction, write)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Saves new bounds for a block; resizing from the top or left edge moves it too.
+    pub fn on_size_change(&mut self, id: &str, bounds: Rect) -> Result<(), PersistError> {
+        self.write(Write::Resize { id: id.to_string(), bounds })
+    }
+
+    pub fn on_add(&mut self, block: SavedBlock) -> Result<(), PersistError> {
+        self.write(Write::AddBlock { board: self.board.clone(), block: Box::new(block) })
     }
 
-    pub fn setup(&mut self) {
-        let connection = Persistor::CONNECTION.with(|c| c.read().unwrap().clone());
-        connection.execute("CREATE TABLE IF NOT EXISTS blocks (id TEXT, type INTEGER, data TEXT, x REAL, y REAL);", params![])
+    pub fn on_style_change(&mut self, id: &str, style: &ShapeStyle) -> Result<(), PersistError> {
+        self.write(Write::Style { id: id.to_string(), style: *style })
+    }
+
+    pub fn on_move(&mut self, id: &str, x: f32, y: f32) -> Result<(), PersistError> {
+        self.write(Write::Move { id: id.to_string(), position: Pos2::new(x, y) })
+    }
+
+    /// Saves the positions of several blocks moved together, all or nothing.
+    pub fn on_moves(&mut self, moves: &[(String, Pos2)]) -> Result<(), PersistError> {
+        let writes = moves
+            .iter()
+            .map(|(id, position)| Write::Move { id: id.clone(), position: *position })
+            .collect();
+        self.write_all(writes)
+    }
+
+    /// Saves which frame a block is in, `None` for none.
+    pub fn on_parent_change(&mut self, id: &str, parent: Option<&str>) -> Result<(), PersistError> {
+        self.write(Write::Parent { id: id.to_string(), parent: parent.map(str::to_string) })
+    }
+
+    pub fn on_data_change(&mut self, id: &str, data: &str) -> Result<(), PersistError> {
+        self.write(Write::Data { id: id.to_string(), data: data.to_string() })
+    }
+
+    /// Saves edits to the checklist `id`, as found by `ChecklistData::changes`.
+    pub fn on_checklist_change(&mut self, id: &str, changes: Vec<ChecklistChange>) -> Result<(), PersistError> {
+        self.write_all(changes.into_iter().map(|change| Write::Checklist { id: id.to_string(), change }).collect())
+    }
+
+    pub fn on_delete(&mut self, id: &str) -> Result<(), PersistError> {
+        self.write(Write::DeleteBlock(id.to_string()))
+    }
+
+    pub fn on_add_connector(&mut self, connector: &Connector) -> Result<(), PersistError> {
+        self.write(Write::AddConnector(connector.clone()))
+    }
+
+    pub fn on_connector_change(&mut self, connector: &Connector) -> Result<(), PersistError> {
+        self.write(Write::ChangeConnector(connector.clone()))
+    }
+
+    pub fn on_delete_connector(&mut self, id: &str) -> Result<(), PersistError> {
+        self.write(Write::DeleteConnector(id.to_string()))
+    }
+
+    /// Stores the image once and returns the hash to keep in the block's `block_data`.
+    pub fn save_image(&mut self, bytes: &[u8]) -> Result<String, PersistError> {
+        let connection = self.connection()?;
+        let hash = content_hash(bytes);
+        connection.execute(
+            "INSERT OR IGNORE INTO images (hash, data) VALUES (?, ?)",
+            params![hash, bytes],
+        )?;
+        Ok(hash)
+    }
+
+    pub fn load_image(&mut self, hash: &str) -> Result<Option<Vec<u8>>, PersistError> {
+        let connection = self.connection()?;
+        let data = connection
+            .query_row("SELECT data FROM images WHERE hash = ?", [hash], |row| row.get(0))
+            .optional()?;
+        Ok(data)
+    }
+
+    /// A handle that cancels whatever query this thread's connection is
+    /// running, for use from other threads.
+    pub fn interrupt_handle(&self) -> Result<InterruptHandle, PersistError> {
+        Ok(self.connection()?.get_interrupt_handle())
+    }
+
+    /// Waits for every write queued so far to be flushed. If that fails the
+    /// writes stay queued and the error is returned.
+    pub fn flush(&self) -> Result<(), PersistError> {
+        if let Some(queue) = &self.queue {
+            let (done, flushed) = channel();
+            if queue.send(Message::Flush(done)).is_ok() {
+                if let Ok(result) = flushed.recv() {
+                    return result;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// This thread's connection, once every write queued so far has been flushed.
+    fn flushed_connection(&self) -> Result<Rc<Connection>, PersistError> {
+        self.flush()?;
+        self.connection()
+    }
+
+    /// Loads a viewport of `board`, first waiting for queued writes to be flushed.
+    pub fn load(&self, board: &str, x_min: f32, x_max: f32, y_min: f32, y_max: f32) -> Result<SavedArea, PersistError> {
+        let connection = self.flushed_connection()?;
+        Ok(load_area(&connection, board, x_min, x_max, y_min, y_max)?)
+    }
+
+    /// All boards, by name.
+    pub fn boards(&self) -> Result<Vec<SavedBoard>, PersistError> {
+        let connection = self.flushed_connection()?;
+        Ok(load_boards(&connection)?)
+    }
+
+    /// The board opened most recently, if there are any.
+    pub fn latest_board(&self) -> Result<Option<SavedBoard>, PersistError> {
+        let connection = self.flushed_connection()?;
+        let board = connection
+            .query_row(
+                "SELECT id, name, x, y, zoom FROM boards ORDER BY opened_at DESC LIMIT 1",
+                [],
+                saved_board,
+            )
+            .optional()?;
+        Ok(board)
+    }
+
+    /// The board opened most recently, creating one if there are none.
+    pub fn last_board(&mut self) -> Result<SavedBoard, PersistError> {
+        match self.latest_board()? {
+            Some(board) => Ok(board),
+            None => {
+                let board = SavedBoard::new(String::from(DEFAULT_BOARD), String::from("Board"));
+                self.on_add_board(&board)?;
+                Ok(board)
+            }
+        }
+    }
+
+    /// Switches to `id`, which is also the board opened on the next start.
+    pub fn open_board(&mut self, id: &str) -> Result<(), PersistError> {
+        self.board = id.to_string();
+        self.write(Write::OpenBoard(id.to_string()))
+    }
+
+    pub fn on_add_board(&mut self, board: &SavedBoard) -> Result<(), PersistError> {
+        self.write(Write::AddBoard(board.clone()))
+    }
+
+    pub fn on_rename_board(&mut self, id: &str, name: &str) -> Result<(), PersistError> {
+        self.write(Write::RenameBoard { id: id.to_string(), name: name.to_string() })
+    }
+
+    pub fn on_board_view_change(&mut self, id: &str, offset: Vec2, zoom: f32) -> Result<(), PersistError> {
+        self.write(Write::BoardView { id: id.to_string(), offset, zoom })
+    }
+
+    /// Deletes a board with everything on it.
+    pub fn on_delete_board(&mut self, id: &str) -> Result<(), PersistError> {
+        self.write(Write::DeleteBoard(id.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::{ConnectorStyle, ShapeKind};
+    use crate::test_util::{block, TempDir};
+
+    fn open_v0() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute(
+                "CREATE TABLE blocks (id TEXT, type INTEGER, data TEXT, x REAL, y REAL)",
+                [],
+            )
             .unwrap();
+        connection
+            .execute(
+                "INSERT INTO blocks VALUES ('a', 'Label', 'hello', 10.0, 20.0)",
+                [],
+            )
+            .unwrap();
+        connection
     }
 
-    pub fn on_size_change(&mut self, id: &String, size: Vec2) {
-        let connection = Persistor::CONNECTION.with(|c| c.read().unwrap().clone());
+    #[test]
+    fn migrates_fresh_database() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len());
         connection
-            .execute("UPDATE blocks SET width = ?, height = ? WHERE id = ?", [size.x.to_string(),
-                size.y.to_string(), id.to_string()]).unwrap();
+            .execute(
+                "INSERT INTO blocks (id, type, data, x, y, width, height) VALUES ('a', 0, '', 0, 0, 10, 10)",
+                [],
+            )
+            .unwrap();
+        assert_eq!(load_blocks(&connection, DEFAULT_BOARD, -5.0, 5.0, -5.0, 5.0).unwrap().len(), 1);
     }
 
-    pub fn on_add(&mut self, block: SavedBlock) {
-        let connection = Persistor::CONNECTION.with(|c| c.read().unwrap().clone());
+    #[test]
+    fn migrates_unversioned_database() {
+        let connection = open_v0();
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len());
         connection
-            .execute("INSERT INTO blocks VALUES(?, ?, ?, ?, ?, ?, ?)", [
-                block.id,
-                block.block_type.to_string(),
-                block.block_data,
-                block.position.x.to_string(),
-                block.position.y.to_string(),
-                block.size.x.to_string(),
-                block.size.y.to_string()]).unwrap();
-    }
-
-    pub fn on_move(&mut self, id: &String, x: f32, y: f32) {
-        println!("block moved: {}, {}, {}", id, x, y);
-        let connection = Persistor::CONNECTION.with(|c| c.read().unwrap().clone());
+            .execute("UPDATE blocks SET width = 100, height = 50 WHERE id = 'a'", [])
+            .unwrap();
+
+        let blocks = load_blocks(&connection, DEFAULT_BOARD, 0.0, 50.0, 0.0, 50.0).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].block_data, "hello");
+        assert_eq!(blocks[0].position, Pos2::new(10.0, 20.0));
+        assert_eq!(blocks[0].size, Vec2::new(100.0, 50.0));
+    }
+
+    fn insert(connection: &Connection, id: &str, block_type: impl rusqlite::ToSql) {
         connection
-            .execute("UPDATE blocks SET x = ?, y = ? WHERE id = ?", [
-                x.to_string(), y.to_string(), id.to_string()]).unwrap();
+            .execute(
+                "INSERT INTO blocks (id, type, data, x, y, width, height) VALUES (?, ?, '', 0, 0, 10, 10)",
+                params![id, block_type],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn block_types_round_trip() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+
+        // Every type, so new ones can't be left out.
+        let block_types: Vec<BlockType> = (0..).map_while(BlockType::from_code).collect();
+        assert!(block_types.contains(&BlockType::Shape));
+        for (i, &block_type) in block_types.iter().enumerate() {
+            let id = i.to_string();
+            insert(&connection, &id, block_type);
+
+            let stored: BlockType = connection
+                .query_row("SELECT type FROM blocks WHERE id = ?", [&id], |row| row.get(0))
+                .unwrap();
+            assert_eq!(stored, block_type);
+            assert_eq!(BlockType::from_code(block_type.code()), Some(block_type));
+        }
+
+        let blocks = load_blocks(&connection, DEFAULT_BOARD, 0.0, 5.0, 0.0, 5.0).unwrap();
+        assert_eq!(blocks.len(), block_types.len());
     }
 
-    pub fn on_data_change(&mut self, id: &String, data: String) {
-        let connection = Persistor::CONNECTION.with(|c| c.read().unwrap().clone());
+    #[test]
+    fn unknown_block_types_are_skipped() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        insert(&connection, "known", BlockType::Button);
+        insert(&connection, "future", 999);
+        insert(&connection, "garbage", "Sticker");
+
+        let blocks = load_blocks(&connection, DEFAULT_BOARD, 0.0, 5.0, 0.0, 5.0).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].id, "known");
+        assert_eq!(blocks[0].block_type, BlockType::Button);
+
+        let count: i64 = connection
+            .query_row("SELECT COUNT(*) FROM blocks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn legacy_block_type_names_are_encoded() {
+        let connection = open_v0();
         connection
-            .execute("UPDATE blocks SET data = ? WHERE id = ?", [
-                data, id.to_string()]).unwrap();
+            .execute("INSERT INTO blocks VALUES ('b', 'Button', 'go', 10.0, 20.0)", [])
+            .unwrap();
+        migrate(&connection).unwrap();
+        connection
+            .execute("UPDATE blocks SET width = 100, height = 50", [])
+            .unwrap();
+
+        let mut blocks = load_blocks(&connection, DEFAULT_BOARD, 0.0, 50.0, 0.0, 50.0).unwrap();
+        blocks.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].block_type, BlockType::Label);
+        assert_eq!(blocks[1].block_type, BlockType::Button);
     }
 
-    pub fn load(self, x_min: f32, x_max: f32, y_min: f32, y_max: f32) -> Vec<SavedBlock> {
-        let connection = Persistor::CONNECTION.with(|c| c.read().unwrap().clone());
+    #[test]
+    fn failed_migration_keeps_previous_version() {
+        let connection = open_v0();
+        connection.execute("CREATE TABLE block_bounds (key INTEGER)", []).unwrap();
 
-        let query = format!("SELECT id, type, data, x, y, height, width FROM blocks WHERE {} < x + width AND {} > x AND {} < y + height AND {} > y",
-                            x_min, x_max, y_min, y_max);
+        match migrate(&connection) {
+            Err(PersistError::Migrate { version, .. }) => assert_eq!(version, 3),
+            other => panic!("expected a migration error, got {:?}", other),
+        }
+        assert_eq!(schema_version(&connection).unwrap(), 2);
+    }
 
-        println!("{}", query);
+    #[test]
+    fn bounds_follow_block_writes() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        connection
+            .execute(
+                "INSERT INTO blocks (id, type, data, x, y, width, height) VALUES ('a', 0, '', 0, 0, 10, 10)",
+                [],
+            )
+            .unwrap();
+        assert_eq!(load_blocks(&connection, DEFAULT_BOARD, 0.0, 20.0, 0.0, 20.0).unwrap().len(), 1);
 
-        let mut stmt = connection.prepare(&query).unwrap();
+        connection
+            .execute("UPDATE blocks SET x = 500, y = 500 WHERE id = 'a'", [])
+            .unwrap();
+        assert_eq!(load_blocks(&connection, DEFAULT_BOARD, 0.0, 20.0, 0.0, 20.0).unwrap().len(), 0);
+        assert_eq!(load_blocks(&connection, DEFAULT_BOARD, 490.0, 505.0, 490.0, 505.0).unwrap().len(), 1);
 
-        let block_iter = stmt.query_map([], |row| {
-            Ok(SavedBlock {
-                size: Vec2::new(row.get(5)?, row.get(6)?),
-                position: Pos2::new(row.get(3)?, row.get(4)?),
-                id: row.get(0)?,
-                block_type: row.get(1)?,
-                block_data: row.get(2)?,
-            })
-        }).unwrap();
+        connection
+            .execute("UPDATE blocks SET width = 0, height = 0 WHERE id = 'a'", [])
+            .unwrap();
+        assert_eq!(load_blocks(&connection, DEFAULT_BOARD, 505.0, 600.0, 505.0, 600.0).unwrap().len(), 0);
+
+        connection.execute("DELETE FROM blocks", []).unwrap();
+        assert_eq!(load_blocks(&connection, DEFAULT_BOARD, 0.0, 1000.0, 0.0, 1000.0).unwrap().len(), 0);
+    }
 
+    /// Compares the R*Tree backed load with the full table scan it replaced.
+    /// Run with `cargo test --release -- --ignored --nocapture bench_viewport_load`.
+    #[test]
+    #[ignore]
+    fn bench_viewport_load() {
+        use std::time::Instant;
 
-        let mut blocks: Vec<SavedBlock> = Vec::new();
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
 
-        for block in block_iter {
-            blocks.push(block.unwrap());
+        let tx = connection.unchecked_transaction().unwrap();
+        for i in 0..100_000 {
+            let x = (i % 1000) as f32 * 400.0;
+            let y = (i / 1000) as f32 * 400.0;
+            tx.execute(
+                "INSERT INTO blocks (id, type, data, x, y, width, height) VALUES (?, 0, '', ?, ?, 300, 200)",
+                params![i.to_string(), x, y],
+            )
+            .unwrap();
+        }
+        tx.commit().unwrap();
+
+        let viewports = 200;
+        let viewport = |i: i32| {
+            let x = (i * 1700) as f32;
+            let y = (i * 190) as f32;
+            (x - 300.0, x + 2220.0, y - 300.0, y + 1380.0)
+        };
+
+        let start = Instant::now();
+        let mut indexed = 0;
+        for i in 0..viewports {
+            let (x_min, x_max, y_min, y_max) = viewport(i);
+            indexed += load_blocks(&connection, DEFAULT_BOARD, x_min, x_max, y_min, y_max).unwrap().len();
+        }
+        let indexed_time = start.elapsed();
+
+        let mut stmt = connection
+            .prepare("SELECT id FROM blocks WHERE ? < x + width AND ? > x AND ? < y + height AND ? > y")
+            .unwrap();
+        let start = Instant::now();
+        let mut scanned = 0;
+        for i in 0..viewports {
+            let (x_min, x_max, y_min, y_max) = viewport(i);
+            scanned += stmt
+                .query_map(params![x_min, x_max, y_min, y_max], |_| Ok(()))
+                .unwrap()
+                .count();
+        }
+        let scan_time = start.elapsed();
+
+        assert_eq!(indexed, scanned);
+        println!(
+            "{} viewport loads over 100000 blocks: r*tree {:?}, full scan {:?}",
+            viewports, indexed_time, scan_time
+        );
+    }
+
+    #[test]
+    fn connectors_crossing_the_viewport_are_loaded() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO blocks (id, type, data, x, y, width, height) VALUES
+                    ('left', 0, '', -500, 0, 10, 10),
+                    ('right', 0, '', 500, 0, 10, 10),
+                    ('far', 0, '', 500, 2000, 10, 10);
+                INSERT INTO connectors (id, from_id, to_id, style, label) VALUES
+                    ('across', 'left', 'right', 1, 'spans the view'),
+                    ('away', 'right', 'far', 0, '');",
+            )
+            .unwrap();
+
+        let area = load_area(&connection, DEFAULT_BOARD, -100.0, 100.0, -100.0, 100.0).unwrap();
+        assert_eq!(area.connectors.len(), 1);
+        assert_eq!(area.connectors[0].id, "across");
+        assert_eq!(area.connectors[0].style, ConnectorStyle::Elbow);
+        assert_eq!(area.connectors[0].label, "spans the view");
+
+        let mut ids: Vec<&str> = area.blocks.iter().map(|b| b.id.as_str()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, ["left", "right"]);
+    }
+
+    #[test]
+    fn connector_bounds_follow_their_blocks() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        let connector = Connector {
+            id: String::from("c"),
+            from: String::from("a"),
+            to: String::from("b"),
+            style: ConnectorStyle::Straight,
+            label: String::new(),
+        };
+        // The connector may be saved before the blocks it joins.
+        apply_write(&connection, &Write::AddConnector(connector)).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO blocks (id, type, data, x, y, width, height) VALUES
+                    ('a', 0, '', -500, 0, 10, 10),
+                    ('b', 0, '', 500, 0, 10, 10);",
+            )
+            .unwrap();
+        let connectors = |x_min, x_max| load_connectors(&connection, DEFAULT_BOARD, x_min, x_max, -100.0, 100.0).unwrap().len();
+        assert_eq!(connectors(-100.0, 100.0), 1);
+
+        apply_write(&connection, &Write::Move { id: String::from("b"), position: Pos2::new(-400.0, 0.0) }).unwrap();
+        assert_eq!(connectors(-100.0, 100.0), 0);
+        assert_eq!(connectors(-450.0, -420.0), 1);
+
+        apply_write(&connection, &Write::DeleteConnector(String::from("c"))).unwrap();
+        assert_eq!(connectors(-450.0, -420.0), 0);
+        let bounds: i64 = connection
+            .query_row("SELECT count(*) FROM connector_bounds", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(bounds, 0);
+    }
+
+    #[test]
+    fn frames_are_loaded_whole() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO blocks (id, type, data, x, y, width, height, parent) VALUES
+                    ('outer', 6, '', -1000, -1000, 3000, 3000, NULL),
+                    ('inner', 6, '', 500, 500, 1000, 1000, 'outer'),
+                    ('child', 0, '', 600, 600, 10, 10, 'inner'),
+                    ('sibling', 0, '', 1800, 1800, 10, 10, 'outer'),
+                    ('outside', 0, '', 5000, 5000, 10, 10, NULL);",
+            )
+            .unwrap();
+        apply_write(&connection, &Write::Parent { id: String::from("outside"), parent: Some(String::from("outer")) }).unwrap();
+        apply_write(&connection, &Write::Parent { id: String::from("sibling"), parent: None }).unwrap();
+
+        // Only the child is in view, but everything it is in comes along,
+        // and so does everything else in those frames.
+        let area = load_area(&connection, DEFAULT_BOARD, 590.0, 620.0, 590.0, 620.0).unwrap();
+        let mut blocks: Vec<(&str, Option<&str>)> = area.blocks.iter().map(|b| (b.id.as_str(), b.parent.as_deref())).collect();
+        blocks.sort_unstable();
+        assert_eq!(
+            blocks,
+            [("child", Some("inner")), ("inner", Some("outer")), ("outer", None), ("outside", Some("outer"))]
+        );
+
+        // Frames saved inside each other don't keep the query going.
+        apply_write(&connection, &Write::Parent { id: String::from("outer"), parent: Some(String::from("inner")) }).unwrap();
+        assert_eq!(load_area(&connection, DEFAULT_BOARD, 590.0, 620.0, 590.0, 620.0).unwrap().blocks.len(), 4);
+    }
+
+    #[test]
+    fn checklist_items_are_rows_of_their_own() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        let block = block("list", BlockType::Checklist, "Stand-up\n[ ] a\n[x] b\n[ ] c", Pos2::ZERO, Vec2::new(200.0, 100.0));
+        apply_write(&connection, &Write::AddBlock { board: DEFAULT_BOARD.to_string(), block: Box::new(block.clone()) }).unwrap();
+        let load = || load_area(&connection, DEFAULT_BOARD, -5.0, 5.0, -5.0, 5.0).unwrap().blocks[0].block_data.clone();
+        assert_eq!(load(), block.block_data);
+        let title: String = connection.query_row("SELECT data FROM blocks", [], |row| row.get(0)).unwrap();
+        assert_eq!(title, "Stand-up");
+
+        // Checking an item off only writes its row.
+        let mut data = ChecklistData::parse(&block.block_data);
+        let before = data.clone();
+        data.items[2].done = true;
+        for change in before.changes(&data) {
+            apply_write(&connection, &Write::Checklist { id: String::from("list"), change }).unwrap();
+            let changed: i64 = connection.query_row("SELECT changes()", [], |row| row.get(0)).unwrap();
+            assert_eq!(changed, 1);
+        }
+        assert_eq!(load(), data.encode());
+
+        for edit in [
+            ChecklistChange::Move { from: 2, to: 0 },
+            ChecklistChange::Insert { index: 1, item: ChecklistItem::new("new") },
+            ChecklistChange::Remove(3),
+            ChecklistChange::Move { from: 0, to: 2 },
+            ChecklistChange::Title(String::from("Retro")),
+        ] {
+            apply_write(&connection, &Write::Checklist { id: String::from("list"), change: edit.clone() }).unwrap();
+            edit.apply(&mut data);
+            assert_eq!(load(), data.encode(), "after {:?}", edit);
+        }
+
+        // Replacing the data, as undo does, replaces every item.
+        apply_write(&connection, &Write::Data { id: String::from("list"), data: block.block_data.clone() }).unwrap();
+        assert_eq!(load(), block.block_data);
+
+        apply_write(&connection, &Write::DeleteBlock(String::from("list"))).unwrap();
+        let items: i64 = connection.query_row("SELECT count(*) FROM checklist_items", [], |row| row.get(0)).unwrap();
+        assert_eq!(items, 0);
+    }
+
+    #[test]
+    fn checklists_are_split_into_rows_when_migrating() {
+        let connection = Connection::open_in_memory().unwrap();
+        let tx = connection.unchecked_transaction().unwrap();
+        for migration in &MIGRATIONS[..10] {
+            migration(&tx).unwrap();
+        }
+        tx.pragma_update(None, "user_version", 10).unwrap();
+        tx.commit().unwrap();
+        connection
+            .execute(
+                "INSERT INTO blocks (id, type, data, x, y, width, height) VALUES ('list', ?, 'Groceries\nmilk\n[x] eggs', 0, 0, 10, 10)",
+                [BlockType::Checklist],
+            )
+            .unwrap();
+
+        migrate(&connection).unwrap();
+        let blocks = load_area(&connection, DEFAULT_BOARD, -5.0, 5.0, -5.0, 5.0).unwrap().blocks;
+        assert_eq!(blocks[0].block_data, "Groceries\n[ ] milk\n[x] eggs");
+        let items: i64 = connection.query_row("SELECT count(*) FROM checklist_items", [], |row| row.get(0)).unwrap();
+        assert_eq!(items, 2);
+    }
+
+    #[test]
+    fn shape_styles_round_trip() {
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+
+        let shape = SavedBlock {
+            style: Some(ShapeStyle::new(ShapeKind::Diamond)),
+            ..block("shape", BlockType::Shape, "inner text", Pos2::new(10.0, 10.0), Vec2::new(160.0, 100.0))
+        };
+        add_block(&connection, DEFAULT_BOARD, &shape).unwrap();
+        insert(&connection, "label", BlockType::Label);
+
+        let mut blocks = load_blocks(&connection, DEFAULT_BOARD, 0.0, 100.0, 0.0, 100.0).unwrap();
+        blocks.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(blocks[0].style, None);
+        assert_eq!(blocks[1], shape);
+
+        connection.execute("DELETE FROM blocks WHERE id = 'shape'", []).unwrap();
+        let styles: i64 = connection
+            .query_row("SELECT count(*) FROM shape_styles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(styles, 0);
+    }
+
+    #[test]
+    fn boards_keep_their_blocks_apart() {
+        let connection = open_v0();
+        migrate(&connection).unwrap();
+        connection.execute("UPDATE blocks SET width = 10, height = 10", []).unwrap();
+
+        let other = SavedBoard::new(String::from("other"), String::from("Other"));
+        let block = block("b", BlockType::Label, "", Pos2::new(10.0, 20.0), Vec2::new(10.0, 10.0));
+        let connector = Connector {
+            id: String::from("c"),
+            from: String::from("b"),
+            to: String::from("b"),
+            style: ConnectorStyle::Straight,
+            label: String::new(),
+        };
+        let writes = [
+            Write::AddBoard(other.clone()),
+            Write::AddBlock { board: other.id.clone(), block: Box::new(block) },
+            Write::AddConnector(connector),
+            Write::OpenBoard(other.id.clone()),
+            Write::OpenBoard(String::from(DEFAULT_BOARD)),
+        ];
+        for write in &writes {
+            apply_write(&connection, write).unwrap();
         }
 
-        println!("total blocks: {}", blocks.len());
+        // Blocks from before boards existed are on the default board.
+        let area = load_area(&connection, DEFAULT_BOARD, 0.0, 50.0, 0.0, 50.0).unwrap();
+        assert_eq!(area.blocks.len(), 1);
+        assert_eq!(area.blocks[0].id, "a");
+        assert!(area.connectors.is_empty());
+        assert_eq!(load_area(&connection, &other.id, 0.0, 50.0, 0.0, 50.0).unwrap().connectors.len(), 1);
+
+        let names: Vec<String> = load_boards(&connection).unwrap().into_iter().map(|b| b.name).collect();
+        assert_eq!(names, ["Board", "Other"]);
+        let last: String = connection
+            .query_row("SELECT id FROM boards ORDER BY opened_at DESC LIMIT 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(last, DEFAULT_BOARD);
+
+        apply_write(&connection, &Write::DeleteBoard(other.id.clone())).unwrap();
+        let (blocks, connectors): (i64, i64) = connection
+            .query_row(
+                "SELECT (SELECT count(*) FROM blocks), (SELECT count(*) FROM connectors)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((blocks, connectors), (1, 0));
+    }
+
+    #[test]
+    fn databases_can_be_switched() {
+        let dir = TempDir::new();
+        let (original, copy) = (dir.join("original.db"), dir.join("copy.db"));
+
+        let mut persist = Persistor::new(original.clone());
+        persist.setup().unwrap();
+        let block = block("a", BlockType::Label, "copied", Pos2::ZERO, Vec2::new(10.0, 10.0));
+        persist.on_add(block.clone()).unwrap();
+
+        persist.save_as(copy.clone()).unwrap();
+        assert_eq!(persist.database(), copy);
+        assert_eq!(persist.load(DEFAULT_BOARD, -5.0, 5.0, -5.0, 5.0).unwrap().blocks, [block]);
+
+        assert!(matches!(persist.save_as(original.clone()), Err(PersistError::Exists(_))));
+        assert!(persist.switch_database(dir.join("missing").join("board.db")).is_err());
+        assert_eq!(persist.database(), copy);
+
+        persist.switch_database(original).unwrap();
+        assert_eq!(persist.boards().unwrap().len(), 1);
+
+    }
+
+    #[test]
+    fn databases_are_not_switched_while_writes_fail() {
+        let dir = TempDir::new();
+        let (original, other) = (dir.join("original.db"), dir.join("other.db"));
+
+        let mut persist = Persistor::new(original.clone());
+        persist.setup().unwrap();
+        persist.start_write_behind();
+        let block = block("a", BlockType::Label, "queued", Pos2::ZERO, Vec2::new(10.0, 10.0));
+        persist.on_add(block.clone()).unwrap();
+
+        let lock = Connection::open(&original).unwrap();
+        lock.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        assert!(persist.switch_database(other.clone()).is_err());
+        assert_eq!(persist.database(), original);
+        assert!(!other.exists());
+
+        lock.execute_batch("COMMIT").unwrap();
+        persist.switch_database(other).unwrap();
+        persist.switch_database(original).unwrap();
+        assert_eq!(persist.load(DEFAULT_BOARD, -5.0, 5.0, -5.0, 5.0).unwrap().blocks, [block]);
+
+        persist.shutdown().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_need_utf8_paths() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = TempDir::new();
+        let mut persist = Persistor::new(dir.join("original.db"));
+        persist.setup().unwrap();
+
+        let copy = dir.join(std::ffi::OsStr::from_bytes(b"copy-\xff.db"));
+        assert!(matches!(persist.save_as(copy.clone()), Err(PersistError::NotUtf8(_))));
+        assert!(!copy.exists());
+        assert_eq!(persist.database(), dir.join("original.db"));
+
+    }
+
+    #[test]
+    fn images_are_stored_once() {
+        let dir = TempDir::new();
+        let mut persist = Persistor::new(dir.join("board.db"));
+        persist.setup().unwrap();
+
+        assert_eq!(content_hash(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let bytes = vec![0x89, b'P', b'N', b'G', 0, 255];
+        let hash = persist.save_image(&bytes).unwrap();
+        assert_eq!(hash, content_hash(&bytes));
+        // Saving the same bytes again, as for a duplicated image block, reuses the row.
+        assert_eq!(persist.save_image(&bytes).unwrap(), hash);
+        let other = persist.save_image(b"other").unwrap();
+        assert_ne!(other, hash);
+
+        assert_eq!(persist.load_image(&hash).unwrap(), Some(bytes));
+        assert_eq!(persist.load_image(&other).unwrap(), Some(b"other".to_vec()));
+        assert_eq!(persist.load_image("missing").unwrap(), None);
+        let count: i64 = persist
+            .connection()
+            .unwrap()
+            .query_row("SELECT count(*) FROM images", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
 
-        return blocks;
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unused_images_are_deleted_on_open() {
+        let dir = TempDir::new();
+        let (original, copy) = (dir.join("original.db"), dir.join("copy.db"));
+        let mut persist = Persistor::new(original.clone());
+        persist.setup().unwrap();
+
+        let image = |id: &str, hash: &str| block(id, BlockType::Image, hash, Pos2::ZERO, Vec2::new(10.0, 10.0));
+        let kept = persist.save_image(b"kept").unwrap();
+        let deleted = persist.save_image(b"deleted").unwrap();
+        let on_board = persist.save_image(b"on a deleted board").unwrap();
+        persist.on_add(image("a", &kept)).unwrap();
+        persist.on_add(image("b", &deleted)).unwrap();
+        persist.on_delete("b").unwrap();
+        persist.on_add_board(&SavedBoard::new(String::from("other"), String::from("Other"))).unwrap();
+        persist.open_board("other").unwrap();
+        persist.on_add(image("c", &on_board)).unwrap();
+        persist.on_delete_board("other").unwrap();
+        // A label that happens to hold a hash doesn't keep the image.
+        persist.open_board(DEFAULT_BOARD).unwrap();
+        persist.on_add(SavedBlock { block_type: BlockType::Label, ..image("d", &deleted) }).unwrap();
+
+        // The copy keeps them all, so deleting the blocks can still be undone.
+        persist.save_as(copy).unwrap();
+        assert_eq!(persist.load_image(&deleted).unwrap(), Some(b"deleted".to_vec()));
+
+        persist.switch_database(original).unwrap();
+        assert_eq!(persist.load_image(&kept).unwrap(), Some(b"kept".to_vec()));
+        assert_eq!(persist.load_image(&deleted).unwrap(), None);
+        assert_eq!(persist.load_image(&on_board).unwrap(), None);
+
+    }
+
+    #[test]
+    fn migration_is_idempotent() {
+        let connection = open_v0();
+        migrate(&connection).unwrap();
+        migrate(&connection).unwrap();
+
+        assert_eq!(schema_version(&connection).unwrap(), MIGRATIONS.len());
+        assert!(has_column(&connection, "blocks", "width").unwrap());
+        assert!(has_column(&connection, "blocks", "height").unwrap());
+    }
+}
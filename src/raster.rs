@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use egui::epaint::{ClippedPrimitive, FontImage, ImageData, ImageDelta, Mesh, Primitive, TextureId};
+use egui::{Color32, Pos2, Rect};
+use image::RgbaImage;
+
+/// A texture the rasterizer samples from, as premultiplied RGBA.
+pub(crate) struct Texture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color32>,
+}
+
+impl Texture {
+    /// The font atlas, with glyph coverage as white.
+    pub fn from_font(image: &FontImage) -> Texture {
+        Texture {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.srgba_pixels(1.0).collect(),
+        }
+    }
+
+    pub fn from_image(image: &RgbaImage) -> Texture {
+        Texture {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels: image
+                .pixels()
+                .map(|p| {
+                    let [r, g, b, a] = p.0;
+                    Color32::from_rgba_unmultiplied(r, g, b, a)
+                })
+                .collect(),
+        }
+    }
+
+    /// Applies a font atlas update, which covers either the whole atlas or a patch of it.
+    pub fn update(textures: &mut HashMap<TextureId, Texture>, id: TextureId, delta: &ImageDelta) {
+        let patch = match &delta.image {
+            ImageData::Font(image) => Texture::from_font(image),
+            ImageData::Color(image) => Texture {
+                width: image.width(),
+                height: image.height(),
+                pixels: image.pixels.clone(),
+            },
+        };
+        match (delta.pos, textures.get_mut(&id)) {
+            (Some([x, y]), Some(texture)) => {
+                for row in 0..patch.height {
+                    let start = (y + row) * texture.width + x;
+                    texture.pixels[start..start + patch.width]
+                        .copy_from_slice(&patch.pixels[row * patch.width..(row + 1) * patch.width]);
+                }
+            }
+            _ => {
+                textures.insert(id, patch);
+            }
+        }
+    }
+
+    /// The color at `uv`, blended between the four nearest texels.
+    fn sample(&self, uv: Pos2) -> [f32; 4] {
+        let x = (uv.x * self.width as f32 - 0.5).max(0.0);
+        let y = (uv.y * self.height as f32 - 0.5).max(0.0);
+        let (x0, y0) = ((x as usize).min(self.width - 1), (y as usize).min(self.height - 1));
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+        let texel = |x: usize, y: usize| premultiplied(self.pixels[y * self.width + x]);
+        let mut color = [0.0; 4];
+        for (i, channel) in color.iter_mut().enumerate() {
+            let top = texel(x0, y0)[i] * (1.0 - fx) + texel(x1, y0)[i] * fx;
+            let bottom = texel(x0, y1)[i] * (1.0 - fx) + texel(x1, y1)[i] * fx;
+            *channel = top * (1.0 - fy) + bottom * fy;
+        }
+        color
+    }
+}
+
+/// A software render target, one premultiplied RGBA value per pixel.
+pub(crate) struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color32>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, background: Color32) -> Canvas {
+        let (width, height) = (width as usize, height as usize);
+        Canvas {
+            width,
+            height,
+            pixels: vec![background; width * height],
+        }
+    }
+
+    /// Draws tessellated `primitives`, whose positions `to_pixels` maps onto
+    /// the canvas. Each is cut to its clip rect.
+    pub fn paint(&mut self, primitives: &[ClippedPrimitive], textures: &HashMap<TextureId, Texture>, to_pixels: impl Fn(Pos2) -> Pos2) {
+        for clipped in primitives {
+            if let Primitive::Mesh(mesh) = &clipped.primitive {
+                if let Some(texture) = textures.get(&mesh.texture_id) {
+                    let clip = Rect::from_two_pos(to_pixels(clipped.clip_rect.min), to_pixels(clipped.clip_rect.max));
+                    self.paint_mesh(mesh, texture, clip, &to_pixels);
+                }
+            }
+        }
+    }
+
+    /// Draws the pixels of `mesh` whose centers are inside `clip`.
+    fn paint_mesh(&mut self, mesh: &Mesh, texture: &Texture, clip: Rect, to_pixels: &impl Fn(Pos2) -> Pos2) {
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| &mesh.vertices[i as usize]);
+            let (pa, mut pb, mut pc) = (to_pixels(a.pos), to_pixels(b.pos), to_pixels(c.pos));
+            let (mut b, mut c) = (b, c);
+            let mut area = edge(pa, pb, pc);
+            if area == 0.0 {
+                continue;
+            }
+            if area < 0.0 {
+                std::mem::swap(&mut pb, &mut pc);
+                std::mem::swap(&mut b, &mut c);
+                area = -area;
+            }
+
+            let x_min = pa.x.min(pb.x).min(pc.x).floor().max(clip.min.x.round()).max(0.0) as usize;
+            let y_min = pa.y.min(pb.y).min(pc.y).floor().max(clip.min.y.round()).max(0.0) as usize;
+            let x_max = (pa.x.max(pb.x).max(pc.x).ceil().min(clip.max.x.round()).max(0.0) as usize).min(self.width);
+            let y_max = (pa.y.max(pb.y).max(pc.y).ceil().min(clip.max.y.round()).max(0.0) as usize).min(self.height);
+
+            for y in y_min..y_max {
+                for x in x_min..x_max {
+                    let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    let weights = [edge(pb, pc, p), edge(pc, pa, p), edge(pa, pb, p)];
+                    let edges = [(pb, pc), (pc, pa), (pa, pb)];
+                    if !weights.iter().zip(edges).all(|(w, (from, to))| *w > 0.0 || (*w == 0.0 && owns_edge(from, to))) {
+                        continue;
+                    }
+                    let [wa, wb, wc] = weights.map(|w| w / area);
+
+                    let uv = (a.uv.to_vec2() * wa + b.uv.to_vec2() * wb + c.uv.to_vec2() * wc).to_pos2();
+                    let texel = texture.sample(uv);
+                    let [ca, cb, cc] = [a.color, b.color, c.color].map(premultiplied);
+                    let pixel = &mut self.pixels[y * self.width + x];
+                    let destination = premultiplied(*pixel);
+                    let mut source = [0.0; 4];
+                    for i in 0..4 {
+                        source[i] = (ca[i] * wa + cb[i] * wb + cc[i] * wc) * texel[i];
+                    }
+                    let mut blended = [0.0; 4];
+                    for i in 0..4 {
+                        blended[i] = source[i] + destination[i] * (1.0 - source[3]);
+                    }
+                    let [r, g, b, a] = blended.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                    *pixel = Color32::from_rgba_premultiplied(r, g, b, a);
+                }
+            }
+        }
+    }
+
+    pub fn into_image(self) -> RgbaImage {
+        let mut image = RgbaImage::new(self.width as u32, self.height as u32);
+        for (pixel, color) in image.pixels_mut().zip(self.pixels) {
+            pixel.0 = color.to_srgba_unmultiplied();
+        }
+        image
+    }
+}
+
+fn premultiplied(color: Color32) -> [f32; 4] {
+    color.to_array().map(|c| c as f32 / 255.0)
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`; which side of the
+/// edge from `a` to `b` the point `p` is on.
+fn edge(a: Pos2, b: Pos2, p: Pos2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+/// Whether pixels exactly on the edge from `from` to `to` belong to its
+/// triangle. Two triangles sharing an edge walk it in opposite directions,
+/// so exactly one of them draws those pixels.
+fn owns_edge(from: Pos2, to: Pos2) -> bool {
+    to.y > from.y || (to.y == from.y && to.x < from.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::epaint::{tessellator, ClippedShape, Shape, TessellationOptions};
+
+    #[test]
+    fn shared_edges_are_drawn_once() {
+        let mut textures = HashMap::new();
+        textures.insert(TextureId::default(), Texture::from_font(&FontImage { size: [1, 1], pixels: vec![1.0] }));
+
+        let options = TessellationOptions { feathering: false, ..TessellationOptions::default() };
+        let half_red = Color32::from_rgba_unmultiplied(255, 0, 0, 128);
+        let shapes = vec![ClippedShape(Rect::EVERYTHING, Shape::rect_filled(Rect::from_min_max(Pos2::new(1.0, 1.0), Pos2::new(3.0, 3.0)), 0.0, half_red))];
+        let primitives = tessellator::tessellate_shapes(1.0, options, shapes, [1, 1]);
+
+        let mut canvas = Canvas::new(4, 4, Color32::WHITE);
+        canvas.paint(&primitives, &textures, |p| p);
+        let image = canvas.into_image();
+
+        // The rect is two triangles; every pixel it covers is blended exactly once.
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let inside = (1..3).contains(&x) && (1..3).contains(&y);
+            let expected = if inside { [255, 127, 127, 255] } else { [255, 255, 255, 255] };
+            assert_eq!(pixel.0, expected, "pixel {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn meshes_are_cut_to_their_clip_rect() {
+        let mut textures = HashMap::new();
+        textures.insert(TextureId::default(), Texture::from_font(&FontImage { size: [1, 1], pixels: vec![1.0] }));
+
+        let options = TessellationOptions { feathering: false, ..TessellationOptions::default() };
+        let clip = Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(2.0, 4.0));
+        let shapes = vec![ClippedShape(clip, Shape::rect_filled(Rect::from_min_max(Pos2::ZERO, Pos2::new(4.0, 4.0)), 0.0, Color32::RED))];
+        let primitives = tessellator::tessellate_shapes(1.0, options, shapes, [1, 1]);
+
+        // The clip rect is in the same coordinates as the mesh, so it moves with it.
+        let mut canvas = Canvas::new(4, 4, Color32::WHITE);
+        canvas.paint(&primitives, &textures, |p| p + egui::Vec2::new(1.0, 0.0));
+        let image = canvas.into_image();
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let expected = if (1..3).contains(&x) { [255, 0, 0, 255] } else { [255, 255, 255, 255] };
+            assert_eq!(pixel.0, expected, "pixel {}, {}", x, y);
+        }
+    }
+}
@@ -1,12 +1,19 @@
-use std::collections::HashMap;
-use egui::{Vec2, Widget};
-use crate::demo::{Block, BlockPosition};
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use egui::{Pos2, Rect, Vec2};
+use crate::demo::{Block, BlockPosition, BlockType, Connector, FrameData};
+use crate::persistor::{SavedArea, SavedBlock};
 
 pub struct BoardState {
     pub(crate) positions: HashMap<String, BlockPosition>,
     pub(crate) blocks: HashMap<String, Block>,
     pub(crate) ids: Vec<String>,
-    pub(crate) sizes: HashMap<String, Vec2>
+    pub(crate) sizes: HashMap<String, Vec2>,
+    pub(crate) connectors: HashMap<String, Connector>,
+    /// Blocks and connectors removed this session and when, until a load
+    /// requested after that comes back. A load that was already running
+    /// when one was removed could still return it, so `merge` skips these.
+    pub(crate) removed: HashMap<String, Instant>,
 }
 
 impl Default for BoardState {
@@ -15,12 +22,503 @@ impl Default for BoardState {
         let blocks = HashMap::new();
         let ids = Vec::new();
         let sizes = HashMap::new();
-        return Self {
+        let connectors = HashMap::new();
+        let removed = HashMap::new();
+        Self {
             positions,
             blocks,
             ids,
-            sizes
+            sizes,
+            connectors,
+            removed,
         }
     }
 }
 
+impl From<SavedArea> for BoardState {
+    fn from(area: SavedArea) -> Self {
+        let mut board_state = BoardState::default();
+        for block in area.blocks {
+            board_state.insert(block);
+        }
+        board_state.connectors = area.connectors.into_iter().map(|c| (c.id.clone(), c)).collect();
+        board_state
+    }
+}
+
+impl BoardState {
+    pub fn insert(&mut self, block: SavedBlock) {
+        if !self.blocks.contains_key(&block.id) {
+            self.ids.push(block.id.clone());
+        }
+        self.positions.insert(block.id.clone(), BlockPosition {
+            id: block.id.clone(),
+            x: block.position.x,
+            y: block.position.y,
+            size: block.size,
+        });
+        self.blocks.insert(block.id.clone(), Block {
+            id: block.id.clone(),
+            block_type: block.block_type,
+            block_data: block.block_data,
+            style: block.style,
+            parent: block.parent,
+        });
+        self.sizes.remove(&block.id);
+        self.removed.remove(&block.id);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.evict(id);
+        self.removed.insert(id.to_string(), Instant::now());
+    }
+
+    /// Drops a block from memory only, leaving it on the board.
+    fn evict(&mut self, id: &str) {
+        self.ids.retain(|i| i != id);
+        self.positions.remove(id);
+        self.blocks.remove(id);
+        self.sizes.remove(id);
+    }
+
+    pub fn connect(&mut self, connector: Connector) {
+        self.removed.remove(&connector.id);
+        self.connectors.insert(connector.id.clone(), connector);
+    }
+
+    pub fn disconnect(&mut self, id: &str) {
+        self.connectors.remove(id);
+        self.removed.insert(id.to_string(), Instant::now());
+    }
+
+    /// Merges an area loaded by a request made at `requested` into the
+    /// resident board.
+    ///
+    /// Resident blocks and connectors are kept as they are: every edit goes
+    /// through this state first, so it is never older than the database, and
+    /// the measured sizes stay valid. Loaded ones that aren't resident are
+    /// added, unless they were removed after the load was requested.
+    /// Resident blocks that weren't loaded and lie outside `keep` are
+    /// evicted, unless they are `pinned`. Connectors are kept while one of
+    /// their blocks is, so deleting that block can still take them along.
+    pub fn merge(&mut self, loaded: BoardState, requested: Instant, keep: Rect, pinned: &[String]) {
+        // Queued writes are saved before a load runs, so anything removed
+        // before it was requested is already gone from what it loaded.
+        self.removed.retain(|_, removed| *removed >= requested);
+
+        let loaded_ids: HashSet<&String> = loaded.ids.iter().collect();
+        let evicted: Vec<String> = self
+            .ids
+            .iter()
+            .filter(|id| !loaded_ids.contains(id) && !pinned.contains(id))
+            .filter(|id| {
+                let position = &self.positions[*id];
+                !keep.intersects(Rect::from_min_size(Pos2::new(position.x, position.y), position.size))
+            })
+            .cloned()
+            .collect();
+        for id in &evicted {
+            self.evict(id);
+        }
+
+        let BoardState { mut positions, mut blocks, ids, connectors, .. } = loaded;
+        for id in ids {
+            if self.blocks.contains_key(&id) || self.removed.contains_key(&id) {
+                continue;
+            }
+            if let (Some(position), Some(block)) = (positions.remove(&id), blocks.remove(&id)) {
+                self.positions.insert(id.clone(), position);
+                self.blocks.insert(id.clone(), block);
+                self.ids.push(id);
+            }
+        }
+
+        for (id, connector) in connectors {
+            if !self.removed.contains_key(&id) && !self.removed.contains_key(&connector.from) && !self.removed.contains_key(&connector.to) {
+                self.connectors.entry(id).or_insert(connector);
+            }
+        }
+        let blocks = &self.blocks;
+        self.connectors
+            .retain(|_, c| blocks.contains_key(&c.from) || blocks.contains_key(&c.to));
+    }
+
+    /// The loaded connectors attached to any of `ids`.
+    pub fn connectors_of(&self, ids: &[String]) -> Vec<Connector> {
+        self.connectors
+            .values()
+            .filter(|c| ids.contains(&c.from) || ids.contains(&c.to))
+            .cloned()
+            .collect()
+    }
+
+    /// The loaded block with `id` in the shape it is saved in.
+    pub fn saved_block(&self, id: &str) -> Option<SavedBlock> {
+        let block = self.blocks.get(id)?;
+        let position = self.positions.get(id)?;
+        Some(SavedBlock {
+            size: position.size,
+            position: Pos2::new(position.x, position.y),
+            id: block.id.clone(),
+            block_type: block.block_type,
+            block_data: block.block_data.clone(),
+            style: block.style,
+            parent: block.parent.clone(),
+        })
+    }
+
+    /// The loaded frame `id` is directly inside of, if any. Blocks whose
+    /// frames lead back to themselves, which only a damaged board has, are
+    /// treated as in none so they are still drawn.
+    fn parent(&self, id: &str) -> Option<&String> {
+        let parent = self.blocks.get(id)?.parent.as_ref()?;
+        match self.blocks.get(parent) {
+            Some(frame) if frame.block_type == BlockType::Frame && !self.is_inside(parent, id) => Some(parent),
+            _ => None,
+        }
+    }
+
+    /// Whether `id` is `frame` or is inside it, however deeply, following
+    /// the saved parents of loaded blocks. Stops at the first repeat.
+    fn is_inside(&self, id: &str, frame: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut id = id;
+        while seen.insert(id) {
+            if id == frame {
+                return true;
+            }
+            match self.blocks.get(id).and_then(|block| block.parent.as_deref()) {
+                Some(parent) => id = parent,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Puts `id` into the frame `to`, or takes it out of any with `None`,
+    /// unless that would put a frame inside itself. Returns whether it did.
+    pub fn reparent(&mut self, id: &str, to: Option<String>) -> bool {
+        if to.as_deref().map_or(false, |to| self.is_inside(to, id)) {
+            log::warn!("not putting {} into {:?}, which is inside it", id, to);
+            return false;
+        }
+        match self.blocks.get_mut(id) {
+            Some(block) => {
+                block.parent = to;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The loaded frames that are collapsed.
+    fn collapsed(&self) -> HashSet<&str> {
+        self.blocks
+            .values()
+            .filter(|block| block.block_type == BlockType::Frame && FrameData::is_collapsed(&block.block_data))
+            .map(|block| block.id.as_str())
+            .collect()
+    }
+
+    /// The loaded blocks inside collapsed frames, however deeply.
+    pub fn hidden(&self) -> HashSet<&str> {
+        let children = self.children();
+        let mut hidden = HashSet::new();
+        for frame in self.collapsed() {
+            BoardState::collect_descendants(frame, &children, &mut hidden);
+        }
+        hidden
+    }
+
+    /// The expanded frames on show, given the `collapsed` frames and the
+    /// `hidden` blocks, with their bounds.
+    fn open_frames(&self, collapsed: &HashSet<&str>, hidden: &HashSet<&str>) -> Vec<(&String, Rect)> {
+        self.ids
+            .iter()
+            .filter(|id| self.blocks[*id].block_type == BlockType::Frame)
+            .filter(|id| !collapsed.contains(id.as_str()) && !hidden.contains(id.as_str()))
+            .map(|id| (id, block_rect(&self.positions[id])))
+            .collect()
+    }
+
+    /// The loaded blocks directly inside each frame, in board order.
+    fn children(&self) -> HashMap<&str, Vec<&String>> {
+        let mut children: HashMap<&str, Vec<&String>> = HashMap::new();
+        for id in &self.ids {
+            if let Some(parent) = self.parent(id) {
+                children.entry(parent.as_str()).or_default().push(id);
+            }
+        }
+        children
+    }
+
+    /// Adds everything inside `id`, however deeply, to `inside`.
+    fn collect_descendants<'a>(id: &str, children: &HashMap<&str, Vec<&'a String>>, inside: &mut HashSet<&'a str>) {
+        let mut frames = vec![id];
+        while let Some(frame) = frames.pop() {
+            for child in children.get(frame).into_iter().flatten() {
+                if inside.insert(child.as_str()) {
+                    frames.push(child.as_str());
+                }
+            }
+        }
+    }
+
+    /// `ids` followed by everything inside those of them that are frames,
+    /// in board order, each once.
+    pub fn with_descendants(&self, ids: &[String]) -> Vec<String> {
+        let children = self.children();
+        let mut inside = HashSet::new();
+        for id in ids {
+            BoardState::collect_descendants(id, &children, &mut inside);
+        }
+        let given: HashSet<&str> = ids.iter().map(|id| id.as_str()).collect();
+        let mut all = ids.to_vec();
+        all.extend(self.ids.iter().filter(|id| inside.contains(id.as_str()) && !given.contains(id.as_str())).cloned());
+        all
+    }
+
+    /// The blocks to draw, bottom first. Frames go underneath the blocks
+    /// inside them and the blocks next to them; blocks in collapsed frames
+    /// are left out.
+    pub fn draw_order(&self) -> Vec<String> {
+        let children = self.children();
+        let collapsed = self.collapsed();
+        let roots: Vec<&String> = self.ids.iter().filter(|id| self.parent(id).is_none()).collect();
+        let mut order = Vec::with_capacity(self.ids.len());
+        self.push_drawn(&roots, &children, &collapsed, &mut order);
+        order
+    }
+
+    fn push_drawn(&self, level: &[&String], children: &HashMap<&str, Vec<&String>>, collapsed: &HashSet<&str>, order: &mut Vec<String>) {
+        let (frames, others): (Vec<&String>, Vec<&String>) =
+            level.iter().partition(|id| self.blocks[id.as_str()].block_type == BlockType::Frame);
+        for frame in frames {
+            order.push(frame.clone());
+            if let (false, Some(inside)) = (collapsed.contains(frame.as_str()), children.get(frame.as_str())) {
+                self.push_drawn(inside, children, collapsed, order);
+            }
+        }
+        order.extend(others.into_iter().cloned());
+    }
+
+    /// The innermost expanded frame on show that contains the board position
+    /// `point`, other than the `excluded` ones.
+    pub fn frame_at(&self, point: Pos2, excluded: &HashSet<&str>) -> Option<String> {
+        BoardState::innermost(&self.open_frames(&self.collapsed(), &self.hidden()), point, excluded)
+    }
+
+    /// The smallest of `frames` that contains `point`, other than the `excluded` ones.
+    fn innermost(frames: &[(&String, Rect)], point: Pos2, excluded: &HashSet<&str>) -> Option<String> {
+        frames
+            .iter()
+            .filter(|(id, bounds)| !excluded.contains(id.as_str()) && bounds.contains(point))
+            .min_by(|(_, a), (_, b)| a.area().partial_cmp(&b.area()).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(id, _)| id.to_string())
+    }
+
+    /// Puts each of the `dropped` blocks, which were just moved together,
+    /// into the frame under its middle, or takes it out of the one it was in.
+    /// A dropped frame also takes in the blocks it now covers, while the
+    /// blocks a frame carried along stay in it. Returns each change as
+    /// `(id, from, to)`.
+    pub fn drop_into_frames(&mut self, dropped: &[String]) -> Vec<(String, Option<String>, Option<String>)> {
+        let children = self.children();
+        let collapsed = self.collapsed();
+        let hidden = self.hidden();
+        let frames = self.open_frames(&collapsed, &hidden);
+        let mut carried = HashSet::new();
+        for id in dropped {
+            BoardState::collect_descendants(id, &children, &mut carried);
+        }
+        let covering: Vec<Rect> = dropped
+            .iter()
+            .filter(|id| self.blocks.get(*id).map_or(false, |b| b.block_type == BlockType::Frame))
+            .filter(|id| !collapsed.contains(id.as_str()))
+            .filter_map(|id| self.positions.get(id).map(block_rect))
+            .collect();
+        let landed: Vec<String> = self
+            .ids
+            .iter()
+            .filter(|id| !carried.contains(id.as_str()) && !hidden.contains(id.as_str()))
+            .filter(|id| {
+                let center = block_rect(&self.positions[*id]).center();
+                dropped.contains(id) || covering.iter().any(|frame| frame.contains(center))
+            })
+            .cloned()
+            .collect();
+
+        let targets: Vec<(String, Option<String>)> = landed
+            .into_iter()
+            .map(|id| {
+                let mut excluded = HashSet::from([id.as_str()]);
+                BoardState::collect_descendants(&id, &children, &mut excluded);
+                let to = BoardState::innermost(&frames, block_rect(&self.positions[&id]).center(), &excluded);
+                (id, to)
+            })
+            .collect();
+
+        let mut changes = Vec::new();
+        for (id, to) in targets {
+            let from = self.blocks[&id].parent.clone();
+            // Blocks moved into a frame earlier in this drop may have put `to` inside `id`.
+            if from != to && self.reparent(&id, to.clone()) {
+                changes.push((id, from, to));
+            }
+        }
+        changes
+    }
+}
+
+/// The board area covered by a block.
+pub(crate) fn block_rect(position: &BlockPosition) -> Rect {
+    Rect::from_min_size(Pos2::new(position.x, position.y), position.size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::{BlockType, ConnectorStyle, FrameData};
+    use crate::test_util;
+
+    fn block(id: &str, x: f32, data: &str) -> SavedBlock {
+        test_util::block(id, BlockType::Label, data, Pos2::new(x, 0.0), Vec2::new(100.0, 50.0))
+    }
+
+    fn board(blocks: &[SavedBlock]) -> BoardState {
+        let mut board = BoardState::default();
+        for block in blocks {
+            board.insert(block.clone());
+        }
+        board
+    }
+
+    fn connector(id: &str, from: &str, to: &str) -> Connector {
+        Connector {
+            id: id.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            style: ConnectorStyle::Straight,
+            label: String::new(),
+        }
+    }
+
+    fn keep() -> Rect {
+        Rect::from_min_max(Pos2::new(-500.0, -500.0), Pos2::new(500.0, 500.0))
+    }
+
+    fn frame(id: &str, min: Pos2, size: Vec2, data: &FrameData) -> SavedBlock {
+        test_util::block(id, BlockType::Frame, &data.encode(), min, size)
+    }
+
+    fn parent(board: &BoardState, id: &str) -> Option<String> {
+        board.blocks[id].parent.clone()
+    }
+
+    #[test]
+    fn dropped_blocks_go_into_the_innermost_frame() {
+        let outer = frame("outer", Pos2::ZERO, Vec2::new(1000.0, 1000.0), &FrameData::default());
+        let inner = frame("inner", Pos2::new(100.0, 100.0), Vec2::new(300.0, 300.0), &FrameData::default());
+        let mut board = board(&[outer, inner, block("a", 150.0, ""), block("b", 2000.0, "")]);
+        board.positions.get_mut("a").unwrap().y = 150.0;
+
+        let changes = board.drop_into_frames(&[String::from("inner"), String::from("a"), String::from("b")]);
+        assert_eq!(parent(&board, "inner").as_deref(), Some("outer"));
+        assert_eq!(parent(&board, "a").as_deref(), Some("inner"));
+        assert_eq!(parent(&board, "b"), None);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(board.with_descendants(&[String::from("outer")]), ["outer", "inner", "a"]);
+        assert_eq!(board.with_descendants(&[String::from("inner"), String::from("a")]), ["inner", "a"]);
+        // Frames are drawn underneath what is inside them.
+        assert_eq!(board.draw_order(), ["outer", "inner", "a", "b"]);
+
+        // Moving the outer frame carries everything along without changing where it is.
+        board.positions.get_mut("outer").unwrap().x = 5000.0;
+        assert!(board.drop_into_frames(&[String::from("outer")]).is_empty());
+
+        // A frame is never dropped into a frame inside itself.
+        board.positions.get_mut("outer").unwrap().x = 0.0;
+        board.positions.get_mut("inner").unwrap().size = Vec2::new(2000.0, 2000.0);
+        board.drop_into_frames(&[String::from("outer")]);
+        assert_eq!(parent(&board, "outer"), None);
+
+        // Dragged out, a block leaves its frame.
+        board.positions.get_mut("a").unwrap().x = -500.0;
+        assert_eq!(board.drop_into_frames(&[String::from("a")]), [(String::from("a"), Some(String::from("inner")), None)]);
+    }
+
+    #[test]
+    fn frames_never_end_up_inside_themselves() {
+        let outer = frame("outer", Pos2::ZERO, Vec2::new(1000.0, 1000.0), &FrameData::default());
+        let mut inner = frame("inner", Pos2::new(100.0, 100.0), Vec2::new(300.0, 300.0), &FrameData::default());
+        inner.parent = Some(String::from("outer"));
+        let mut board = board(&[outer, inner, block("a", 150.0, "")]);
+
+        assert!(!board.reparent("outer", Some(String::from("inner"))));
+        assert!(!board.reparent("outer", Some(String::from("outer"))));
+        assert_eq!(parent(&board, "outer"), None);
+        assert!(board.reparent("a", Some(String::from("inner"))));
+
+        // A board saved with frames inside each other still draws them all.
+        board.blocks.get_mut("outer").unwrap().parent = Some(String::from("inner"));
+        assert_eq!(board.draw_order(), ["outer", "inner", "a"]);
+        assert!(!board.hidden().contains("a"));
+        assert_eq!(board.with_descendants(&[String::from("inner")]), ["inner", "a"]);
+    }
+
+    #[test]
+    fn collapsed_frames_hide_their_contents() {
+        let collapsed = FrameData { collapsed: Some(300.0), ..FrameData::default() };
+        let mut board = board(&[frame("frame", Pos2::ZERO, Vec2::new(300.0, 28.0), &collapsed), block("a", 0.0, ""), block("b", 500.0, "")]);
+        board.blocks.get_mut("a").unwrap().parent = Some(String::from("frame"));
+        board.positions.get_mut("a").unwrap().y = 100.0;
+
+        assert!(board.hidden().contains("a"));
+        assert!(!board.hidden().contains("frame"));
+        assert_eq!(board.draw_order(), ["frame", "b"]);
+        // Nothing is dropped into a collapsed frame.
+        board.positions.get_mut("b").unwrap().x = 10.0;
+        assert!(board.drop_into_frames(&[String::from("b")]).is_empty());
+    }
+
+    #[test]
+    fn merging_keeps_resident_blocks() {
+        let requested = Instant::now();
+        let mut board = board(&[block("a", 0.0, "new text"), block("far", 5000.0, ""), block("dragged", 9000.0, "")]);
+        board.sizes.insert(String::from("a"), Vec2::new(120.0, 40.0));
+        board.remove("deleted");
+
+        // Requested before the delete, so it still has the deleted block.
+        let mut loaded = self::board(&[block("a", 0.0, "old text"), block("deleted", 0.0, ""), block("new", 200.0, "")]);
+        loaded.connect(connector("c", "a", "deleted"));
+        board.merge(loaded, requested, keep(), &[String::from("dragged")]);
+
+        assert_eq!(board.ids, ["a", "dragged", "new"]);
+        assert_eq!(board.blocks["a"].block_data, "new text");
+        assert_eq!(board.sizes["a"], Vec2::new(120.0, 40.0));
+        assert!(board.connectors.is_empty());
+        assert!(board.removed.contains_key("deleted"));
+
+        // A load requested after the delete doesn't have it any more.
+        board.merge(self::board(&[block("a", 0.0, "")]), Instant::now(), keep(), &[]);
+        assert!(board.removed.is_empty());
+    }
+
+    #[test]
+    fn connectors_to_off_screen_blocks_are_kept() {
+        let mut board = board(&[block("a", 0.0, ""), block("b", 0.0, "")]);
+        board.connect(connector("ab", "a", "b"));
+        board.positions.get_mut("b").unwrap().x = 5000.0;
+
+        // "b" is too far away to stay loaded, but the arrow to it stays
+        // with "a", so deleting "a" can take it along and undo bring it back.
+        board.merge(self::board(&[block("a", 0.0, "")]), Instant::now(), keep(), &[]);
+        assert_eq!(board.ids, ["a"]);
+        assert_eq!(board.connectors_of(&[String::from("a")]), [connector("ab", "a", "b")]);
+
+        // Once neither end is loaded it goes too.
+        board.positions.get_mut("a").unwrap().x = 5000.0;
+        board.merge(BoardState::default(), Instant::now(), keep(), &[]);
+        assert!(board.connectors.is_empty());
+    }
+}
@@ -0,0 +1,49 @@
+//! Helpers shared by the tests of several modules.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use egui::{Pos2, Vec2};
+use uuid::Uuid;
+
+use crate::demo::BlockType;
+use crate::persistor::SavedBlock;
+
+/// A fresh directory under the system's temporary directory, removed with
+/// everything in it when dropped, including when a test fails.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new() -> TempDir {
+        let dir = std::env::temp_dir().join(format!("boardx-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A block with no style that isn't in a frame.
+pub(crate) fn block(id: &str, block_type: BlockType, data: &str, position: Pos2, size: Vec2) -> SavedBlock {
+    SavedBlock {
+        size,
+        position,
+        id: id.to_string(),
+        block_type,
+        block_data: data.to_string(),
+        style: None,
+        parent: None,
+    }
+}
@@ -1,37 +1,99 @@
-use std::collections::HashMap;
-use egui::Vec2;
+use egui::{Pos2, Vec2};
 
+pub const MIN_ZOOM: f32 = 0.10;
+pub const MAX_ZOOM: f32 = 5.00;
+
+/// The camera over the board.
+///
+/// Board ("world") coordinates are what blocks are stored in. Screen
+/// coordinates are egui points relative to the window's top left corner.
 #[derive(Clone, Copy)]
 pub struct ViewState {
+    /// Bottom right corner of the visible area, in board coordinates.
     pub(crate) viewport: Vec2,
+    /// Top left corner of the visible area, in board coordinates.
     pub(crate) offset: Vec2,
+    /// `viewport` as of the last load request.
     pub(crate) last_offset: Vec2,
+    /// Screen points per board unit.
+    pub(crate) zoom: f32,
+    /// `zoom` as of the last load request.
+    pub(crate) last_zoom: f32,
 }
 
 impl Default for ViewState {
     fn default() -> Self {
-        return Self {
+        Self {
             viewport: Vec2::ZERO,
             offset: Vec2::ZERO,
             last_offset: Vec2::ZERO,
+            zoom: 1.00,
+            last_zoom: 1.00,
         }
     }
 }
 
 impl ViewState {
+    /// Recomputes the visible area for a window of `screen_size` points.
+    pub fn set_screen_size(&mut self, screen_size: Vec2) {
+        self.viewport = self.offset + screen_size / self.zoom;
+    }
 
-    pub fn in_viewport(&self, x: f32, y: f32) -> bool {
-        let buffer = 300.00;
-        let y_min = self.offset.y - buffer;
-        let y_max = self.viewport.y + buffer;
-        let x_min = self.offset.x - buffer;
-        let x_max = self.viewport.x + buffer;
+    pub fn screen_to_world(&self, screen: Pos2) -> Pos2 {
+        (self.offset + screen.to_vec2() / self.zoom).to_pos2()
+    }
 
-        if y > y_max || y < y_min || x > x_max || x < x_min {
-            return false;
-        }
+    pub fn world_to_screen(&self, world: Pos2) -> Pos2 {
+        ((world.to_vec2() - self.offset) * self.zoom).to_pos2()
+    }
+
+    /// Zooms by `factor`, keeping the board position under the screen point `anchor` in place.
+    pub fn zoom_around(&mut self, factor: f32, anchor: Pos2) {
+        let world = self.screen_to_world(anchor);
+        let screen_size = (self.viewport - self.offset) * self.zoom;
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = world.to_vec2() - anchor.to_vec2() / self.zoom;
+        self.set_screen_size(screen_size);
+    }
+
+    /// Pans by a distance measured in screen points.
+    pub fn pan(&mut self, screen_delta: Vec2) {
+        self.offset -= screen_delta / self.zoom;
+        self.viewport -= screen_delta / self.zoom;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_and_world_round_trip() {
+        let view = ViewState {
+            offset: Vec2::new(100.0, -50.0),
+            zoom: 2.0,
+            ..ViewState::default()
+        };
 
-        return true;
+        let world = Pos2::new(130.0, 10.0);
+        assert_eq!(view.world_to_screen(world), Pos2::new(60.0, 120.0));
+        assert_eq!(view.screen_to_world(view.world_to_screen(world)), world);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn zoom_keeps_anchor_in_place() {
+        let mut view = ViewState {
+            offset: Vec2::new(40.0, 40.0),
+            ..ViewState::default()
+        };
+        view.set_screen_size(Vec2::new(800.0, 600.0));
+
+        let anchor = Pos2::new(200.0, 150.0);
+        let before = view.screen_to_world(anchor);
+        view.zoom_around(2.5, anchor);
+
+        assert_eq!(view.zoom, 2.5);
+        assert!((view.screen_to_world(anchor) - before).length() < 0.001);
+        assert_eq!(view.viewport - view.offset, Vec2::new(320.0, 240.0));
+    }
+}
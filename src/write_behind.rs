@@ -0,0 +1,324 @@
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+use crate::demo::{ChecklistChange, ChecklistData};
+use crate::persistor::{apply_write, PersistError, Persistor, Write};
+
+/// How long an edit may wait in the queue before it is written out.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub(crate) enum Message {
+    /// Edits to queue, always flushed in the same transaction.
+    Write(Vec<Write>),
+    /// Write out everything queued so far, then answer on the sender with
+    /// how that went.
+    Flush(Sender<Result<(), PersistError>>),
+    /// Write out everything queued so far and stop.
+    Shutdown,
+}
+
+/// Which row an edit goes to.
+#[derive(PartialEq)]
+enum Target<'a> {
+    Block(&'a str),
+    Connector(&'a str),
+    Board(&'a str),
+}
+
+impl Write {
+    fn target(&self) -> Target<'_> {
+        match self {
+            Write::AddBlock { block, .. } => Target::Block(&block.id),
+            Write::DeleteBlock(id)
+            | Write::Move { id, .. }
+            | Write::Resize { id, .. }
+            | Write::Data { id, .. }
+            | Write::Checklist { id, .. }
+            | Write::Style { id, .. }
+            | Write::Parent { id, .. } => Target::Block(id),
+            Write::AddConnector(connector) | Write::ChangeConnector(connector) => Target::Connector(&connector.id),
+            Write::DeleteConnector(id) => Target::Connector(id),
+            Write::AddBoard(board) => Target::Board(&board.id),
+            Write::RenameBoard { id, .. }
+            | Write::BoardView { id, .. }
+            | Write::OpenBoard(id)
+            | Write::DeleteBoard(id) => Target::Board(id),
+        }
+    }
+
+    /// Folds the update `write` into this one if it makes it redundant, both
+    /// going to the same row. Returns `write` back otherwise.
+    fn absorb(&mut self, write: Write) -> Result<(), Write> {
+        match (self, write) {
+            (Write::Move { position, .. }, Write::Move { position: to, .. }) => *position = to,
+            (Write::Resize { bounds, .. }, Write::Resize { bounds: to, .. }) => *bounds = to,
+            (Write::Data { data, .. }, Write::Data { data: to, .. }) => *data = to,
+            (Write::Checklist { change: ChecklistChange::Title(title), .. }, Write::Checklist { change: ChecklistChange::Title(to), .. }) => *title = to,
+            // Typing into an item rewrites that item on every keystroke.
+            (
+                Write::Checklist { change: ChecklistChange::Item { index, item }, .. },
+                Write::Checklist { change: ChecklistChange::Item { index: to_index, item: to }, .. },
+            ) if *index == to_index => *item = to,
+            (Write::Style { style, .. }, Write::Style { style: to, .. }) => *style = to,
+            (Write::Parent { parent, .. }, Write::Parent { parent: to, .. }) => *parent = to,
+            (Write::ChangeConnector(connector), Write::ChangeConnector(to)) => *connector = to,
+            // A block or connector that hasn't been written yet is simply inserted as it is now.
+            (Write::AddBlock { block, .. }, Write::Move { position, .. }) => block.position = position,
+            (Write::AddBlock { block, .. }, Write::Resize { bounds, .. }) => {
+                block.position = bounds.min;
+                block.size = bounds.size();
+            }
+            (Write::AddBlock { block, .. }, Write::Data { data, .. }) => block.block_data = data,
+            (Write::AddBlock { block, .. }, Write::Checklist { change, .. }) => {
+                let mut data = ChecklistData::parse(&block.block_data);
+                change.apply(&mut data);
+                block.block_data = data.encode();
+            }
+            (Write::AddBlock { block, .. }, Write::Style { style, .. }) => block.style = Some(style),
+            (Write::AddBlock { block, .. }, Write::Parent { parent, .. }) => block.parent = parent,
+            (Write::AddConnector(connector), Write::ChangeConnector(to)) => *connector = to,
+            (Write::RenameBoard { name, .. }, Write::RenameBoard { name: to, .. }) => *name = to,
+            (Write::BoardView { offset, zoom, .. }, Write::BoardView { offset: to, zoom: to_zoom, .. }) => {
+                *offset = to;
+                *zoom = to_zoom;
+            }
+            (Write::AddBoard(board), Write::RenameBoard { name, .. }) => board.name = name,
+            (Write::AddBoard(board), Write::BoardView { offset, zoom, .. }) => {
+                board.offset = offset;
+                board.zoom = zoom;
+            }
+            (_, write) => return Err(write),
+        }
+        Ok(())
+    }
+
+    /// Whether applying `self` and then `other` could end differently from
+    /// applying them the other way round.
+    fn conflicts_with(&self, other: &Write) -> bool {
+        match (self, other) {
+            (Write::Data { .. }, Write::Data { .. })
+            | (Write::Style { .. }, Write::Style { .. })
+            | (Write::Parent { .. }, Write::Parent { .. }) => true,
+            (Write::Data { .. }, Write::Move { .. } | Write::Resize { .. } | Write::Style { .. } | Write::Parent { .. }) => false,
+            (Write::Style { .. }, Write::Move { .. } | Write::Resize { .. } | Write::Data { .. } | Write::Parent { .. }) => false,
+            (Write::Parent { .. }, Write::Move { .. } | Write::Resize { .. } | Write::Data { .. } | Write::Style { .. }) => false,
+            (Write::Move { .. } | Write::Resize { .. }, Write::Data { .. } | Write::Style { .. } | Write::Parent { .. }) => false,
+            (Write::RenameBoard { .. }, Write::BoardView { .. } | Write::OpenBoard(_)) => false,
+            (Write::BoardView { .. }, Write::RenameBoard { .. } | Write::OpenBoard(_)) => false,
+            (Write::OpenBoard(_), Write::RenameBoard { .. } | Write::BoardView { .. }) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Edits waiting to be written, with later updates to a row merged into
+/// earlier ones where that doesn't change the outcome.
+#[derive(Default)]
+pub(crate) struct WriteQueue {
+    pending: Vec<Write>,
+}
+
+impl WriteQueue {
+    pub fn push(&mut self, write: Write) {
+        // Look back for an earlier write to the same row that this one can be
+        // merged into, without reordering it past anything it conflicts with.
+        let mut write = write;
+        for queued in self.pending.iter_mut().rev() {
+            if queued.target() != write.target() {
+                continue;
+            }
+            write = match queued.absorb(write) {
+                Ok(()) => return,
+                Err(write) => write,
+            };
+            if queued.conflicts_with(&write) {
+                break;
+            }
+        }
+        self.pending.push(write);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Writes everything queued in one transaction. The queue is only
+    /// emptied if that succeeds, so a failed flush is retried later.
+    pub fn flush(&mut self, connection: &Connection) -> rusqlite::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let transaction = connection.unchecked_transaction()?;
+        for write in &self.pending {
+            apply_write(&transaction, write)?;
+        }
+        transaction.commit()?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Starts the thread that owns the write queue, writing through `persist`.
+/// Failed flushes are sent to `errors`, unless asked for with
+/// `Message::Flush`, which gets the result instead; the thread stops on
+/// `Message::Shutdown` or once every sender for the queue is gone, flushing
+/// one last time either way.
+pub(crate) fn spawn(persist: Persistor, errors: Sender<PersistError>) -> (Sender<Message>, JoinHandle<()>) {
+    let (queue, messages): (Sender<Message>, Receiver<Message>) = channel();
+
+    let writer = thread::spawn(move || {
+        let mut pending = WriteQueue::default();
+        let mut deadline: Option<Instant> = None;
+
+        let flush = |pending: &mut WriteQueue| -> Result<(), PersistError> {
+            let connection = persist.connection()?;
+            Ok(pending.flush(&connection)?)
+        };
+        let flush_or_report = |pending: &mut WriteQueue| {
+            if let Err(e) = flush(pending) {
+                let _ = errors.send(e);
+            }
+        };
+
+        loop {
+            let message = match deadline {
+                Some(deadline) => messages.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => messages.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match message {
+                Ok(Message::Write(writes)) => {
+                    for write in writes {
+                        pending.push(write);
+                    }
+                }
+                Ok(Message::Flush(done)) => {
+                    let _ = done.send(flush(&mut pending));
+                    deadline = None;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    flush_or_report(&mut pending);
+                    deadline = None;
+                }
+                Ok(Message::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                    flush_or_report(&mut pending);
+                    return;
+                }
+            }
+            if !pending.is_empty() {
+                deadline.get_or_insert_with(|| Instant::now() + FLUSH_INTERVAL);
+            }
+        }
+    });
+
+    (queue, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::{BlockType, ChecklistItem};
+    use crate::persistor::{migrate, SavedBlock, DEFAULT_BOARD};
+    use crate::test_util::block;
+    use egui::{Pos2, Rect, Vec2};
+
+    fn moved(id: &str, x: f32) -> Write {
+        Write::Move { id: id.to_string(), position: Pos2::new(x, 0.0) }
+    }
+
+    fn typed(id: &str, data: &str) -> Write {
+        Write::Data { id: id.to_string(), data: data.to_string() }
+    }
+
+    #[test]
+    fn updates_to_a_block_are_coalesced() {
+        let mut queue = WriteQueue::default();
+        for x in 0..10 {
+            queue.push(moved("a", x as f32));
+            queue.push(typed("a", &x.to_string()));
+            queue.push(moved("b", x as f32));
+        }
+        assert_eq!(queue.pending, vec![moved("a", 9.0), typed("a", "9"), moved("b", 9.0)]);
+
+        // Moves and resizes both write the position, so neither jumps over the other.
+        let bounds = Rect::from_min_size(Pos2::new(5.0, 5.0), Vec2::splat(10.0));
+        queue.push(Write::Resize { id: String::from("a"), bounds });
+        queue.push(moved("a", 20.0));
+        assert_eq!(queue.pending.len(), 5);
+
+        // Nothing is merged across a delete.
+        queue.push(Write::DeleteBlock(String::from("b")));
+        queue.push(moved("b", 30.0));
+        assert_eq!(queue.pending.last(), Some(&moved("b", 30.0)));
+    }
+
+    #[test]
+    fn updates_are_folded_into_new_blocks() {
+        let block = block("a", BlockType::Label, "", Pos2::ZERO, Vec2::new(100.0, 20.0));
+        let mut queue = WriteQueue::default();
+        queue.push(Write::AddBlock { board: String::from(DEFAULT_BOARD), block: Box::new(block.clone()) });
+        queue.push(typed("a", "hello"));
+        queue.push(moved("a", 40.0));
+        queue.push(Write::Parent { id: String::from("a"), parent: Some(String::from("frame")) });
+
+        let expected = SavedBlock {
+            position: Pos2::new(40.0, 0.0),
+            block_data: String::from("hello"),
+            parent: Some(String::from("frame")),
+            ..block
+        };
+        assert_eq!(queue.pending, vec![Write::AddBlock { board: String::from(DEFAULT_BOARD), block: Box::new(expected.clone()) }]);
+
+        let connection = Connection::open_in_memory().unwrap();
+        migrate(&connection).unwrap();
+        queue.flush(&connection).unwrap();
+        assert!(queue.is_empty());
+
+        let (data, parent): (String, Option<String>) = connection
+            .query_row("SELECT data, parent FROM blocks WHERE id = 'a'", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(data, expected.block_data);
+        assert_eq!(parent, expected.parent);
+    }
+
+    #[test]
+    fn checklist_edits_stay_apart() {
+        let checked = |index: usize, text: &str, done: bool| Write::Checklist {
+            id: String::from("a"),
+            change: ChecklistChange::Item { index, item: ChecklistItem { text: text.to_string(), done } },
+        };
+        let mut queue = WriteQueue::default();
+        queue.push(checked(0, "t", false));
+        queue.push(checked(0, "typed", false));
+        queue.push(Write::Checklist { id: String::from("a"), change: ChecklistChange::Move { from: 0, to: 1 } });
+        queue.push(checked(1, "typed", true));
+        // Typing is merged; the move and the check after it are written as they are.
+        assert_eq!(queue.pending.len(), 3);
+        assert_eq!(queue.pending[0], checked(0, "typed", false));
+
+        let block = block("b", BlockType::Checklist, "List\n[ ] one", Pos2::ZERO, Vec2::new(100.0, 20.0));
+        let mut queue = WriteQueue::default();
+        queue.push(Write::AddBlock { board: String::from(DEFAULT_BOARD), block: Box::new(block) });
+        queue.push(Write::Checklist { id: String::from("b"), change: ChecklistChange::Insert { index: 1, item: ChecklistItem::new("two") } });
+        match &queue.pending[..] {
+            [Write::AddBlock { block, .. }] => assert_eq!(block.block_data, "List\n[ ] one\n[ ] two"),
+            pending => panic!("not folded into the new block: {:?}", pending),
+        }
+    }
+
+    #[test]
+    fn failed_flushes_keep_their_writes() {
+        let connection = Connection::open_in_memory().unwrap();
+        let mut queue = WriteQueue::default();
+        queue.push(typed("a", "lost?"));
+
+        assert!(queue.flush(&connection).is_err());
+        assert_eq!(queue.pending.len(), 1);
+
+        migrate(&connection).unwrap();
+        queue.flush(&connection).unwrap();
+        assert!(queue.is_empty());
+    }
+}